@@ -0,0 +1,105 @@
+//! Cost of one control loop on the host. Absolute numbers say little about an
+//! STM32, but the ratios between the variants show what each feature adds,
+//! and a jump between runs flags an addition that blows the loop budget.
+//!
+//! Run with `cargo bench -p controller`, and again with `--features
+//! micromath` to see what the approximations save. Only the target can tell:
+//! a desktop CPU with a hardware square root runs the accurate path as fast
+//! or faster.
+
+use std::hint::black_box;
+
+use controller::{
+    BatteryState, Controller, FlightMode, IMUDataPoint, PidGains, Saturation, TransmitterState,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use nalgebra::Vector3;
+
+/// 8 kHz, a common gyro loop rate.
+const DT: f32 = 1.0 / 8000.0;
+
+fn tuned() -> Controller {
+    let gains = PidGains::new(0.1, 0.5, 0.01);
+    Controller::with_gains(gains, gains, PidGains::new(0.2, 0.1, 0.0))
+}
+
+/// Every feature the loop runs each step: angle mode on the attitude
+/// estimators, gyro filtering, feed-forward, I-term relax, TPA, tilt and
+/// battery compensation, heading hold and air mode.
+fn fully_loaded() -> Controller {
+    let mut controller = tuned();
+    controller.set_mode(FlightMode::Angle);
+    controller.set_gyro_lpf_hz(100.0).unwrap();
+    controller.set_gyro_notch(180.0, 3.0);
+    controller.set_rpm_notch(true);
+    controller.feed_motor_rpm([12000.0; 4]);
+    controller.set_feedforward(0.05, 0.05, 0.05);
+    controller.set_feedforward_lpf_hz(30.0);
+    controller.set_iterm_relax(15.0);
+    controller.set_tpa(0.5, 0.3).unwrap();
+    controller.set_tilt_compensation(true);
+    controller.set_battery(BatteryState::new(15.2, 4)).unwrap();
+    controller.set_heading_hold(true);
+    controller.set_saturation(Saturation::AirMode);
+    controller
+}
+
+/// Times `calculate_motor_speeds` on `controller`, armed, with a wobbling gyro
+/// and moving sticks so no branch settles into a shortcut.
+fn bench_loop(c: &mut Criterion, name: &str, mut controller: Controller) {
+    controller.arm();
+    let mut step = 0u32;
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            // Wrapping every 10 s keeps the timestamps precise. The loop
+            // clamps the one step back in time like any bad dt.
+            step = (step + 1) % 80_000;
+            let t = step as f32 * DT;
+            let gyro = Vector3::new((t * 40.0).sin(), 0.1, (t * 25.0).cos());
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.3, 9.7, -0.2), t);
+            let stick = 0.5 + 0.4 * (t * 3.0).sin();
+            let sticks = TransmitterState::new(0.5, 0.5, stick, stick);
+            black_box(
+                controller
+                    .calculate_motor_speeds(black_box(imu), &sticks)
+                    .get(0),
+            );
+        })
+    });
+}
+
+fn control_loop(c: &mut Criterion) {
+    bench_loop(c, "loop/rate_pid", tuned());
+    bench_loop(c, "loop/fully_loaded", fully_loaded());
+}
+
+/// Each gyro filter alone on top of the bare rate loop; the difference from
+/// `loop/rate_pid` is what the filter update costs.
+fn filters(c: &mut Criterion) {
+    let mut lpf = tuned();
+    lpf.set_gyro_lpf_hz(100.0).unwrap();
+    bench_loop(c, "filter/gyro_lpf", lpf);
+
+    let mut notch = tuned();
+    notch.set_gyro_notch(180.0, 3.0);
+    bench_loop(c, "filter/gyro_notch", notch);
+
+    // The rpm notch retunes its coefficients every step.
+    let mut rpm = tuned();
+    rpm.set_gyro_notch(180.0, 3.0);
+    rpm.set_rpm_notch(true);
+    rpm.feed_motor_rpm([12000.0; 4]);
+    bench_loop(c, "filter/rpm_notch", rpm);
+
+    let mut feedforward = tuned();
+    feedforward.set_feedforward(0.05, 0.05, 0.05);
+    feedforward.set_feedforward_lpf_hz(30.0);
+    bench_loop(c, "filter/feedforward_lpf", feedforward);
+
+    let mut relax = tuned();
+    relax.set_iterm_relax(15.0);
+    bench_loop(c, "filter/iterm_relax", relax);
+}
+
+criterion_group!(benches, control_loop, filters);
+criterion_main!(benches);
@@ -0,0 +1,130 @@
+use nalgebra::{RealField, UnitQuaternion, Vector3};
+
+use crate::attitude::GRAVITY;
+use crate::cast;
+
+/// Exponent of the standard atmosphere's barometric formula.
+const BAROMETRIC_EXPONENT: f64 = 5.255;
+/// Altitude in meters at which the standard atmosphere's pressure reaches zero.
+const BAROMETRIC_SCALE_HEIGHT: f64 = 44330.0;
+
+/// Height in meters above the level where the pressure is `reference`.
+pub(crate) fn pressure_altitude<T: RealField + Copy>(pressure: T, reference: T) -> T {
+    cast::<T>(BAROMETRIC_SCALE_HEIGHT)
+        * (T::one() - (pressure / reference).powf(cast(1.0 / BAROMETRIC_EXPONENT)))
+}
+
+/// Height and vertical speed from double integrating the accelerometer. Any
+/// bias in the accelerometer or error in the attitude estimate grows
+/// quadratically in the altitude, so on its own this only holds for a few
+/// seconds. Barometer fixes fed through `correct` pull it back with a second
+/// order complementary filter.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct AltitudeEstimator<T> {
+    altitude: T,
+    velocity: T,
+    /// Seconds over which the barometer overrides the inertial estimate.
+    time_constant: T,
+}
+impl<T: RealField + Copy> AltitudeEstimator<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            altitude: T::zero(),
+            velocity: T::zero(),
+            time_constant: cast(1.0),
+        }
+    }
+
+    pub(crate) fn set_time_constant(&mut self, time_constant: T) {
+        self.time_constant = time_constant;
+    }
+
+    pub(crate) fn altitude(&self) -> T {
+        self.altitude
+    }
+
+    pub(crate) fn velocity(&self) -> T {
+        self.velocity
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.altitude = T::zero();
+        self.velocity = T::zero();
+    }
+
+    /// `orientation` rotates body vectors into the world frame, where y is up.
+    /// `accel` is the accelerometer's specific force in the body frame.
+    pub(crate) fn update(&mut self, orientation: &UnitQuaternion<T>, accel: Vector3<T>, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+        let vertical = (orientation * accel).y - cast(GRAVITY);
+        self.velocity += vertical * dt;
+        self.altitude += self.velocity * dt;
+    }
+
+    /// Blends in an absolute altitude measured `dt` after the previous one.
+    /// The gains make the error decay critically damped with the filter's
+    /// time constant.
+    pub(crate) fn correct(&mut self, measured: T, dt: T) {
+        if dt <= T::zero() || self.time_constant <= T::zero() {
+            return;
+        }
+        let error = measured - self.altitude;
+        let tau = self.time_constant;
+        self.altitude += error * cast::<T>(2.0) / tau * dt;
+        self.velocity += error / (tau * tau) * dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrates_vertical_acceleration_twice() {
+        let mut estimator = AltitudeEstimator::new();
+        let level = UnitQuaternion::identity();
+        let accel = Vector3::new(0.0, GRAVITY as f32 + 2.0, 0.0);
+        for _ in 0..100 {
+            estimator.update(&level, accel, 0.01);
+        }
+        assert!((estimator.velocity() - 2.0).abs() < 1e-4);
+        assert!((estimator.altitude() - 1.01).abs() < 1e-4);
+    }
+
+    #[test]
+    fn tilted_hover_reads_no_climb() {
+        let mut estimator = AltitudeEstimator::new();
+        let tilt = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.3_f32);
+        let accel = tilt.inverse() * Vector3::new(0.0, GRAVITY as f32, 0.0);
+        for _ in 0..100 {
+            estimator.update(&tilt, accel, 0.01);
+        }
+        assert!(estimator.velocity().abs() < 1e-4);
+        assert!(estimator.altitude().abs() < 1e-4);
+    }
+
+    #[test]
+    fn pressure_altitude_matches_standard_atmosphere() {
+        assert_eq!(pressure_altitude(101_325.0_f32, 101_325.0), 0.0);
+        // Roughly 12 Pa per meter near sea level.
+        let one_meter = pressure_altitude(101_313.0_f32, 101_325.0);
+        assert!((one_meter - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn barometer_bounds_accel_bias_drift() {
+        let mut estimator = AltitudeEstimator::new();
+        let level = UnitQuaternion::identity();
+        let biased = Vector3::new(0.0, GRAVITY as f32 + 0.1, 0.0);
+        for _ in 0..6000 {
+            estimator.update(&level, biased, 0.01);
+            estimator.correct(0.0, 0.01);
+        }
+        // Pure integration would be 180 m off by now.
+        assert!(estimator.altitude().abs() < 0.2);
+        assert!(estimator.velocity().abs() < 0.2);
+    }
+}
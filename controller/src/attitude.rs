@@ -0,0 +1,325 @@
+use nalgebra::{Quaternion, RealField, UnitQuaternion, Vector3};
+
+use crate::{cast, math};
+
+pub(crate) const GRAVITY: f64 = 9.81;
+
+/// Accelerometer samples further than this fraction away from 1g are treated as
+/// dynamic acceleration and not used for leveling.
+const ACCEL_TRUST_BAND: f64 = 0.15;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ComplementaryFilter<T> {
+    roll: T,
+    pitch: T,
+    time_constant: T,
+}
+impl<T: RealField + Copy> ComplementaryFilter<T> {
+    pub(crate) fn new(time_constant: T) -> Self {
+        Self {
+            roll: T::zero(),
+            pitch: T::zero(),
+            time_constant,
+        }
+    }
+
+    pub(crate) fn set_time_constant(&mut self, time_constant: T) {
+        self.time_constant = time_constant;
+    }
+
+    pub(crate) fn roll(&self) -> T {
+        self.roll
+    }
+
+    pub(crate) fn pitch(&self) -> T {
+        self.pitch
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.roll = T::zero();
+        self.pitch = T::zero();
+    }
+
+    /// Body frame: x forward, y up, z right. Roll is about x (right side down is
+    /// positive), pitch is about z (nose up is positive).
+    pub(crate) fn update(&mut self, gyro: Vector3<T>, accel: Vector3<T>, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+        self.roll += gyro.x * dt;
+        self.pitch += gyro.z * dt;
+
+        let accel_norm = math::norm(&accel);
+        if (accel_norm / cast(GRAVITY) - T::one()).abs() > cast(ACCEL_TRUST_BAND) {
+            return;
+        }
+        let accel_roll = math::atan2(-accel.z, accel.y);
+        let accel_pitch = math::atan2(accel.x, math::sqrt(accel.y * accel.y + accel.z * accel.z));
+        let alpha = self.time_constant / (self.time_constant + dt);
+        self.roll = alpha * self.roll + (T::one() - alpha) * accel_roll;
+        self.pitch = alpha * self.pitch + (T::one() - alpha) * accel_pitch;
+    }
+}
+
+/// Quaternion rotates body vectors into the world frame, where y is up.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub(crate) struct Madgwick<T> {
+    q: Quaternion<T>,
+    beta: T,
+}
+impl<T: RealField + Copy> Madgwick<T> {
+    pub(crate) fn new(beta: T) -> Self {
+        Self {
+            q: Quaternion::identity(),
+            beta,
+        }
+    }
+
+    pub(crate) fn set_beta(&mut self, beta: T) {
+        self.beta = beta;
+    }
+
+    pub(crate) fn orientation(&self) -> UnitQuaternion<T> {
+        UnitQuaternion::new_normalize(self.q)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.q = Quaternion::identity();
+    }
+
+    pub(crate) fn update(&mut self, gyro: Vector3<T>, accel: Vector3<T>, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+        let two: T = cast(2.0);
+        let four: T = cast(4.0);
+        let mut q_dot = self.q * Quaternion::from_imag(gyro) * cast::<T>(0.5);
+
+        let accel_norm = math::norm(&accel);
+        if accel_norm > T::zero() {
+            let a = accel / accel_norm;
+            let (w, x, y, z) = (self.q.w, self.q.i, self.q.j, self.q.k);
+            // Gradient of the error between the estimated and measured gravity
+            // direction in the body frame.
+            let f1 = two * (x * y + w * z) - a.x;
+            let f2 = T::one() - two * (x * x + z * z) - a.y;
+            let f3 = two * (y * z - w * x) - a.z;
+            let gradient = Quaternion::new(
+                two * z * f1 - two * x * f3,
+                two * y * f1 - four * x * f2 - two * w * f3,
+                two * x * f1 + two * z * f3,
+                two * w * f1 - four * z * f2 + two * y * f3,
+            );
+            let gradient_norm = math::sqrt(gradient.norm_squared());
+            if gradient_norm > T::zero() {
+                q_dot -= gradient * (self.beta / gradient_norm);
+            }
+        }
+
+        self.q = math::normalize(self.q + q_dot * dt);
+    }
+}
+
+/// Wraps an angle in radians into -pi..pi.
+pub(crate) fn wrap_angle<T: RealField + Copy>(angle: T) -> T {
+    let wrapped = (angle + T::pi()) % T::two_pi();
+    if wrapped < T::zero() {
+        wrapped + T::pi()
+    } else {
+        wrapped - T::pi()
+    }
+}
+
+/// Angle in radians of the horizontal part of world vector `v`, counterclockwise
+/// about y from x, the same sense as a positive yaw rate.
+fn horizontal_angle<T: RealField + Copy>(v: Vector3<T>) -> T {
+    (-v.z).atan2(v.x)
+}
+
+/// Yaw of `orientation` about the world vertical, measured like a heading.
+pub(crate) fn yaw_of<T: RealField + Copy>(orientation: &UnitQuaternion<T>) -> T {
+    horizontal_angle(orientation * Vector3::x())
+}
+
+/// Yaw from integrating the gyro about the world vertical, pulled towards the
+/// tilt-compensated compass heading whenever a magnetometer sample arrives.
+/// Without any it is plain gyro yaw, starting at zero.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct HeadingFilter<T> {
+    heading: T,
+    time_constant: T,
+    has_mag: bool,
+}
+impl<T: RealField + Copy> HeadingFilter<T> {
+    pub(crate) fn new(time_constant: T) -> Self {
+        Self {
+            heading: T::zero(),
+            time_constant,
+            has_mag: false,
+        }
+    }
+
+    pub(crate) fn heading(&self) -> T {
+        self.heading
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.heading = T::zero();
+        self.has_mag = false;
+    }
+
+    /// `orientation` rotates body vectors into the world frame, where y is up.
+    pub(crate) fn update(&mut self, orientation: &UnitQuaternion<T>, gyro: Vector3<T>, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+        self.heading = wrap_angle(self.heading + (orientation * gyro).y * dt);
+    }
+
+    /// Blends in the heading of body frame field `mag`, measured `dt` after the
+    /// previous sample. The first sample is taken as is. Samples without a
+    /// horizontal component, such as a dead sensor reading zero, are skipped.
+    pub(crate) fn correct(&mut self, orientation: &UnitQuaternion<T>, mag: Vector3<T>, dt: T) {
+        // Both vectors carry the same error in the estimated yaw, so only the
+        // tilt of `orientation` matters for the angle between them.
+        let field = orientation * mag;
+        if field.xz().norm() <= T::default_epsilon() || !field.x.is_finite() {
+            return;
+        }
+        let forward = orientation * Vector3::x();
+        let measured = wrap_angle(horizontal_angle(forward) - horizontal_angle(field));
+        if !self.has_mag {
+            self.heading = measured;
+            self.has_mag = true;
+            return;
+        }
+        if dt <= T::zero() || self.time_constant <= T::zero() {
+            return;
+        }
+        let alpha = dt / (self.time_constant + dt);
+        self.heading = wrap_angle(self.heading + wrap_angle(measured - self.heading) * alpha);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::*;
+
+    fn tilted_gravity(roll: f32, pitch: f32) -> Vector3<f32> {
+        Vector3::new(
+            pitch.sin(),
+            roll.cos() * pitch.cos(),
+            -roll.sin() * pitch.cos(),
+        ) * GRAVITY as f32
+    }
+
+    #[test]
+    fn converges_to_accelerometer_tilt() {
+        let mut filter = ComplementaryFilter::new(0.5);
+        let accel = tilted_gravity(0.2, -0.1);
+        for _ in 0..1000 {
+            filter.update(Vector3::zeros(), accel, 0.01);
+        }
+        // The micromath atan2 is a few thousandths of a radian off.
+        let tolerance = if cfg!(feature = "micromath") {
+            3e-3
+        } else {
+            1e-3
+        };
+        assert!((filter.roll() - 0.2).abs() < tolerance);
+        assert!((filter.pitch() + 0.1).abs() < tolerance);
+    }
+
+    #[test]
+    fn ignores_accel_under_dynamic_acceleration() {
+        let mut filter = ComplementaryFilter::new(0.5);
+        let accel = tilted_gravity(0.5, 0.0) * 2.0;
+        for _ in 0..100 {
+            filter.update(Vector3::new(0.1, 0.0, 0.0), accel, 0.01);
+        }
+        assert!((filter.roll() - 0.1).abs() < 1e-4);
+        assert_eq!(filter.pitch(), 0.0);
+    }
+
+    #[test]
+    fn madgwick_aligns_with_gravity() {
+        let mut filter = Madgwick::new(0.1);
+        let accel = tilted_gravity(0.3, 0.2);
+        for _ in 0..5000 {
+            filter.update(Vector3::zeros(), accel, 0.01);
+        }
+        let up_in_body = filter.orientation().inverse() * Vector3::y();
+        assert!((up_in_body - accel.normalize()).norm() < 5e-3);
+    }
+
+    #[test]
+    fn madgwick_integrates_gyro() {
+        let mut filter = Madgwick::<f32>::new(0.1);
+        for _ in 0..100 {
+            filter.update(Vector3::new(0.0, 1.0, 0.0), Vector3::zeros(), 0.01);
+        }
+        let (axis, angle) = filter.orientation().axis_angle().unwrap();
+        assert!((axis.into_inner() - Vector3::y()).norm() < 1e-4);
+        assert!((angle - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn wraps_angles() {
+        assert!((wrap_angle(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-6);
+        assert!((wrap_angle(-3.0 * PI / 2.0) - PI / 2.0).abs() < 1e-6);
+        assert!((wrap_angle(0.3_f32) - 0.3).abs() < 1e-6);
+    }
+
+    /// North along world x, dipping down.
+    fn earth_field() -> Vector3<f32> {
+        Vector3::new(0.2, -0.4, 0.0)
+    }
+
+    #[test]
+    fn compass_heading_is_tilt_compensated() {
+        let heading = 0.7;
+        let body = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), heading)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.4);
+        let mag = body.inverse() * earth_field();
+        // The estimate only knows the tilt, with its yaw off by a radian.
+        let estimate = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), heading - 1.0)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.4);
+        let mut filter = HeadingFilter::<f32>::new(1.0);
+        filter.correct(&estimate, mag, 0.0);
+        assert!((filter.heading() - heading).abs() < 1e-5);
+    }
+
+    #[test]
+    fn heading_falls_back_to_gyro() {
+        let mut filter = HeadingFilter::<f32>::new(1.0);
+        let level = UnitQuaternion::identity();
+        for _ in 0..100 {
+            filter.update(&level, Vector3::new(0.0, 0.5, 0.0), 0.01);
+            filter.correct(&level, Vector3::zeros(), 0.01);
+        }
+        assert!((filter.heading() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn magnetometer_removes_gyro_drift() {
+        let mut filter = HeadingFilter::<f32>::new(1.0);
+        let level = UnitQuaternion::identity();
+        for _ in 0..3000 {
+            filter.update(&level, Vector3::new(0.0, 0.01, 0.0), 0.01);
+            filter.correct(&level, earth_field(), 0.01);
+        }
+        assert!(filter.heading().abs() < 0.02);
+    }
+}
@@ -0,0 +1,330 @@
+use nalgebra::{RealField, Vector3};
+
+use crate::cast;
+
+/// Frame markers. An intra frame holds every field as is, a predicted frame
+/// only the change from the frame before it.
+const INTRA: u8 = b'I';
+const PREDICTED: u8 = b'P';
+/// Every this many frames an intra frame is written, so a decoder can pick
+/// the log up again after a damaged frame.
+const INTRA_INTERVAL: u32 = 32;
+
+/// Fixed point scales: microseconds, and thousandths of everything else.
+const TIME_SCALE: f64 = 1e6;
+const VALUE_SCALE: f64 = 1e3;
+
+/// Longest LEB128 encoding of a 64 bit value.
+const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlackboxError {
+    /// The output buffer can't hold the frame. Nothing was written.
+    BufferFull,
+    /// The log ends part way through a frame.
+    Truncated,
+    BadMarker(u8),
+    /// A predicted frame came before any intra frame.
+    MissingIntraFrame,
+}
+impl core::fmt::Display for BlackboxError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BlackboxError::BufferFull => write!(f, "blackbox buffer is full"),
+            BlackboxError::Truncated => write!(f, "blackbox log ends mid frame"),
+            BlackboxError::BadMarker(byte) => {
+                write!(f, "bad blackbox frame marker {:#04x}", byte)
+            }
+            BlackboxError::MissingIntraFrame => {
+                write!(f, "blackbox log doesn't start with an intra frame")
+            }
+        }
+    }
+}
+
+/// One control loop's worth of state. Rates are in rad/s, PID terms and motor
+/// outputs in the controller's normalized units. Logged to a thousandth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlackboxFrame<T = f32, const N: usize = 4> {
+    pub time: T,
+    pub setpoint: Vector3<T>,
+    pub gyro: Vector3<T>,
+    pub p: Vector3<T>,
+    pub i: Vector3<T>,
+    pub d: Vector3<T>,
+    pub motors: [T; N],
+}
+
+/// Most bytes a frame with `motors` motor outputs can take: the marker and a
+/// full length varint for the time, the 15 axis values and every motor.
+#[cfg(feature = "alloc")]
+pub(crate) const fn max_frame_len(motors: usize) -> usize {
+    1 + (1 + 15 + motors) * MAX_VARINT_LEN
+}
+
+/// A frame in fixed point.
+#[derive(Clone, Copy, PartialEq)]
+struct Quantized<const N: usize> {
+    time: i64,
+    axes: [[i64; 3]; 5],
+    motors: [i64; N],
+}
+impl<const N: usize> Quantized<N> {
+    fn new<T: RealField + Copy>(frame: &BlackboxFrame<T, N>) -> Self {
+        let quantize = |v: T, scale: f64| -> i64 {
+            (v * cast(scale)).round().to_subset().unwrap_or(0.0) as i64
+        };
+        let axes = [frame.setpoint, frame.gyro, frame.p, frame.i, frame.d];
+        Self {
+            time: quantize(frame.time, TIME_SCALE),
+            axes: axes.map(|axis| [0, 1, 2].map(|i| quantize(axis[i], VALUE_SCALE))),
+            motors: frame.motors.map(|motor| quantize(motor, VALUE_SCALE)),
+        }
+    }
+
+    fn frame<T: RealField + Copy>(&self) -> BlackboxFrame<T, N> {
+        let value = |q: i64| cast::<T>(q as f64 / VALUE_SCALE);
+        let [setpoint, gyro, p, i, d] = self
+            .axes
+            .map(|axis| Vector3::new(value(axis[0]), value(axis[1]), value(axis[2])));
+        BlackboxFrame {
+            time: cast(self.time as f64 / TIME_SCALE),
+            setpoint,
+            gyro,
+            p,
+            i,
+            d,
+            motors: self.motors.map(value),
+        }
+    }
+
+    fn fields_mut(&mut self) -> impl Iterator<Item = &mut i64> {
+        core::iter::once(&mut self.time)
+            .chain(self.axes.iter_mut().flatten())
+            .chain(self.motors.iter_mut())
+    }
+
+    fn fields(&self) -> impl Iterator<Item = i64> + '_ {
+        core::iter::once(self.time)
+            .chain(self.axes.iter().flatten().copied())
+            .chain(self.motors.iter().copied())
+    }
+}
+
+impl<const N: usize> Default for Quantized<N> {
+    fn default() -> Self {
+        Self {
+            time: 0,
+            axes: [[0; 3]; 5],
+            motors: [0; N],
+        }
+    }
+}
+
+/// Writes frames as a marker byte followed by one zigzag LEB128 varint per
+/// field. Small loop to loop changes mostly fit in a byte each.
+pub struct BlackboxEncoder<const N: usize = 4> {
+    previous: Option<Quantized<N>>,
+    since_intra: u32,
+}
+impl<const N: usize> BlackboxEncoder<N> {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            since_intra: 0,
+        }
+    }
+
+    /// Encodes `frame` into the start of `out` and returns how many bytes it
+    /// took.
+    pub fn encode<T: RealField + Copy>(
+        &mut self,
+        frame: &BlackboxFrame<T, N>,
+        out: &mut [u8],
+    ) -> Result<usize, BlackboxError> {
+        let current = Quantized::new(frame);
+        let previous = self.previous.filter(|_| self.since_intra < INTRA_INTERVAL);
+        let mut len = 0;
+        let mut push = |byte: u8| -> Result<(), BlackboxError> {
+            *out.get_mut(len).ok_or(BlackboxError::BufferFull)? = byte;
+            len += 1;
+            Ok(())
+        };
+        // An intra frame is the change from all zeros.
+        push(if previous.is_some() { PREDICTED } else { INTRA })?;
+        let base = previous.unwrap_or_default();
+        for (value, last) in current.fields().zip(base.fields()) {
+            write_varint(value.wrapping_sub(last), &mut push)?;
+        }
+        self.since_intra = if previous.is_some() {
+            self.since_intra + 1
+        } else {
+            1
+        };
+        self.previous = Some(current);
+        Ok(len)
+    }
+}
+impl<const N: usize> Default for BlackboxEncoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_varint(
+    value: i64,
+    push: &mut impl FnMut(u8) -> Result<(), BlackboxError>,
+) -> Result<(), BlackboxError> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7F) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return push(byte);
+        }
+        push(byte | 0x80)?;
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<i64, BlackboxError> {
+    let mut zigzag = 0u64;
+    for shift in 0..MAX_VARINT_LEN {
+        let (&byte, rest) = bytes.split_first().ok_or(BlackboxError::Truncated)?;
+        *bytes = rest;
+        zigzag |= u64::from(byte & 0x7F) << (7 * shift);
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(BlackboxError::Truncated)
+}
+
+/// Reads frames back out of a log written by `BlackboxEncoder`. Stops after
+/// the first error.
+pub struct BlackboxDecoder<'a, T = f32, const N: usize = 4> {
+    bytes: &'a [u8],
+    previous: Option<Quantized<N>>,
+    marker: core::marker::PhantomData<T>,
+}
+impl<'a, T, const N: usize> BlackboxDecoder<'a, T, N> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            previous: None,
+            marker: core::marker::PhantomData,
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Quantized<N>, BlackboxError> {
+        let (&marker, rest) = self.bytes.split_first().ok_or(BlackboxError::Truncated)?;
+        self.bytes = rest;
+        let mut frame = match marker {
+            INTRA => Quantized::default(),
+            PREDICTED => self.previous.ok_or(BlackboxError::MissingIntraFrame)?,
+            byte => return Err(BlackboxError::BadMarker(byte)),
+        };
+        for value in frame.fields_mut() {
+            *value = value.wrapping_add(read_varint(&mut self.bytes)?);
+        }
+        Ok(frame)
+    }
+}
+impl<T: RealField + Copy, const N: usize> Iterator for BlackboxDecoder<'_, T, N> {
+    type Item = Result<BlackboxFrame<T, N>, BlackboxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        match self.next_frame() {
+            Ok(frame) => {
+                self.previous = Some(frame);
+                Some(Ok(frame.frame()))
+            }
+            Err(err) => {
+                self.bytes = &[];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(step: usize) -> BlackboxFrame {
+        let t = step as f32 * 0.002;
+        let wave = Vector3::new(t.sin(), t.cos(), -t.sin());
+        BlackboxFrame {
+            time: t,
+            setpoint: wave * 2.0,
+            gyro: wave * 1.9,
+            p: wave * 0.1,
+            i: Vector3::new(0.01, -0.02, 0.0),
+            d: -wave * 0.05,
+            motors: [0.5 + 0.1 * t.sin(), 0.5, 0.45, 0.55],
+        }
+    }
+
+    #[test]
+    fn round_trips_to_a_thousandth() {
+        let mut encoder = BlackboxEncoder::new();
+        let mut log = [0; 4096];
+        let mut len = 0;
+        for step in 0..100 {
+            len += encoder.encode(&frame(step), &mut log[len..]).unwrap();
+        }
+        // Delta frames of a smooth signal take under a third of the space of
+        // 20 raw floats a frame.
+        assert!(len < 100 * 20 * 4 / 3, "{} bytes", len);
+
+        let decoded = BlackboxDecoder::<f32>::new(&log[..len]);
+        let mut count = 0;
+        for (step, decoded) in decoded.enumerate() {
+            let decoded = decoded.unwrap();
+            let original = frame(step);
+            assert!((decoded.time - original.time).abs() < 1e-6);
+            assert!((decoded.gyro - original.gyro).amax() <= 5e-4);
+            assert!((decoded.d - original.d).amax() <= 5e-4);
+            for (decoded, original) in decoded.motors.iter().zip(original.motors) {
+                assert!((decoded - original).abs() <= 5e-4);
+            }
+            count += 1;
+        }
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn full_buffer_writes_nothing() {
+        let mut encoder = BlackboxEncoder::new();
+        let mut log = [0; 8];
+        assert_eq!(
+            encoder.encode(&frame(0), &mut log),
+            Err(BlackboxError::BufferFull)
+        );
+        // The failed frame isn't used as a base for the next one.
+        let mut log = [0; 256];
+        let len = encoder.encode(&frame(1), &mut log).unwrap();
+        assert_eq!(log[0], INTRA);
+        let decoded: BlackboxFrame = BlackboxDecoder::new(&log[..len]).next().unwrap().unwrap();
+        assert_eq!(decoded.motors[1], 0.5);
+    }
+
+    #[test]
+    fn decoder_reports_damage() {
+        let mut encoder = BlackboxEncoder::new();
+        let mut log = [0; 256];
+        let first = encoder.encode(&frame(0), &mut log).unwrap();
+        let second = encoder.encode(&frame(1), &mut log[first..]).unwrap();
+        assert_eq!(log[first], PREDICTED);
+
+        let mut truncated = BlackboxDecoder::<f32>::new(&log[..first + second - 1]);
+        assert!(truncated.next().unwrap().is_ok());
+        assert_eq!(truncated.next(), Some(Err(BlackboxError::Truncated)));
+        assert_eq!(truncated.next(), None);
+
+        let mut headless = BlackboxDecoder::<f32>::new(&log[first..first + second]);
+        assert_eq!(headless.next(), Some(Err(BlackboxError::MissingIntraFrame)));
+    }
+}
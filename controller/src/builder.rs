@@ -0,0 +1,368 @@
+use nalgebra::{RealField, Vector3};
+
+use crate::{cast, Controller, FlightMode, MotorMixer, PidGains, ThrustCurve};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Roll,
+    Pitch,
+    Yaw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    GyroLowPass,
+    GyroNotch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A PID gain was negative or NaN.
+    Gain(Axis),
+    ILimit,
+    LoopRate,
+    /// A filter frequency was negative, or at or above half the loop rate.
+    Cutoff(Filter),
+    NotchQ,
+    /// The max angle wasn't above 0 and at most 90°.
+    MaxAngle,
+    Expo(Axis),
+    Deadzone,
+    /// The TPA breakpoint wasn't in [0, 1) or the factor wasn't in [0, 1].
+    Tpa,
+    /// A rate profile's full stick rate wasn't positive.
+    Rate(Axis),
+    /// There's no rate profile slot with this index.
+    Profile(usize),
+    /// There's no motor with this index.
+    Motor(usize),
+    /// A battery reading had no cells, or a voltage that wasn't positive.
+    Battery,
+}
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConfigError::Gain(axis) => write!(f, "{:?} gains must be nonnegative", axis),
+            ConfigError::ILimit => write!(f, "integral limit must be nonnegative"),
+            ConfigError::LoopRate => write!(f, "loop rate must be positive"),
+            ConfigError::Cutoff(filter) => {
+                write!(
+                    f,
+                    "{:?} frequency must be between 0 and half the loop rate",
+                    filter
+                )
+            }
+            ConfigError::NotchQ => write!(f, "notch Q must be positive"),
+            ConfigError::MaxAngle => write!(f, "max angle must be between 0 and 90°"),
+            ConfigError::Expo(axis) => write!(f, "{:?} expo must be between 0 and 1", axis),
+            ConfigError::Deadzone => write!(f, "deadzone must be between 0 and 0.5"),
+            ConfigError::Tpa => write!(
+                f,
+                "TPA breakpoint must be in [0, 1) and its factor between 0 and 1"
+            ),
+            ConfigError::Rate(axis) => write!(f, "{:?} rate must be positive", axis),
+            ConfigError::Profile(index) => write!(f, "no rate profile {}", index),
+            ConfigError::Motor(index) => write!(f, "no motor {}", index),
+            ConfigError::Battery => {
+                write!(f, "battery needs at least one cell and a positive voltage")
+            }
+        }
+    }
+}
+
+/// Collects a controller's configuration and checks it all at once in
+/// `build`. Anything not set keeps the value `Controller::with_mixer` gives
+/// it.
+pub struct ControllerBuilder<T = f32, const N: usize = 4> {
+    mixer: MotorMixer<T, N>,
+    roll: PidGains<T>,
+    pitch: PidGains<T>,
+    yaw: PidGains<T>,
+    i_limit: Option<T>,
+    loop_hz: Option<T>,
+    gyro_lpf_hz: Option<T>,
+    gyro_notch: Option<(T, T)>,
+    mode: Option<FlightMode>,
+    max_angle: Option<T>,
+    max_rates: Option<Vector3<T>>,
+    expo: Option<Vector3<T>>,
+    deadzone: Option<T>,
+    tpa: Option<(T, T)>,
+    thrust_curve: Option<ThrustCurve>,
+}
+impl<T: RealField + Copy, const N: usize> ControllerBuilder<T, N> {
+    pub fn new(mixer: MotorMixer<T, N>) -> Self {
+        let gains = PidGains::new(T::one(), T::zero(), T::zero());
+        Self {
+            mixer,
+            roll: gains,
+            pitch: gains,
+            yaw: gains,
+            i_limit: None,
+            loop_hz: None,
+            gyro_lpf_hz: None,
+            gyro_notch: None,
+            mode: None,
+            max_angle: None,
+            max_rates: None,
+            expo: None,
+            deadzone: None,
+            tpa: None,
+            thrust_curve: None,
+        }
+    }
+
+    pub fn gains(mut self, roll: PidGains<T>, pitch: PidGains<T>, yaw: PidGains<T>) -> Self {
+        self.roll = roll;
+        self.pitch = pitch;
+        self.yaw = yaw;
+        self
+    }
+
+    pub fn i_limit(mut self, i_limit: T) -> Self {
+        self.i_limit = Some(i_limit);
+        self
+    }
+
+    /// How often the loop will run. Only used to check filter frequencies
+    /// against Nyquist; without it they are only checked for sign.
+    pub fn loop_hz(mut self, loop_hz: T) -> Self {
+        self.loop_hz = Some(loop_hz);
+        self
+    }
+
+    pub fn gyro_lpf_hz(mut self, cutoff_hz: T) -> Self {
+        self.gyro_lpf_hz = Some(cutoff_hz);
+        self
+    }
+
+    pub fn gyro_notch(mut self, hz: T, q: T) -> Self {
+        self.gyro_notch = Some((hz, q));
+        self
+    }
+
+    pub fn mode(mut self, mode: FlightMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Radians.
+    pub fn max_angle(mut self, max_angle: T) -> Self {
+        self.max_angle = Some(max_angle);
+        self
+    }
+
+    /// Rad/s at full stick. See `Controller::set_max_rates`.
+    pub fn max_rates(mut self, roll: T, pitch: T, yaw: T) -> Self {
+        self.max_rates = Some(Vector3::new(roll, pitch, yaw));
+        self
+    }
+
+    pub fn expo(mut self, roll: T, pitch: T, yaw: T) -> Self {
+        self.expo = Some(Vector3::new(roll, pitch, yaw));
+        self
+    }
+
+    pub fn deadzone(mut self, deadzone: T) -> Self {
+        self.deadzone = Some(deadzone);
+        self
+    }
+
+    /// See `Controller::set_tpa`.
+    pub fn tpa(mut self, breakpoint: T, factor: T) -> Self {
+        self.tpa = Some((breakpoint, factor));
+        self
+    }
+
+    pub fn thrust_curve(mut self, thrust_curve: ThrustCurve) -> Self {
+        self.thrust_curve = Some(thrust_curve);
+        self
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let nonnegative = |v: T| v >= T::zero();
+        let positive = |v: T| v > T::zero();
+        let axes = [
+            (Axis::Roll, self.roll),
+            (Axis::Pitch, self.pitch),
+            (Axis::Yaw, self.yaw),
+        ];
+        for (axis, gains) in axes {
+            if ![gains.kp, gains.ki, gains.kd].into_iter().all(nonnegative) {
+                return Err(ConfigError::Gain(axis));
+            }
+        }
+        if self.i_limit.is_some_and(|i_limit| !nonnegative(i_limit)) {
+            return Err(ConfigError::ILimit);
+        }
+        if self.loop_hz.is_some_and(|hz| !positive(hz)) {
+            return Err(ConfigError::LoopRate);
+        }
+        let nyquist = self.loop_hz.map(|hz| hz / cast(2.0));
+        let in_band = |hz: T| nonnegative(hz) && nyquist.is_none_or(|nyquist| hz < nyquist);
+        if self.gyro_lpf_hz.is_some_and(|hz| !in_band(hz)) {
+            return Err(ConfigError::Cutoff(Filter::GyroLowPass));
+        }
+        if let Some((hz, q)) = self.gyro_notch {
+            if !in_band(hz) {
+                return Err(ConfigError::Cutoff(Filter::GyroNotch));
+            }
+            if !positive(q) {
+                return Err(ConfigError::NotchQ);
+            }
+        }
+        if self
+            .max_angle
+            .is_some_and(|angle| !(angle > T::zero() && angle <= T::frac_pi_2()))
+        {
+            return Err(ConfigError::MaxAngle);
+        }
+        if let Some(rates) = self.max_rates {
+            let axes = [
+                (Axis::Roll, rates.x),
+                (Axis::Pitch, rates.y),
+                (Axis::Yaw, rates.z),
+            ];
+            for (axis, rate) in axes {
+                if !(positive(rate) && rate.is_finite()) {
+                    return Err(ConfigError::Rate(axis));
+                }
+            }
+        }
+        if let Some(expo) = self.expo {
+            let axes = [
+                (Axis::Roll, expo.x),
+                (Axis::Pitch, expo.y),
+                (Axis::Yaw, expo.z),
+            ];
+            for (axis, expo) in axes {
+                if !(nonnegative(expo) && expo <= T::one()) {
+                    return Err(ConfigError::Expo(axis));
+                }
+            }
+        }
+        if self
+            .deadzone
+            .is_some_and(|deadzone| !(nonnegative(deadzone) && deadzone <= cast(0.5)))
+        {
+            return Err(ConfigError::Deadzone);
+        }
+        if let Some((breakpoint, factor)) = self.tpa {
+            if !(nonnegative(breakpoint) && breakpoint < T::one()) {
+                return Err(ConfigError::Tpa);
+            }
+            if !(nonnegative(factor) && factor <= T::one()) {
+                return Err(ConfigError::Tpa);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<Controller<T, N>, ConfigError> {
+        self.validate()?;
+        let mut controller =
+            Controller::with_mixer_and_gains(self.mixer, self.roll, self.pitch, self.yaw);
+        if let Some(i_limit) = self.i_limit {
+            controller.set_i_limit(i_limit);
+        }
+        if let Some(cutoff_hz) = self.gyro_lpf_hz {
+            controller.set_gyro_lpf_hz(cutoff_hz)?;
+        }
+        if let Some((hz, q)) = self.gyro_notch {
+            controller.set_gyro_notch(hz, q);
+        }
+        if let Some(mode) = self.mode {
+            controller.set_mode(mode);
+        }
+        if let Some(max_angle) = self.max_angle {
+            controller.set_max_angle(max_angle);
+        }
+        if let Some(rates) = self.max_rates {
+            controller.set_max_rates(rates.x, rates.y, rates.z)?;
+        }
+        if let Some(expo) = self.expo {
+            controller.set_expo(expo.x, expo.y, expo.z)?;
+        }
+        if let Some(deadzone) = self.deadzone {
+            controller.set_deadzone(deadzone);
+        }
+        if let Some((breakpoint, factor)) = self.tpa {
+            controller.set_tpa(breakpoint, factor)?;
+        }
+        if let Some(thrust_curve) = self.thrust_curve {
+            controller.set_thrust_curve(thrust_curve);
+        }
+        Ok(controller)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_valid_config() {
+        let controller = Controller::builder()
+            .gains(
+                PidGains::new(0.03, 0.01, 0.0),
+                PidGains::new(0.03, 0.01, 0.0),
+                PidGains::new(0.1, 0.0, 0.0),
+            )
+            .loop_hz(1000.0)
+            .gyro_lpf_hz(100.0)
+            .gyro_notch(200.0, 3.0)
+            .mode(FlightMode::Angle)
+            .max_angle(0.5)
+            .build()
+            .unwrap();
+        assert_eq!(controller.mode(), FlightMode::Angle);
+        assert_eq!(controller.max_angle(), 0.5);
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        let negative = PidGains::new(0.1, -0.01, 0.0);
+        let fine = PidGains::new(0.1, 0.0, 0.0);
+        assert_eq!(
+            Controller::builder()
+                .gains(fine, negative, fine)
+                .build()
+                .err(),
+            Some(ConfigError::Gain(Axis::Pitch))
+        );
+        assert_eq!(
+            Controller::builder()
+                .loop_hz(500.0)
+                .gyro_lpf_hz(250.0)
+                .build()
+                .err(),
+            Some(ConfigError::Cutoff(Filter::GyroLowPass))
+        );
+        // Without a loop rate only the sign is checked.
+        assert!(Controller::builder().gyro_lpf_hz(250.0).build().is_ok());
+        assert_eq!(
+            Controller::builder().gyro_notch(100.0, 0.0).build().err(),
+            Some(ConfigError::NotchQ)
+        );
+        assert_eq!(
+            Controller::builder().max_angle(2.0).build().err(),
+            Some(ConfigError::MaxAngle)
+        );
+        assert_eq!(
+            Controller::builder().max_rates(3.0, 0.0, 6.0).build().err(),
+            Some(ConfigError::Rate(Axis::Pitch))
+        );
+        assert_eq!(
+            Controller::builder().expo(0.2, 0.2, 1.2).build().err(),
+            Some(ConfigError::Expo(Axis::Yaw))
+        );
+        assert_eq!(
+            Controller::builder().tpa(0.5, 1.5).build().err(),
+            Some(ConfigError::Tpa)
+        );
+        assert_eq!(
+            Controller::builder().tpa(1.0, 0.5).build().err(),
+            Some(ConfigError::Tpa)
+        );
+    }
+}
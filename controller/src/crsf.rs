@@ -0,0 +1,145 @@
+use nalgebra::RealField;
+
+use crate::sbus::{unpack_channels, StickMap};
+use crate::TransmitterState;
+
+/// Longest CRSF frame, address and length bytes included.
+const MAX_FRAME_LEN: usize = 64;
+/// Address bytes a receiver starts its frames with: the flight controller and
+/// the legacy sync byte.
+const ADDRESSES: [u8; 2] = [0xC8, 0xEE];
+const RC_CHANNELS_PACKED: u8 = 0x16;
+/// 16 channels of 11 bits.
+const RC_CHANNELS_LEN: usize = 22;
+const CRC_POLY: u8 = 0xD5;
+
+/// CRC-8/DVB-S2 over a frame's type and payload.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ CRC_POLY
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Assembles CRSF frames from a UART one byte at a time and turns the RC
+/// channel frames into stick positions. Other frame types, and frames that
+/// fail their CRC, are dropped. ExpressLRS stops sending channels when the
+/// link goes down, which the controller's own failsafe picks up.
+pub struct CrsfParser {
+    buffer: [u8; MAX_FRAME_LEN],
+    len: usize,
+    map: StickMap,
+}
+impl CrsfParser {
+    pub fn new(map: StickMap) -> Self {
+        Self {
+            buffer: [0; MAX_FRAME_LEN],
+            len: 0,
+            map,
+        }
+    }
+
+    /// Returns the sticks once `byte` completes a valid RC channel frame.
+    pub fn push_byte<T: RealField + Copy>(&mut self, byte: u8) -> Option<TransmitterState<T>> {
+        match self.len {
+            // Skip anything until an address byte.
+            0 if !ADDRESSES.contains(&byte) => return None,
+            // The length covers the type, payload and CRC.
+            1 if !(2..=MAX_FRAME_LEN - 2).contains(&(byte as usize)) => {
+                self.len = 0;
+                return self.push_byte(byte);
+            }
+            _ => {}
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        if self.len < 2 || self.len < self.buffer[1] as usize + 2 {
+            return None;
+        }
+
+        let frame = &self.buffer[2..self.len];
+        self.len = 0;
+        let (&crc, body) = frame.split_last()?;
+        if crc8(body) != crc {
+            return None;
+        }
+        match body {
+            [RC_CHANNELS_PACKED, payload @ ..] if payload.len() == RC_CHANNELS_LEN => {
+                self.map.sticks(&unpack_channels(payload)).ok()
+            }
+            _ => None,
+        }
+    }
+}
+impl Default for CrsfParser {
+    fn default() -> Self {
+        Self::new(StickMap::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Full right roll, full back pitch, some throttle and left yaw.
+    const RC_CHANNELS: [u8; 26] = [
+        0xC8, 0x18, 0x16, 0x13, 0x67, 0x05, 0x5E, 0xB1, 0x04, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xE0,
+        0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x67,
+    ];
+    /// Link statistics, which carry no sticks.
+    const LINK_STATISTICS: [u8; 14] = [
+        0xC8, 0x0C, 0x14, 0x50, 0x00, 0x64, 0x05, 0x00, 0x04, 0x02, 0x50, 0x64, 0x05, 0xA5,
+    ];
+
+    fn feed(parser: &mut CrsfParser, bytes: &[u8]) -> Option<TransmitterState> {
+        bytes
+            .iter()
+            .fold(None, |last, &byte| parser.push_byte(byte).or(last))
+    }
+
+    #[test]
+    fn crc_matches_dvb_s2() {
+        assert_eq!(crc8(b"123456789"), 0xBC);
+    }
+
+    #[test]
+    fn parses_rc_channel_frame() {
+        let mut parser = CrsfParser::default();
+        // Nothing comes out until the CRC byte.
+        let (last, rest) = RC_CHANNELS.split_last().unwrap();
+        assert_eq!(feed(&mut parser, rest), None);
+        let sticks: TransmitterState = parser.push_byte(*last).unwrap();
+        assert_eq!(sticks.left_right(), 1.0);
+        assert_eq!(sticks.forward_backward(), 1.0);
+        assert!((sticks.up_down() - 0.749).abs() < 1e-3);
+        assert!(sticks.rotate_pos_neg() > 0.5);
+    }
+
+    #[test]
+    fn drops_frames_with_bad_crc() {
+        let mut parser = CrsfParser::default();
+        let mut corrupt = RC_CHANNELS;
+        corrupt[10] ^= 0x01;
+        assert_eq!(feed(&mut parser, &corrupt), None);
+        // The next good frame still gets through.
+        assert!(feed(&mut parser, &RC_CHANNELS).is_some());
+    }
+
+    #[test]
+    fn resyncs_through_garbage_and_other_frames() {
+        let mut parser = CrsfParser::default();
+        let garbage = [0x00, 0xFF, 0x16, 0xC8, 0xFF, 0x42];
+        let frames = garbage
+            .iter()
+            .chain(&LINK_STATISTICS)
+            .chain(&RC_CHANNELS)
+            .filter(|&&byte| parser.push_byte::<f32>(byte).is_some())
+            .count();
+        assert_eq!(frames, 1);
+    }
+}
@@ -0,0 +1,209 @@
+use nalgebra::{RealField, Vector3};
+
+use crate::cast;
+
+/// First-order low-pass filter on a 3-axis signal. A cutoff of zero disables it.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub(crate) struct LowPass<T> {
+    cutoff_hz: T,
+    state: Option<Vector3<T>>,
+}
+impl<T: RealField + Copy> LowPass<T> {
+    pub(crate) fn new(cutoff_hz: T) -> Self {
+        Self {
+            cutoff_hz,
+            state: None,
+        }
+    }
+
+    pub(crate) fn set_cutoff_hz(&mut self, cutoff_hz: T) {
+        self.cutoff_hz = cutoff_hz;
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.state = None;
+    }
+
+    pub(crate) fn update(&mut self, input: Vector3<T>, dt: T) -> Vector3<T> {
+        if self.cutoff_hz <= T::zero() {
+            return input;
+        }
+        let output = match self.state {
+            Some(state) if dt > T::zero() => {
+                let rc = T::one() / (T::two_pi() * self.cutoff_hz);
+                let alpha = dt / (rc + dt);
+                state + (input - state) * alpha
+            }
+            Some(state) => state,
+            None => input,
+        };
+        self.state = Some(output);
+        output
+    }
+}
+
+/// Biquad notch filter on a 3-axis signal, cutting a narrow band around
+/// `center_hz`. Higher `q` makes the notch narrower. The coefficients follow
+/// the sample interval, so irregular timing shifts the notch with it. A zero
+/// center, or one at or above the Nyquist frequency, disables it.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub(crate) struct Notch<T> {
+    center_hz: T,
+    q: T,
+    /// Last two inputs and outputs, newest first.
+    inputs: [Vector3<T>; 2],
+    outputs: [Vector3<T>; 2],
+}
+impl<T: RealField + Copy> Notch<T> {
+    pub(crate) fn new(center_hz: T, q: T) -> Self {
+        Self {
+            center_hz,
+            q,
+            inputs: [Vector3::zeros(); 2],
+            outputs: [Vector3::zeros(); 2],
+        }
+    }
+
+    pub(crate) fn set(&mut self, center_hz: T, q: T) {
+        self.center_hz = center_hz;
+        self.q = q;
+    }
+
+    pub(crate) fn q(&self) -> T {
+        self.q
+    }
+
+    /// Moves the notch without disturbing the filter state, so it can track
+    /// a changing frequency sample by sample.
+    pub(crate) fn set_center_hz(&mut self, center_hz: T) {
+        self.center_hz = center_hz;
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.inputs = [Vector3::zeros(); 2];
+        self.outputs = [Vector3::zeros(); 2];
+    }
+
+    pub(crate) fn update(&mut self, input: Vector3<T>, dt: T) -> Vector3<T> {
+        if dt <= T::zero()
+            || self.center_hz <= T::zero()
+            || self.q <= T::zero()
+            || self.center_hz >= cast::<T>(0.5) / dt
+        {
+            return input;
+        }
+        let w0 = T::two_pi() * self.center_hz * dt;
+        let alpha = w0.sin() / (cast::<T>(2.0) * self.q);
+        let a0 = T::one() + alpha;
+        let b1 = cast::<T>(-2.0) * w0.cos() / a0;
+        let b0 = T::one() / a0;
+        let a2 = (T::one() - alpha) / a0;
+        // The notch's numerator is 1, -2cos(w0), 1 and shares b1 with the
+        // denominator.
+        let output = input * b0 + self.inputs[0] * b1 + self.inputs[1] * b0
+            - self.outputs[0] * b1
+            - self.outputs[1] * a2;
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn low_pass_tracks_dc_under_high_frequency_noise() {
+        let mut filter = LowPass::new(20.0);
+        let dt = 1.0 / 2000.0;
+        let mut output = Vector3::zeros();
+        for i in 0..2000 {
+            let t = i as f32 * dt;
+            let noise = 0.3 * (2.0 * PI * 200.0 * t).sin();
+            output = filter.update(Vector3::new(0.5 + noise, -0.2, noise), dt);
+            if i > 500 {
+                assert!((output.x - 0.5).abs() < 0.05);
+                assert!(output.z.abs() < 0.05);
+            }
+        }
+        assert!((output.y + 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn notch_removes_tone_but_keeps_dc() {
+        let mut filter = Notch::new(150.0, 3.0);
+        let dt = 1.0 / 2000.0;
+        let mut peak = 0.0_f32;
+        for i in 0..4000 {
+            let t = i as f32 * dt;
+            let tone = 0.5 * (2.0 * PI * 150.0 * t).sin();
+            let output = filter.update(Vector3::new(1.0 + tone, tone, 0.0), dt);
+            if i > 2000 {
+                assert!((output.x - 1.0).abs() < 0.02);
+                peak = peak.max(output.y.abs());
+            }
+        }
+        // Down from 0.5 by well over 20 dB.
+        assert!(peak < 0.02, "{}", peak);
+    }
+
+    #[test]
+    fn retuned_notch_tracks_swept_tone() {
+        let mut filter = Notch::new(100.0, 3.0);
+        let dt = 1.0 / 2000.0;
+        let mut phase = 0.0_f32;
+        let mut peak = 0.0_f32;
+        for i in 0..4000 {
+            // 100 Hz to 300 Hz over two seconds.
+            let hz = 100.0 + 100.0 * i as f32 * dt;
+            phase += 2.0 * PI * hz * dt;
+            filter.set_center_hz(hz);
+            let output = filter.update(Vector3::new(0.5 * phase.sin(), 0.0, 0.0), dt);
+            if i > 1000 {
+                peak = peak.max(output.x.abs());
+            }
+        }
+        assert!(peak < 0.05, "{}", peak);
+    }
+
+    #[test]
+    fn notch_passes_other_frequencies() {
+        let mut filter = Notch::new(150.0, 3.0);
+        let dt = 1.0 / 2000.0;
+        let mut peak = 0.0_f32;
+        for i in 0..4000 {
+            let t = i as f32 * dt;
+            let output = filter.update(Vector3::new((2.0 * PI * 20.0 * t).sin(), 0.0, 0.0), dt);
+            if i > 2000 {
+                peak = peak.max(output.x.abs());
+            }
+        }
+        assert!(peak > 0.95);
+    }
+
+    #[test]
+    fn zero_cutoff_passes_through() {
+        let mut filter = LowPass::new(0.0);
+        let input = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(filter.update(input, 0.001), input);
+    }
+}
@@ -0,0 +1,34 @@
+//! `defmt::Format` for the types that hold vectors or private fields, which
+//! can't derive it.
+
+use defmt::{Format, Formatter};
+use nalgebra::{RealField, Scalar};
+
+use crate::{IMUDataPoint, MotorSpeeds};
+
+impl<T: Scalar + Copy + Format> Format for IMUDataPoint<T> {
+    fn format(&self, f: Formatter<'_>) {
+        let (gyro, accel) = (&self.gyro, &self.accel);
+        defmt::write!(
+            f,
+            "IMUDataPoint {{ gyro: [{}, {}, {}], accel: [{}, {}, {}], time_point: {} }}",
+            gyro.x,
+            gyro.y,
+            gyro.z,
+            accel.x,
+            accel.y,
+            accel.z,
+            self.time_point
+        );
+    }
+}
+
+/// Logged as `f32` whatever the controller's scalar type, so the controller
+/// can trace its outputs without requiring `T: Format`.
+impl<T: RealField + Copy, const N: usize> Format for MotorSpeeds<T, N> {
+    fn format(&self, f: Formatter<'_>) {
+        let speeds: [f32; N] =
+            core::array::from_fn(|i| self.get(i).to_subset().unwrap_or(f64::NAN) as f32);
+        defmt::write!(f, "MotorSpeeds {}", speeds);
+    }
+}
@@ -0,0 +1,20 @@
+use crate::IMUDataPoint;
+
+/// A gyro and accelerometer the controller can poll, so the same control
+/// loop runs on any IMU driver. `read` returns `nb::Error::WouldBlock` until
+/// a new sample is ready. Samples are in the controller's body frame.
+pub trait ImuSource<T = f32> {
+    type Error;
+
+    fn read(&mut self) -> nb::Result<IMUDataPoint<T>, Self::Error>;
+}
+
+/// Anything that yields samples, such as a recorded flight, is a source that
+/// never blocks. It runs dry with `WouldBlock`.
+impl<T, I: Iterator<Item = IMUDataPoint<T>>> ImuSource<T> for I {
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self) -> nb::Result<IMUDataPoint<T>, Self::Error> {
+        self.next().ok_or(nb::Error::WouldBlock)
+    }
+}
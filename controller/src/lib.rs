@@ -1,109 +1,298 @@
 #![no_std]
 
-use core::f32::consts::PI;
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
-use nalgebra::Vector3;
+use nalgebra::{RealField, UnitQuaternion, Vector3};
 
-fn min(v1: f32, v2: f32) -> f32 {
+/// `defmt::trace!` with the `defmt` feature on, nothing without it.
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::trace!($($arg)*);
+    }};
+}
+
+mod altitude;
+mod attitude;
+mod blackbox;
+mod builder;
+mod crsf;
+mod filter;
+#[cfg(feature = "defmt")]
+mod format;
+mod imu;
+mod math;
+mod mixer;
+mod navigation;
+mod pid;
+mod profile;
+#[cfg(feature = "alloc")]
+mod recorder;
+mod sbus;
+#[cfg(feature = "serde")]
+mod serialize;
+mod state;
+#[cfg(feature = "units")]
+mod units;
+
+use altitude::{pressure_altitude, AltitudeEstimator};
+use attitude::{wrap_angle, yaw_of, ComplementaryFilter, HeadingFilter, Madgwick, GRAVITY};
+pub use blackbox::{BlackboxDecoder, BlackboxEncoder, BlackboxError, BlackboxFrame};
+pub use builder::{Axis, ConfigError, ControllerBuilder, Filter};
+pub use crsf::CrsfParser;
+use filter::{LowPass, Notch};
+pub use imu::ImuSource;
+pub use mixer::{MotorMixer, PropSpin, Saturation, ThrustCurve};
+use navigation::{approach, arrived, Mission, PositionEstimator};
+use pid::Pid;
+pub use pid::{PidGains, PidTerms};
+pub use profile::{RateProfile, RATE_PROFILES};
+#[cfg(feature = "alloc")]
+pub use recorder::FlightRecorder;
+pub use sbus::{ChannelMap, SbusError, SbusFrame, StickMap, SBUS_FRAME_LEN};
+pub use state::ControllerState;
+#[cfg(feature = "units")]
+pub use units::{Normalized, RadPerSec, Radians};
+
+/// Converts an `f64` constant into the controller's scalar type.
+pub(crate) fn cast<T: RealField>(v: f64) -> T {
+    nalgebra::convert(v)
+}
+
+fn min<T: RealField + Copy>(v1: T, v2: T) -> T {
     if v1 < v2 {
-        return v1;
+        v1
+    } else {
+        v2
     }
-    return v2;
 }
 
-fn max(v1: f32, v2: f32) -> f32 {
+fn max<T: RealField + Copy>(v1: T, v2: T) -> T {
     if v1 > v2 {
-        return v1;
+        v1
+    } else {
+        v2
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Motor<T> {
+    speed: T,
+}
+impl<T: RealField + Copy> Motor<T> {
+    fn new() -> Self {
+        Self { speed: T::zero() }
+    }
+}
+
+/// Where a quadcopter's motor sits, in the order the mixer numbers them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotorPosition {
+    FrontLeft,
+    FrontRight,
+    RearLeft,
+    RearRight,
+}
+impl MotorPosition {
+    pub const ALL: [MotorPosition; 4] = [
+        MotorPosition::FrontLeft,
+        MotorPosition::FrontRight,
+        MotorPosition::RearLeft,
+        MotorPosition::RearRight,
+    ];
+
+    /// The unit corner `MotorMixer::quad_x` puts this motor on, in the body
+    /// frame: x forward, y up and z right.
+    pub fn unit_position<T: RealField + Copy>(self) -> Vector3<T> {
+        let (x, z) = match self {
+            MotorPosition::FrontLeft => (1.0, -1.0),
+            MotorPosition::FrontRight => (1.0, 1.0),
+            MotorPosition::RearLeft => (-1.0, -1.0),
+            MotorPosition::RearRight => (-1.0, 1.0),
+        };
+        Vector3::new(cast(x), T::zero(), cast(z))
     }
-    return v2;
 }
 
-struct Motor {
-    speed: f32,
-    pos: Vector3<f32>,
+/// Anything that picks out one of `N` motors: a plain index for any frame, or
+/// a `MotorPosition` on a quad.
+pub trait MotorIndex<const N: usize> {
+    fn index(self) -> usize;
 }
-impl Motor {
-    fn new(pos: Vector3<f32>) -> Self {
-        Self { speed: 0.0, pos }
+impl<const N: usize> MotorIndex<N> for usize {
+    fn index(self) -> usize {
+        self
+    }
+}
+impl MotorIndex<4> for MotorPosition {
+    fn index(self) -> usize {
+        self as usize
     }
 }
 
-pub struct MotorSpeeds {
-    front_left: Motor,
-    front_right: Motor,
-    rear_left: Motor,
-    rear_right: Motor,
+/// DSHOT frame that stops the motor. Values 1 to 47 are ESC commands.
+pub const DSHOT_DISARMED: u16 = 0;
+pub const DSHOT_MIN_THROTTLE: u16 = 48;
+pub const DSHOT_MAX_THROTTLE: u16 = 2047;
+/// DSHOT commands that set the spin direction, sent while the motors are
+/// stopped.
+pub const DSHOT_CMD_SPIN_DIRECTION_NORMAL: u16 = 20;
+pub const DSHOT_CMD_SPIN_DIRECTION_REVERSED: u16 = 21;
+
+#[derive(Clone, Copy)]
+pub struct MotorSpeeds<T = f32, const N: usize = 4> {
+    motors: [Motor<T>; N],
 }
-impl MotorSpeeds {
+pub type Quad<T = f32> = MotorSpeeds<T, 4>;
+impl<T: RealField + Copy, const N: usize> MotorSpeeds<T, N> {
     pub fn new() -> Self {
         Self {
-            front_left: Motor::new(Vector3::new(1.0, 0.0, -1.0)),
-            front_right: Motor::new(Vector3::new(1.0, 0.0, 1.0)),
-            rear_left: Motor::new(Vector3::new(-1.0, 0.0, -1.0)),
-            rear_right: Motor::new(Vector3::new(-1.0, 0.0, 1.0)),
+            motors: core::array::from_fn(|_| Motor::new()),
         }
     }
-    pub fn set_front_left(&mut self, val: f32) {
-        self.front_left.speed = min(max(val, 0.0), 1.0);
+    pub fn set(&mut self, i: impl MotorIndex<N>, val: T) {
+        self.motors[i.index()].speed = constrain(val);
+    }
+    pub fn get(&self, i: impl MotorIndex<N>) -> T {
+        self.motors[i.index()].speed
+    }
+
+    /// Servo pulse widths in microseconds, with zero output at `min_us` and
+    /// full output at `max_us`. Most ESCs stop the motor at `min_us`, so that
+    /// is also the value to send while disarmed.
+    pub fn to_pwm(&self, min_us: u16, max_us: u16) -> [u16; N] {
+        self.scaled(min_us, max_us)
+    }
+
+    /// DSHOT throttle values from `DSHOT_MIN_THROTTLE` to `DSHOT_MAX_THROTTLE`.
+    /// Zero output maps to the lowest throttle, which spins the motors at
+    /// idle, so send `DSHOT_DISARMED` instead while disarmed.
+    pub fn to_dshot(&self) -> [u16; N] {
+        self.scaled(DSHOT_MIN_THROTTLE, DSHOT_MAX_THROTTLE)
+    }
+
+    fn scaled(&self, low: u16, high: u16) -> [u16; N] {
+        let low: T = cast(low as f64);
+        let high: T = cast(high as f64);
+        core::array::from_fn(|i| {
+            let value = (low + constrain(self.motors[i].speed) * (high - low)).round();
+            value.to_subset().unwrap_or(0.0) as u16
+        })
+    }
+}
+impl<T: RealField + Copy> MotorSpeeds<T, 4> {
+    pub fn iter(&self) -> impl Iterator<Item = (MotorPosition, T)> + '_ {
+        MotorPosition::ALL
+            .into_iter()
+            .map(|position| (position, self.get(position)))
+    }
+    pub fn set_front_left(&mut self, val: T) {
+        self.set(MotorPosition::FrontLeft, val);
+    }
+    pub fn set_front_right(&mut self, val: T) {
+        self.set(MotorPosition::FrontRight, val);
     }
-    pub fn set_front_right(&mut self, val: f32) {
-        self.front_right.speed = min(max(val, 0.0), 1.0);
+    pub fn set_rear_left(&mut self, val: T) {
+        self.set(MotorPosition::RearLeft, val);
     }
-    pub fn set_rear_left(&mut self, val: f32) {
-        self.rear_left.speed = min(max(val, 0.0), 1.0);
+    pub fn set_rear_right(&mut self, val: T) {
+        self.set(MotorPosition::RearRight, val);
     }
-    pub fn set_rear_right(&mut self, val: f32) {
-        self.rear_right.speed = min(max(val, 0.0), 1.0);
+    pub fn get_front_left(&self) -> T {
+        self.get(MotorPosition::FrontLeft)
     }
-    pub fn get_front_left(&self) -> f32 {
-        self.front_left.speed
+    pub fn get_front_right(&self) -> T {
+        self.get(MotorPosition::FrontRight)
     }
-    pub fn get_front_right(&self) -> f32 {
-        self.front_right.speed
+    pub fn get_rear_left(&self) -> T {
+        self.get(MotorPosition::RearLeft)
     }
-    pub fn get_rear_left(&self) -> f32 {
-        self.rear_left.speed
+    pub fn get_rear_right(&self) -> T {
+        self.get(MotorPosition::RearRight)
     }
-    pub fn get_rear_right(&self) -> f32 {
-        self.rear_right.speed
+}
+impl<T: RealField + Copy, const N: usize> Default for MotorSpeeds<T, N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-struct IMUData {
-    imu_data: [IMUDataPoint; 10],
+/// The last `N` IMU samples. `N` has to be at least 2 for the D term to see
+/// the previous sample.
+#[derive(Clone)]
+struct IMUData<T, const N: usize = DEFAULT_IMU_HISTORY> {
+    imu_data: [IMUDataPoint<T>; N],
     data_idx: usize,
+    len: usize,
 }
-impl IMUData {
+impl<T: RealField + Copy, const N: usize> IMUData<T, N> {
     fn new() -> Self {
         Self {
-            imu_data: Default::default(),
+            imu_data: core::array::from_fn(|_| IMUDataPoint::default()),
             data_idx: 0,
+            len: 0,
         }
     }
 
-    fn add_data_point(&mut self, data_point: IMUDataPoint) {
-        self.data_idx += 1;
+    fn add_data_point(&mut self, data_point: IMUDataPoint<T>) {
+        self.data_idx = (self.data_idx + 1) % self.imu_data.len();
         self.imu_data[self.data_idx] = data_point;
+        self.len = (self.len + 1).min(self.imu_data.len());
     }
 
-    fn get_data_point(&self) -> &IMUDataPoint {
+    fn get_data_point(&self) -> &IMUDataPoint<T> {
         &self.imu_data[self.data_idx]
     }
+
+    /// `get_previous(0)` is the latest sample, `get_previous(1)` the one before it.
+    fn get_previous(&self, n: usize) -> Option<&IMUDataPoint<T>> {
+        if n >= self.len {
+            return None;
+        }
+        let capacity = self.imu_data.len();
+        Some(&self.imu_data[(self.data_idx + capacity - n) % capacity])
+    }
+
+    /// Standard deviation of the accelerometer magnitude over the recorded samples.
+    fn accel_std_dev(&self) -> T {
+        if self.len == 0 {
+            return T::zero();
+        }
+        let samples = || (0..self.len).filter_map(|n| self.get_previous(n));
+        let count: T = cast(self.len as f64);
+        let mean = samples().fold(T::zero(), |acc, p| acc + p.accel.norm()) / count;
+        let variance = samples().fold(T::zero(), |acc, p| {
+            let deviation = p.accel.norm() - mean;
+            acc + deviation * deviation
+        }) / count;
+        variance.sqrt()
+    }
 }
 
-pub struct IMUDataPoint {
-    pub gyro: Vector3<f32>,
-    pub accel: Vector3<f32>,
-    pub time_point: f32,
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub struct IMUDataPoint<T = f32> {
+    /// Body rates about x forward (roll), y up (yaw) and z right (pitch).
+    pub gyro: Vector3<T>,
+    pub accel: Vector3<T>,
+    pub time_point: T,
 }
 
-impl Default for IMUDataPoint {
+impl<T: RealField + Copy> Default for IMUDataPoint<T> {
     fn default() -> Self {
-        Self::new(Vector3::zeros(), Vector3::zeros(), 0.0)
+        Self::new(Vector3::zeros(), Vector3::zeros(), T::zero())
     }
 }
-impl IMUDataPoint {
-    pub fn new(gyro: Vector3<f32>, accel: Vector3<f32>, time_point: f32) -> Self {
+impl<T> IMUDataPoint<T> {
+    pub fn new(gyro: Vector3<T>, accel: Vector3<T>, time_point: T) -> Self {
         Self {
             gyro,
             accel,
@@ -112,106 +301,3192 @@ impl IMUDataPoint {
     }
 }
 
-pub struct TransmitterState {
-    up_down: f32,
-    rotate_pos_neg: f32,
-    left_right: f32,
-    forwar_backward: f32,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    UpDown,
+    RotatePosNeg,
+    ForwardBackward,
+    LeftRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransmitterError {
+    /// The channel's value was outside 0..1 (or NaN).
+    OutOfRange(Channel),
+}
+impl core::fmt::Display for TransmitterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TransmitterError::OutOfRange(channel) => {
+                write!(f, "{:?} transmitter value must be between 0 and 1", channel)
+            }
+        }
+    }
+}
+
+/// Stick positions, each in 0..1. Throttle (`up_down`) idles at 0, the other
+/// three channels are centered at 0.5. `left_right` is roll, above center
+/// rolling right; `forward_backward` is pitch, above center raising the nose;
+/// `rotate_pos_neg` is yaw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "serialize::Channels<T>",
+        bound(deserialize = "T: RealField + Copy + serde::Deserialize<'de>")
+    )
+)]
+pub struct TransmitterState<T = f32> {
+    up_down: T,
+    rotate_pos_neg: T,
+    left_right: T,
+    forward_backward: T,
+}
+impl<T: RealField + Copy> TransmitterState<T> {
+    fn validate_input(val: T, channel: Channel) -> Result<T, TransmitterError> {
+        if !(val >= T::zero() && val <= T::one()) {
+            return Err(TransmitterError::OutOfRange(channel));
+        }
+        Ok(val)
+    }
+    pub fn try_new(
+        up_down: T,
+        rotate_pos_neg: T,
+        forward_backward: T,
+        left_right: T,
+    ) -> Result<Self, TransmitterError> {
+        Ok(Self {
+            up_down: Self::validate_input(up_down, Channel::UpDown)?,
+            rotate_pos_neg: Self::validate_input(rotate_pos_neg, Channel::RotatePosNeg)?,
+            forward_backward: Self::validate_input(forward_backward, Channel::ForwardBackward)?,
+            left_right: Self::validate_input(left_right, Channel::LeftRight)?,
+        })
+    }
+    /// Like `try_new`, but panics if any channel is outside 0..1.
+    pub fn new(up_down: T, rotate_pos_neg: T, forward_backward: T, left_right: T) -> Self {
+        match Self::try_new(up_down, rotate_pos_neg, forward_backward, left_right) {
+            Ok(state) => state,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    pub fn up_down(&self) -> T {
+        self.up_down
+    }
+    pub fn rotate_pos_neg(&self) -> T {
+        self.rotate_pos_neg
+    }
+    pub fn forward_backward(&self) -> T {
+        self.forward_backward
+    }
+    pub fn left_right(&self) -> T {
+        self.left_right
+    }
+}
+impl<T: RealField + Copy> Default for TransmitterState<T> {
+    fn default() -> Self {
+        let center = cast(0.5);
+        Self::new(T::zero(), center, center, center)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BaroSample<T = f32> {
+    /// Static pressure in pascals.
+    pub pressure: T,
+    /// On the same clock as the IMU samples.
+    pub time_point: T,
+}
+impl<T> BaroSample<T> {
+    pub fn new(pressure: T, time_point: T) -> Self {
+        Self {
+            pressure,
+            time_point,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MagSample<T = f32> {
+    /// Magnetic field in the body frame. Only its direction is used.
+    pub field: Vector3<T>,
+    /// On the same clock as the IMU samples.
+    pub time_point: T,
+}
+impl<T> MagSample<T> {
+    pub fn new(field: Vector3<T>, time_point: T) -> Self {
+        Self { field, time_point }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsSample<T = f32> {
+    /// Meters from any fixed origin in the navigation frame: x north, y up,
+    /// z east.
+    pub position: Vector3<T>,
+    /// When the fix was taken, on the same clock as the IMU samples.
+    pub time_point: T,
 }
-impl TransmitterState {
-    fn validate_input(val: f32) -> f32 {
-        if val > 1.0 || val < 0.0 {
-            panic!("Transmitter values must be between 1 and 0");
+impl<T> GpsSample<T> {
+    pub fn new(position: Vector3<T>, time_point: T) -> Self {
+        Self {
+            position,
+            time_point,
         }
-        val
     }
-    pub fn new(up_down: f32, rotate_pos_neg: f32, forwar_backward: f32, left_right: f32) -> Self {
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub struct Waypoint<T = f32> {
+    /// Navigation frame position, like a GPS fix. Only the horizontal part is
+    /// flown to.
+    pub position: Vector3<T>,
+    /// Seconds to hover at the waypoint before heading to the next one.
+    pub hold_time: T,
+}
+impl<T> Waypoint<T> {
+    pub fn new(position: Vector3<T>, hold_time: T) -> Self {
         Self {
-            up_down: Self::validate_input(up_down),
-            rotate_pos_neg: Self::validate_input(rotate_pos_neg),
-            forwar_backward: Self::validate_input(forwar_backward),
-            left_right: Self::validate_input(left_right),
+            position,
+            hold_time,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissionError {
+    /// The mission already holds as many waypoints as it can.
+    Full,
+}
+impl core::fmt::Display for MissionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MissionError::Full => write!(f, "mission is full"),
+        }
+    }
+}
+
+/// Largest variance of the accelerometer magnitude, in (m/s²)², over which the
+/// drone is still considered stationary during gyro calibration.
+const CALIBRATION_ACCEL_VARIANCE: f64 = 0.04;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationError {
+    NoSamples,
+    /// The accelerometer showed the drone moving during the calibration window.
+    NotStationary,
+}
+impl core::fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CalibrationError::NoSamples => write!(f, "no samples to calibrate from"),
+            CalibrationError::NotStationary => {
+                write!(f, "drone was not stationary during calibration")
+            }
         }
     }
 }
 
-fn length(vec: Vector3<f32>) -> f32 {
-    f32::sqrt(vec.x * vec.x + vec.y * vec.y + vec.z * vec.z)
+/// Per-cell voltage the controller is assumed to be tuned at.
+const FULL_CELL_VOLTAGE: f64 = 4.2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryState<T = f32> {
+    pub voltage: T,
+    pub cells: u8,
+}
+impl<T: RealField + Copy> BatteryState<T> {
+    pub fn new(voltage: T, cells: u8) -> Self {
+        Self { voltage, cells }
+    }
+
+    /// Fraction of full-charge thrust the motors can currently produce.
+    fn thrust_ratio(&self) -> T {
+        self.voltage / cast(self.cells as f64 * FULL_CELL_VOLTAGE)
+    }
+}
+
+fn constrain<T: RealField + Copy>(val: T) -> T {
+    min(max(val, T::zero()), T::one())
+}
+
+/// `constrain`, and whether it had to clamp.
+fn constrain_reporting<T: RealField + Copy>(val: T) -> (T, bool) {
+    let constrained = constrain(val);
+    (constrained, constrained != val)
+}
+
+/// Tilt in radians past which tilt compensation stops adding throttle.
+const MAX_COMPENSATED_TILT: f64 = core::f64::consts::FRAC_PI_3;
+
+/// Tilt in radians below which turtle mode takes the drone to be back on its
+/// feet and stops the motors. Gravity does the rest.
+const TURTLE_UPRIGHT_TILT: f64 = core::f64::consts::FRAC_PI_3;
+
+/// Rate in rad/s at which the setpoint has to outrun its low-passed copy for
+/// I-term relax to stop the integral completely.
+const ITERM_RELAX_THRESHOLD: f64 = 0.3;
+
+/// Notch width used until `set_gyro_notch` picks one.
+const DEFAULT_NOTCH_Q: f64 = 3.0;
+
+/// Default cutoff of the filter on the feed-forward setpoint derivative.
+const FEEDFORWARD_LPF_HZ: f64 = 20.0;
+
+/// Bounds on the time step between IMU samples, so a duplicated or
+/// out-of-order timestamp or a run of dropped frames can't blow up the
+/// integrating and differentiating terms.
+const MIN_DT: f64 = 0.0001;
+const MAX_DT: f64 = 0.05;
+
+fn clamp_dt<T: RealField + Copy>(dt: T) -> T {
+    min(max(dt, cast(MIN_DT)), cast(MAX_DT))
+}
+
+/// Maps a 0..1 stick channel centered at 0.5 onto -1..1.
+fn stick_axis<T: RealField + Copy>(val: T) -> T {
+    val * cast(2.0) - T::one()
+}
+
+/// Zeroes a -1..1 stick deflection within `deadzone` of center and rescales the
+/// rest so full deflection is still reached.
+fn apply_deadzone<T: RealField + Copy>(val: T, deadzone: T) -> T {
+    if val.abs() <= deadzone {
+        return T::zero();
+    }
+    val.signum() * (val.abs() - deadzone) / (T::one() - deadzone)
+}
+
+/// Blends a linear and a cubic response to a -1..1 stick deflection. Zero
+/// keeps the mapping linear; higher values soften the center while full
+/// deflection still reaches full scale.
+fn apply_expo<T: RealField + Copy>(val: T, expo: T) -> T {
+    val * (T::one() - expo) + val * val * val * expo
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FlightMode {
+    /// Sticks command rotation rates.
+    Rate,
+    /// Sticks command a tilt angle; centered sticks level the drone.
+    Angle,
+    /// Self-levels like `Angle` around center and fades into `Rate` towards
+    /// full stick, so the drone can still flip. See
+    /// `Controller::set_horizon_transition`.
+    Horizon,
+    /// Like `Angle`, with the throttle stick commanding a climb rate around
+    /// center instead of collective thrust.
+    AltitudeHold,
+    /// Like `AltitudeHold`, with the roll and pitch sticks moving a position
+    /// target that the drone flies to and holds. Until the first GPS fix it
+    /// flies like `AltitudeHold`.
+    PositionHold,
+    /// Climbs to a safe altitude, flies home and lands, ignoring the sticks
+    /// apart from yaw. See `ReturnPhase`.
+    ReturnToHome,
+    /// Flips a drone lying upside down back over: the motors on the side
+    /// that has to lift spin in reverse, at up to the throttle stick, and stop
+    /// once the drone is within 60° of level. Can only be
+    /// entered or left while disarmed. See `Controller::motors_reversed`.
+    Turtle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReturnPhase {
+    /// Holding position while climbing to the return altitude.
+    Climb,
+    /// Flying to the home position at the return altitude.
+    Transit,
+    /// Sinking at `LAND_RATE` until the ground stops the drone.
+    Descend,
+    /// On the ground and disarmed.
+    Landed,
 }
 
-fn constrain(val: f32) -> f32 {
-    return min(max(val, 0.0), 1.0);
+/// How far below the return altitude, in meters, the climb counts as done.
+const RETURN_ALTITUDE_TOLERANCE: f64 = 0.5;
+/// Sink rate in m/s while landing.
+const LAND_RATE: f64 = 0.5;
+/// How far in meters the altitude target may run ahead of the drone while
+/// landing before the drone is taken to be on the ground.
+const LANDED_MARGIN: f64 = 1.0;
+
+/// Stick deflection past which horizon mode no longer self-levels.
+const DEFAULT_HORIZON_TRANSITION: f64 = 0.75;
+
+/// Climb rate in m/s at full throttle deflection in altitude hold.
+const MAX_CLIMB_RATE: f64 = 1.0;
+/// Time constant in seconds of the hover throttle auto-trim.
+const HOVER_TRIM_TIME_CONSTANT: f64 = 5.0;
+/// Fastest vertical speed in m/s the auto-trim still counts as hovering.
+const HOVER_TRIM_MAX_SPEED: f64 = 0.1;
+/// Ground speed in m/s at full stick deflection in position hold, and the
+/// fastest the position loop flies towards its target.
+const MAX_HORIZONTAL_SPEED: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArmState {
+    Armed,
+    /// Motors are held at zero whatever the sticks say.
+    Disarmed,
 }
 
-pub struct Controller {
-    motors: MotorSpeeds,
-    imu: IMUData,
+/// Throttle below which the arming gestures are recognized.
+const ARM_THROTTLE: f64 = 0.05;
+/// Yaw deflection, as a fraction of full stick, the gestures must hold.
+const ARM_YAW: f64 = 0.9;
+/// Seconds a gesture must be held before it takes effect.
+const ARM_HOLD_TIME: f64 = 1.0;
+
+/// Seconds the failsafe takes to bring the throttle from its last received
+/// value down to zero, after which the controller disarms.
+const FAILSAFE_DESCENT_TIME: f64 = 3.0;
+
+/// IMU samples the controller keeps by default.
+pub const DEFAULT_IMU_HISTORY: usize = 10;
+
+/// `N` is the number of motors, `H` how many IMU samples the controller keeps
+/// for the vibration estimate and the D term.
+pub struct Controller<T = f32, const N: usize = 4, const H: usize = DEFAULT_IMU_HISTORY> {
+    arm_state: ArmState,
+    /// Latched by `kill`, holds the motors at zero until `clear_kill`.
+    killed: bool,
+    /// How long the current arming or disarming gesture has been held.
+    gesture_time: T,
+    /// Time since `arm`, for the arm ramp.
+    armed_time: T,
+    idle: T,
+    arm_ramp: T,
+    motors: MotorSpeeds<T, N>,
+    imu: IMUData<T, H>,
+    /// Indexed like the rotation vectors: x = roll, y = yaw, z = pitch.
+    pids: [Pid<T>; 3],
+    saturated: bool,
+    attitude: ComplementaryFilter<T>,
+    madgwick: Madgwick<T>,
+    heading: HeadingFilter<T>,
+    last_mag_time: Option<T>,
+    heading_hold: bool,
+    held_heading: Option<T>,
+    altitude: AltitudeEstimator<T>,
+    position: PositionEstimator<T>,
+    last_gps_time: Option<T>,
+    target_position: Option<Vector3<T>>,
+    mission: Mission<T>,
+    home: Option<Vector3<T>>,
+    return_altitude: T,
+    return_phase: ReturnPhase,
+    position_gain: T,
+    velocity_gain: T,
+    altitude_pid: Pid<T>,
+    altitude_saturated: bool,
+    target_altitude: T,
+    /// Hover throttle as set, which the auto-trim starts from.
+    hover_throttle_setting: T,
+    hover_throttle: T,
+    hover_trim: bool,
+    /// Pressure at the point the drone was armed, the zero of the altitude.
+    baro_reference: Option<T>,
+    last_baro: Option<BaroSample<T>>,
+    mode: FlightMode,
+    horizon_transition: T,
+    max_angle: T,
+    angle_gain: T,
+    rate_setpoint: Vector3<T>,
+    setpoint_slew: T,
+    throttle_slew: T,
+    /// Throttle fed into the mixer on the last step, where the throttle slew
+    /// limit starts from.
+    collective: T,
+    /// Feed-forward gain on the setpoint's rate of change, per axis.
+    feedforward: Vector3<T>,
+    feedforward_lpf: LowPass<T>,
+    /// Feed-forward part of the last step's torque.
+    feedforward_term: Vector3<T>,
+    /// Smoothed setpoint for I-term relax. Its difference from the raw
+    /// setpoint measures how fast the sticks are moving.
+    iterm_relax_lpf: LowPass<T>,
+    /// Setpoint the rate loop ran on last step, for the feed-forward term.
+    last_setpoint: Option<Vector3<T>>,
+    profiles: [RateProfile<T>; RATE_PROFILES],
+    /// Index of the active rate profile.
+    profile: usize,
+    deadzone: T,
+    mixer: MotorMixer<T, N>,
+    saturation: Saturation,
+    battery: Option<BatteryState<T>>,
+    tilt_compensation: bool,
+    tpa_breakpoint: T,
+    tpa_factor: T,
+    thrust_curve: ThrustCurve,
+    gyro_lpf: LowPass<T>,
+    gyro_notch: Notch<T>,
+    /// Center of the gyro notch when it isn't following the motors.
+    gyro_notch_hz: T,
+    rpm_notch: bool,
+    /// Mean motor rotation frequency from the latest rpm telemetry.
+    motor_hz: Option<T>,
+    gyro_bias: Vector3<T>,
+    /// Time since the previous IMU sample, or zero on the first one.
+    dt: T,
+    motor_enabled: [bool; N],
+    /// Latest transmitter frame and the time it arrived.
+    last_frame: Option<(TransmitterState<T>, T)>,
+    failsafe_timeout: T,
+    /// Whether the last step flew on failsafe sticks.
+    in_failsafe: bool,
+    /// The pilot's mode, while the failsafe has switched to return to home.
+    failsafe_mode: Option<FlightMode>,
 }
 impl Controller {
     pub fn new() -> Self {
+        Self::with_mixer(MotorMixer::quad_x())
+    }
+
+    /// A quad X controller, configured and checked through the builder.
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new(MotorMixer::quad_x())
+    }
+
+    pub fn with_gains(roll: PidGains, pitch: PidGains, yaw: PidGains) -> Self {
+        Self::with_mixer_and_gains(MotorMixer::quad_x(), roll, pitch, yaw)
+    }
+}
+impl<T: RealField + Copy, const N: usize, const H: usize> Controller<T, N, H> {
+    pub fn with_mixer(mixer: MotorMixer<T, N>) -> Self {
+        let gains = PidGains::new(T::one(), T::zero(), T::zero());
+        Self::with_mixer_and_gains(mixer, gains, gains, gains)
+    }
+
+    pub fn with_mixer_and_gains(
+        mixer: MotorMixer<T, N>,
+        roll: PidGains<T>,
+        pitch: PidGains<T>,
+        yaw: PidGains<T>,
+    ) -> Self {
         Self {
+            arm_state: ArmState::Disarmed,
+            killed: false,
+            gesture_time: T::zero(),
+            armed_time: T::zero(),
+            idle: T::zero(),
+            arm_ramp: T::zero(),
             motors: MotorSpeeds::new(),
             imu: IMUData::new(),
+            pids: [Pid::new(roll), Pid::new(yaw), Pid::new(pitch)],
+            saturated: false,
+            attitude: ComplementaryFilter::new(cast(0.5)),
+            madgwick: Madgwick::new(cast(0.1)),
+            heading: HeadingFilter::new(cast(2.0)),
+            last_mag_time: None,
+            heading_hold: false,
+            held_heading: None,
+            altitude: AltitudeEstimator::new(),
+            position: PositionEstimator::new(),
+            last_gps_time: None,
+            target_position: None,
+            mission: Mission::new(),
+            home: None,
+            return_altitude: cast(10.0),
+            return_phase: ReturnPhase::Climb,
+            position_gain: T::one(),
+            velocity_gain: cast(2.0),
+            altitude_pid: Pid::new(PidGains::new(cast(0.1), cast(0.02), cast(0.1))),
+            altitude_saturated: false,
+            target_altitude: T::zero(),
+            hover_throttle_setting: cast(0.5),
+            hover_throttle: cast(0.5),
+            hover_trim: false,
+            baro_reference: None,
+            last_baro: None,
+            mode: FlightMode::Rate,
+            horizon_transition: cast(DEFAULT_HORIZON_TRANSITION),
+            max_angle: cast(35.0_f64.to_radians()),
+            angle_gain: cast(4.0),
+            rate_setpoint: Vector3::zeros(),
+            setpoint_slew: T::zero(),
+            throttle_slew: T::zero(),
+            collective: T::zero(),
+            feedforward: Vector3::zeros(),
+            feedforward_lpf: LowPass::new(cast(FEEDFORWARD_LPF_HZ)),
+            feedforward_term: Vector3::zeros(),
+            iterm_relax_lpf: LowPass::new(T::zero()),
+            last_setpoint: None,
+            profiles: [RateProfile::standard(); RATE_PROFILES],
+            profile: 0,
+            deadzone: T::zero(),
+            mixer,
+            saturation: Saturation::Clip,
+            battery: None,
+            tilt_compensation: false,
+            tpa_breakpoint: T::one(),
+            tpa_factor: T::zero(),
+            thrust_curve: ThrustCurve::Linear,
+            gyro_lpf: LowPass::new(T::zero()),
+            gyro_notch: Notch::new(T::zero(), cast(DEFAULT_NOTCH_Q)),
+            gyro_notch_hz: T::zero(),
+            rpm_notch: false,
+            motor_hz: None,
+            gyro_bias: Vector3::zeros(),
+            dt: T::zero(),
+            motor_enabled: [true; N],
+            last_frame: None,
+            failsafe_timeout: T::zero(),
+            in_failsafe: false,
+            failsafe_mode: None,
         }
     }
 
-    fn calculate_torque(&self, desired_rotation: Vector3<f32>) -> Vector3<f32> {
-        let mut dt = desired_rotation - self.imu.get_data_point().gyro;
-        dt.y = 0.0;
-        dt
+    /// Does nothing while killed.
+    pub fn arm(&mut self) {
+        if self.killed {
+            trace!("can't arm while killed");
+            return;
+        }
+        if self.arm_state != ArmState::Armed {
+            trace!("armed");
+            self.armed_time = T::zero();
+        }
+        self.arm_state = ArmState::Armed;
+        self.gesture_time = T::zero();
     }
 
-    pub fn calculate_motor_speeds(
-        &mut self,
-        imu_data_point: IMUDataPoint,
-        transmitter_state: &TransmitterState,
-    ) -> &MotorSpeeds {
-        self.imu.add_data_point(imu_data_point);
-        let desired_rotation = Vector3::new(
-            (1.0 / 6.0) * PI * transmitter_state.left_right,
-            0.0,
-            (1.0 / 6.0) * PI * transmitter_state.forwar_backward,
-        );
-
-        let desiered_torque: Vector3<f32> = self.calculate_torque(desired_rotation);
-        self.motors.front_left.speed = constrain(
-            length(
-                desiered_torque
-                    - self.motors.front_left.pos * self.motors.front_left.pos.dot(&desiered_torque),
-            ) + transmitter_state.up_down * 0.5
-                + transmitter_state.rotate_pos_neg * 0.25,
-        );
-        self.motors.front_right.speed = constrain(
-            length(
-                desiered_torque
-                    - self.motors.front_right.pos
-                        * self.motors.front_right.pos.dot(&desiered_torque),
-            ) + transmitter_state.up_down * 0.5
-                - transmitter_state.rotate_pos_neg * 0.25,
-        );
-        self.motors.rear_left.speed = constrain(
-            length(
-                desiered_torque
-                    - self.motors.rear_left.pos * self.motors.rear_left.pos.dot(&desiered_torque),
-            ) + transmitter_state.up_down * 0.5
-                - transmitter_state.rotate_pos_neg * 0.25,
-        );
-        self.motors.rear_right.speed = constrain(
-            length(
-                desiered_torque
-                    - self.motors.rear_right.pos * self.motors.rear_right.pos.dot(&desiered_torque),
-            ) + transmitter_state.up_down * 0.5
-                + transmitter_state.rotate_pos_neg * 0.25,
+    pub fn disarm(&mut self) {
+        if self.arm_state != ArmState::Disarmed {
+            trace!("disarmed");
+        }
+        self.arm_state = ArmState::Disarmed;
+        self.gesture_time = T::zero();
+    }
+
+    pub fn arm_state(&self) -> ArmState {
+        self.arm_state
+    }
+
+    /// Emergency motor cut: every output goes to zero from the next loop on
+    /// and stays there, whatever the sticks or the arming gestures do, until
+    /// `clear_kill`. Disarms too.
+    pub fn kill(&mut self) {
+        if !self.killed {
+            trace!("killed");
+        }
+        self.killed = true;
+        self.disarm();
+    }
+
+    /// Lifts a `kill`. The controller stays disarmed until armed again.
+    pub fn clear_kill(&mut self) {
+        self.killed = false;
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed
+    }
+
+    /// Lowest output a working motor gets while armed, so the props keep
+    /// spinning at zero throttle. Zero by default.
+    pub fn set_idle(&mut self, idle: T) {
+        self.idle = constrain(idle);
+    }
+
+    /// Seconds over which the motor outputs' ceiling rises from zero to full
+    /// after arming, so the motors spin up gently. Zero, the default, turns
+    /// the ramp off.
+    pub fn set_arm_ramp(&mut self, seconds: T) {
+        self.arm_ramp = seconds.abs();
+    }
+
+    /// Records that a transmitter frame arrived at `time_point`, on the same
+    /// clock as the IMU samples. The failsafe only watches the link once the
+    /// first frame has been fed.
+    pub fn feed_transmitter(&mut self, transmitter_state: TransmitterState<T>, time_point: T) {
+        self.last_frame = Some((transmitter_state, time_point));
+    }
+
+    /// Seconds without a transmitter frame before the failsafe takes over.
+    /// With a GPS fix and a home position an armed drone returns home, and
+    /// goes back to the pilot's mode once frames arrive again; otherwise the
+    /// throttle ramps down and the drone disarms. Zero disables the failsafe.
+    pub fn set_failsafe_timeout(&mut self, timeout: T) {
+        self.failsafe_timeout = timeout;
+    }
+
+    /// Sticks to fly on in place of a stale link: level attitude, no yaw and
+    /// the last throttle ramping down to zero.
+    fn failsafe_sticks(&self, time_point: T) -> Option<TransmitterState<T>> {
+        if self.failsafe_timeout <= T::zero() {
+            return None;
+        }
+        let (last, received) = self.last_frame?;
+        let stale = time_point - received - self.failsafe_timeout;
+        if stale <= T::zero() {
+            return None;
+        }
+        let remaining = max(T::one() - stale / cast(FAILSAFE_DESCENT_TIME), T::zero());
+        let center = cast(0.5);
+        Some(TransmitterState {
+            up_down: last.up_down * remaining,
+            rotate_pos_neg: center,
+            forward_backward: center,
+            left_right: center,
+        })
+    }
+
+    /// With throttle at idle, holding yaw fully right arms and holding it fully
+    /// left disarms, once held for `ARM_HOLD_TIME`.
+    fn update_arm_gesture(&mut self, transmitter_state: &TransmitterState<T>) {
+        let yaw = stick_axis(transmitter_state.rotate_pos_neg);
+        let target = if transmitter_state.up_down > cast(ARM_THROTTLE) {
+            None
+        } else if yaw <= -cast::<T>(ARM_YAW) {
+            Some(ArmState::Armed)
+        } else if yaw >= cast(ARM_YAW) {
+            Some(ArmState::Disarmed)
+        } else {
+            None
+        };
+        match target {
+            Some(state) if state != self.arm_state => {
+                self.gesture_time += self.dt;
+                if self.gesture_time >= cast(ARM_HOLD_TIME) {
+                    match state {
+                        ArmState::Armed => self.arm(),
+                        ArmState::Disarmed => self.disarm(),
+                    }
+                }
+            }
+            _ => self.gesture_time = T::zero(),
+        }
+    }
+
+    /// Averages the gyro over samples taken while the drone sits still and
+    /// subtracts the result from every later sample.
+    pub fn calibrate_gyro(&mut self, samples: &[IMUDataPoint<T>]) -> Result<(), CalibrationError> {
+        if samples.is_empty() {
+            return Err(CalibrationError::NoSamples);
+        }
+        let count: T = cast(samples.len() as f64);
+        let mut gyro_sum = Vector3::zeros();
+        let mut accel_sum = T::zero();
+        let mut accel_square_sum = T::zero();
+        for sample in samples {
+            gyro_sum += sample.gyro;
+            let accel = sample.accel.norm();
+            accel_sum += accel;
+            accel_square_sum += accel * accel;
+        }
+        let accel_mean = accel_sum / count;
+        if accel_square_sum / count - accel_mean * accel_mean > cast(CALIBRATION_ACCEL_VARIANCE) {
+            return Err(CalibrationError::NotStationary);
+        }
+        self.gyro_bias = gyro_sum / count;
+        Ok(())
+    }
+
+    pub fn gyro_bias(&self) -> Vector3<T> {
+        self.gyro_bias
+    }
+
+    pub fn reset_gyro_bias(&mut self) {
+        self.gyro_bias = Vector3::zeros();
+    }
+
+    /// Cutoff of the low-pass filter applied to incoming gyro samples. Zero
+    /// disables the filter. Changes the active rate profile.
+    pub fn set_gyro_lpf_hz(&mut self, cutoff_hz: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| profile.gyro_lpf_hz = cutoff_hz)
+    }
+
+    /// Notch filter on incoming gyro samples, for a frame resonance at a
+    /// known frequency. `q` sets how narrow the cut is. Zero `hz` disables it.
+    pub fn set_gyro_notch(&mut self, hz: T, q: T) {
+        self.gyro_notch.set(hz, q);
+        self.gyro_notch_hz = hz;
+    }
+
+    /// Lets motor rpm telemetry steer the gyro notch onto the motors' mean
+    /// rotation frequency. Until rpm arrives, or while the motors are
+    /// stopped, the notch stays where `set_gyro_notch` put it.
+    pub fn set_rpm_notch(&mut self, enabled: bool) {
+        self.rpm_notch = enabled;
+    }
+
+    /// Latest rotation speed of each motor in rpm, from ESC telemetry.
+    pub fn feed_motor_rpm(&mut self, rpm: [T; N]) {
+        let mean = rpm.iter().fold(T::zero(), |sum, &rpm| sum + rpm) / cast(N as f64);
+        self.motor_hz = (mean > T::zero()).then(|| mean / cast(60.0));
+    }
+
+    /// The mixer works in thrust; motor commands are derived through the
+    /// inverse of this curve.
+    pub fn set_thrust_curve(&mut self, thrust_curve: ThrustCurve) {
+        self.thrust_curve = thrust_curve;
+    }
+
+    /// Divides the throttle by the cosine of the estimated tilt, so leaning
+    /// over doesn't cost height. Beyond `MAX_COMPENSATED_TILT` the boost stops
+    /// growing.
+    pub fn set_tilt_compensation(&mut self, enabled: bool) {
+        self.tilt_compensation = enabled;
+    }
+
+    fn tilt_compensated(&self, throttle: T) -> T {
+        if !self.tilt_compensation {
+            return throttle;
+        }
+        let (roll, pitch) = self.attitude();
+        let cos_tilt = max(
+            math::cos(roll) * math::cos(pitch),
+            cast::<T>(MAX_COMPENSATED_TILT).cos(),
         );
-        &self.motors
+        min(throttle / cos_tilt, T::one())
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Throttle PID attenuation: above `breakpoint` throttle the P and D terms
+    /// are scaled down linearly, reaching `1 - factor` at full throttle. The
+    /// breakpoint has to be in [0, 1) and the factor in [0, 1], so the terms
+    /// never change sign.
+    pub fn set_tpa(&mut self, breakpoint: T, factor: T) -> Result<(), ConfigError> {
+        let breakpoint_ok = breakpoint >= T::zero() && breakpoint < T::one();
+        if !(breakpoint_ok && factor >= T::zero() && factor <= T::one()) {
+            return Err(ConfigError::Tpa);
+        }
+        self.tpa_breakpoint = breakpoint;
+        self.tpa_factor = factor;
+        Ok(())
+    }
 
-    #[test]
-    fn it_works() {
-        assert_eq!(4, 4);
+    fn tpa_scale(&self, throttle: T) -> T {
+        if throttle <= self.tpa_breakpoint {
+            return T::one();
+        }
+        let over = (throttle - self.tpa_breakpoint) / (T::one() - self.tpa_breakpoint);
+        T::one() - self.tpa_factor * over
+    }
+
+    /// Compensate motor commands for a sagging pack. Without battery telemetry
+    /// the motors are assumed to always deliver full-charge thrust. A reading
+    /// with no cells or a voltage that isn't positive is rejected, and the
+    /// last good one stays in use.
+    pub fn set_battery(&mut self, battery: BatteryState<T>) -> Result<(), ConfigError> {
+        if battery.cells == 0 || !(battery.voltage > T::zero() && battery.voltage.is_finite()) {
+            return Err(ConfigError::Battery);
+        }
+        self.battery = Some(battery);
+        Ok(())
+    }
+
+    pub fn clear_battery(&mut self) {
+        self.battery = None;
+    }
+
+    pub fn set_saturation(&mut self, saturation: Saturation) {
+        self.saturation = saturation;
+    }
+
+    pub fn set_mixer(&mut self, mixer: MotorMixer<T, N>) {
+        self.mixer = mixer;
+    }
+
+    /// Marks motor `i` as working or dead. With any motor out the command is
+    /// spread over the rest, bypassing the saturation mode. On a quad that
+    /// leaves only enough authority for thrust, roll and pitch, so yaw is
+    /// given up and the drone spins while it stays upright; frames with more
+    /// motors can keep yaw too.
+    pub fn set_motor_enabled(&mut self, i: usize, enabled: bool) -> Result<(), ConfigError> {
+        *self.motor_enabled.get_mut(i).ok_or(ConfigError::Motor(i))? = enabled;
+        Ok(())
+    }
+
+    /// Whether motor `i` is marked as working. `None` if there's no such
+    /// motor.
+    pub fn motor_enabled(&self, i: usize) -> Option<bool> {
+        self.motor_enabled.get(i).copied()
+    }
+
+    /// Switching modes drops the position hold target, so set it afterwards.
+    /// Switches into or out of `FlightMode::Turtle` are ignored while armed,
+    /// so the motors never reverse in flight.
+    pub fn set_mode(&mut self, mode: FlightMode) {
+        let turtle = mode == FlightMode::Turtle || self.mode == FlightMode::Turtle;
+        if turtle && mode != self.mode && self.arm_state == ArmState::Armed {
+            trace!("can't switch turtle mode while armed");
+            return;
+        }
+        if mode != self.mode {
+            self.target_position = None;
+            self.return_phase = ReturnPhase::Climb;
+        }
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> FlightMode {
+        self.mode
+    }
+
+    /// Whether the motor outputs are meant to spin the props backwards, as
+    /// they are while armed in `FlightMode::Turtle`. The ESCs have to be told,
+    /// e.g. with `DSHOT_CMD_SPIN_DIRECTION_REVERSED`.
+    pub fn motors_reversed(&self) -> bool {
+        self.mode == FlightMode::Turtle && self.arm_state == ArmState::Armed
+    }
+
+    /// Stick deflection, as a fraction of full, at which horizon mode stops
+    /// self-leveling and flies purely on rates. Leveling fades out linearly
+    /// from center up to it. Defaults to 0.75.
+    pub fn set_horizon_transition(&mut self, deflection: T) {
+        self.horizon_transition = constrain(deflection);
+    }
+
+    /// Altitude in meters above the point where the drone was armed that
+    /// altitude hold climbs or descends to.
+    pub fn set_target_altitude(&mut self, altitude: T) {
+        self.target_altitude = altitude;
+    }
+
+    pub fn target_altitude(&self) -> T {
+        self.target_altitude
+    }
+
+    /// Corrects the heading estimate with a magnetometer reading. Without any
+    /// the heading is integrated from the gyro alone and drifts.
+    pub fn feed_mag(&mut self, sample: MagSample<T>) {
+        let dt = match self.last_mag_time.replace(sample.time_point) {
+            Some(last) => min(max(sample.time_point - last, T::zero()), cast(MAX_DT)),
+            None => T::zero(),
+        };
+        self.heading
+            .correct(&self.madgwick.orientation(), sample.field, dt);
+    }
+
+    /// Heading in radians, growing counterclockwise seen from above and zero
+    /// facing magnetic north. Without magnetometer samples it starts from zero
+    /// wherever the drone was facing.
+    pub fn heading(&self) -> T {
+        self.heading.heading()
+    }
+
+    /// With heading hold on, a centered yaw stick holds the heading it was
+    /// released at instead of commanding zero yaw rate.
+    pub fn set_heading_hold(&mut self, heading_hold: bool) {
+        self.heading_hold = heading_hold;
+        self.held_heading = None;
+    }
+
+    fn yaw_rate(&mut self, stick: T, max_rate: T) -> T {
+        if !self.heading_hold || stick != T::zero() {
+            self.held_heading = None;
+            return max_rate * stick;
+        }
+        let heading = self.heading.heading();
+        let target = *self.held_heading.get_or_insert(heading);
+        (self.angle_gain * wrap_angle(target - heading)).clamp(-max_rate, max_rate)
+    }
+
+    /// Fuses a GPS fix into the position estimate.
+    pub fn feed_gps(&mut self, sample: GpsSample<T>) {
+        let dt = self
+            .last_gps_time
+            .replace(sample.time_point)
+            .map(|last| max(sample.time_point - last, T::zero()));
+        self.position.correct(sample.position, dt);
+        if self.home.is_none() && self.arm_state == ArmState::Armed {
+            self.home = Some(self.position.position());
+        }
+    }
+
+    /// Seconds over which GPS fixes override the drift of the inertial
+    /// position estimate.
+    pub fn set_gps_time_constant(&mut self, time_constant: T) {
+        self.position.set_time_constant(time_constant);
+    }
+
+    /// Estimated position in the navigation frame of the GPS fixes.
+    pub fn position(&self) -> Vector3<T> {
+        self.position.position()
+    }
+
+    /// Estimated velocity in m/s in the navigation frame.
+    pub fn velocity(&self) -> Vector3<T> {
+        self.position.velocity()
+    }
+
+    /// Point position hold flies to. Only its horizontal part is used; height
+    /// is left to altitude hold. Without one the position where position
+    /// hold first engages is held.
+    pub fn set_target_position(&mut self, position: Vector3<T>) {
+        self.target_position = Some(position);
+    }
+
+    pub fn target_position(&self) -> Option<Vector3<T>> {
+        self.target_position
+    }
+
+    /// Queues a waypoint for position hold to fly to once it has passed the
+    /// ones before it. With the queue empty, position hold keeps the drone
+    /// where it is.
+    pub fn push_waypoint(&mut self, waypoint: Waypoint<T>) -> Result<(), MissionError> {
+        self.mission.push(waypoint).map_err(|_| MissionError::Full)
+    }
+
+    /// Drops all waypoints and holds the current position instead.
+    pub fn clear_waypoints(&mut self) {
+        self.mission.clear();
+        self.target_position = None;
+    }
+
+    /// The waypoint currently being flown to.
+    pub fn current_waypoint(&self) -> Option<&Waypoint<T>> {
+        self.mission.current()
+    }
+
+    pub fn waypoints_left(&self) -> usize {
+        self.mission.len()
+    }
+
+    /// Whether the last queued waypoint has been reached.
+    pub fn mission_complete(&self) -> bool {
+        self.mission.complete()
+    }
+
+    /// Position return to home flies back to. Without one, the first GPS fix
+    /// after arming is used.
+    pub fn set_home(&mut self, position: Vector3<T>) {
+        self.home = Some(position);
+    }
+
+    pub fn home(&self) -> Option<Vector3<T>> {
+        self.home
+    }
+
+    /// Altitude above the arming point that return to home climbs to before
+    /// heading home. It never descends to reach it.
+    pub fn set_return_altitude(&mut self, altitude: T) {
+        self.return_altitude = altitude;
+    }
+
+    /// How far return to home has got. Only meaningful in that mode.
+    pub fn return_phase(&self) -> ReturnPhase {
+        self.return_phase
+    }
+
+    /// Steps the return to home state machine, moving the position and
+    /// altitude targets for the hold loops to follow. Without GPS or a home
+    /// position the drone lands where it is.
+    fn update_return_to_home(&mut self) {
+        let dt = self.dt;
+        let position = self.position.position();
+        let altitude = self.altitude.altitude();
+        let home = self.home.filter(|_| self.last_gps_time.is_some());
+        match self.return_phase {
+            ReturnPhase::Climb => {
+                self.target_altitude = max(self.target_altitude, self.return_altitude);
+                if altitude >= self.return_altitude - cast(RETURN_ALTITUDE_TOLERANCE) {
+                    self.return_phase = ReturnPhase::Transit;
+                }
+            }
+            ReturnPhase::Transit => match home {
+                Some(home) => {
+                    let target = self.target_position.get_or_insert(position);
+                    approach(target, home, cast::<T>(MAX_HORIZONTAL_SPEED) * dt);
+                    if arrived(position, home) {
+                        self.return_phase = ReturnPhase::Descend;
+                    }
+                }
+                None => self.return_phase = ReturnPhase::Descend,
+            },
+            ReturnPhase::Descend => {
+                self.target_altitude -= cast::<T>(LAND_RATE) * dt;
+                if self.target_altitude < altitude - cast(LANDED_MARGIN) {
+                    self.return_phase = ReturnPhase::Landed;
+                    self.disarm();
+                }
+            }
+            ReturnPhase::Landed => {}
+        }
+    }
+
+    /// Gains of the position hold cascade: m/s of desired speed per meter of
+    /// position error, and m/s² of desired acceleration per m/s of speed
+    /// error.
+    pub fn set_position_gains(&mut self, position: T, velocity: T) {
+        self.position_gain = position;
+        self.velocity_gain = velocity;
+    }
+
+    /// Body-to-navigation rotation: the Madgwick tilt turned to the heading
+    /// estimate.
+    fn nav_orientation(&self) -> UnitQuaternion<T> {
+        let orientation = self.madgwick.orientation();
+        let correction = wrap_angle(self.heading.heading() - yaw_of(&orientation));
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), correction) * orientation
+    }
+
+    /// Level unit vectors along the estimated heading and to its right.
+    fn heading_basis(&self) -> (Vector3<T>, Vector3<T>) {
+        let heading = self.heading.heading();
+        (
+            Vector3::new(heading.cos(), T::zero(), -heading.sin()),
+            Vector3::new(heading.sin(), T::zero(), heading.cos()),
+        )
+    }
+
+    /// Roll and pitch that accelerate the drone towards the position target,
+    /// which the sticks move at up to `MAX_HORIZONTAL_SPEED`.
+    fn position_hold_tilt(&mut self, roll_stick: T, pitch_stick: T) -> (T, T) {
+        let (forward, right) = self.heading_basis();
+        let max_speed: T = cast(MAX_HORIZONTAL_SPEED);
+        let position = self.position.position();
+        let target = self.target_position.get_or_insert(position);
+        // Positive pitch is nose up, which flies backwards.
+        *target += (forward * -pitch_stick + right * roll_stick) * (max_speed * self.dt);
+        self.mission.step(position, target, max_speed, self.dt);
+        let target = *target;
+        self.tilt_towards(target, forward, right)
+    }
+
+    /// Roll and pitch that accelerate the drone towards `target`, with
+    /// `forward` and `right` from `heading_basis`.
+    fn tilt_towards(&self, target: Vector3<T>, forward: Vector3<T>, right: Vector3<T>) -> (T, T) {
+        let mut error = target - self.position.position();
+        error.y = T::zero();
+        let desired_velocity =
+            (error * self.position_gain).cap_magnitude(cast(MAX_HORIZONTAL_SPEED));
+        let mut velocity = self.position.velocity();
+        velocity.y = T::zero();
+        let acceleration = (desired_velocity - velocity) * self.velocity_gain;
+        let gravity: T = cast(GRAVITY);
+        (
+            acceleration.dot(&right).atan2(gravity),
+            -acceleration.dot(&forward).atan2(gravity),
+        )
+    }
+
+    /// Fuses a barometer reading into the altitude estimate. While disarmed the
+    /// readings only track the ground level pressure.
+    pub fn feed_baro(&mut self, sample: BaroSample<T>) {
+        let last = self.last_baro.replace(sample);
+        let reference = match self.baro_reference {
+            Some(reference) if self.arm_state == ArmState::Armed => reference,
+            _ => {
+                self.baro_reference = Some(sample.pressure);
+                return;
+            }
+        };
+        if let Some(last) = last {
+            let dt = min(
+                max(sample.time_point - last.time_point, T::zero()),
+                cast(MAX_DT),
+            );
+            self.altitude
+                .correct(pressure_altitude(sample.pressure, reference), dt);
+        }
+    }
+
+    /// Seconds over which barometer readings override the drift of the
+    /// inertial altitude estimate.
+    pub fn set_baro_time_constant(&mut self, time_constant: T) {
+        self.altitude.set_time_constant(time_constant);
+    }
+
+    /// Estimated altitude in meters above the point where the drone was armed.
+    pub fn altitude(&self) -> T {
+        self.altitude.altitude()
+    }
+
+    /// Estimated vertical speed in m/s, positive up.
+    pub fn vertical_velocity(&self) -> T {
+        self.altitude.velocity()
+    }
+
+    /// Gains of the altitude hold loop, from meters of altitude error to
+    /// collective throttle.
+    pub fn set_altitude_gains(&mut self, gains: PidGains<T>) {
+        self.altitude_pid = Pid::new(gains);
+    }
+
+    /// Collective throttle that roughly balances the drone's weight. Altitude
+    /// hold adds its correction on top of it.
+    pub fn set_hover_throttle(&mut self, throttle: T) {
+        self.hover_throttle_setting = throttle;
+        self.hover_throttle = throttle;
+    }
+
+    /// Hover throttle altitude hold currently builds on, learned by the
+    /// auto-trim if it's on.
+    pub fn hover_throttle(&self) -> T {
+        self.hover_throttle
+    }
+
+    /// While holding altitude with the climb stick centered and the drone
+    /// neither climbing nor sinking, slowly moves the hover throttle to the
+    /// throttle that's actually holding it up. Takes the steady work off the
+    /// altitude PID as the battery sags or the payload changes.
+    pub fn set_hover_auto_trim(&mut self, enabled: bool) {
+        self.hover_trim = enabled;
+    }
+
+    /// Forgets the learned hover throttle and goes back to the one set.
+    pub fn reset_hover_throttle(&mut self) {
+        self.hover_throttle = self.hover_throttle_setting;
+    }
+
+    fn altitude_hold_throttle(&mut self, stick: T) -> T {
+        let dt = self.dt;
+        let climb = apply_deadzone(stick_axis(stick), self.deadzone);
+        self.target_altitude += climb * cast(MAX_CLIMB_RATE) * dt;
+        let error = self.target_altitude - self.altitude.altitude();
+        let throttle = self.hover_throttle
+            + self.altitude_pid.update(
+                error,
+                self.altitude.velocity() * dt,
+                dt,
+                self.altitude_saturated,
+                T::one(),
+                T::one(),
+            );
+        let (throttle, saturated) = constrain_reporting(throttle);
+        self.altitude_saturated = saturated;
+        let hovering = self.altitude.velocity().abs() < cast(HOVER_TRIM_MAX_SPEED);
+        if self.hover_trim && !saturated && hovering && climb == T::zero() {
+            let alpha = dt / (dt + cast(HOVER_TRIM_TIME_CONSTANT));
+            self.hover_throttle += (throttle - self.hover_throttle) * alpha;
+        }
+        throttle
+    }
+
+    /// Largest tilt in radians that full stick commands in angle mode.
+    pub fn set_max_angle(&mut self, max_angle: T) {
+        self.max_angle = max_angle.abs();
+    }
+
+    pub fn max_angle(&self) -> T {
+        self.max_angle
+    }
+
+    /// Rotation rates in rad/s at full stick in rate mode, per axis. Yaw
+    /// usually wants more than roll and pitch, and its rate applies in every
+    /// mode. Changes the active rate profile.
+    pub fn set_max_rates(&mut self, roll: T, pitch: T, yaw: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| {
+            profile.roll_rate = roll;
+            profile.pitch_rate = pitch;
+            profile.yaw_rate = yaw;
+        })
+    }
+
+    pub fn set_max_roll_rate(&mut self, rate: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| profile.roll_rate = rate)
+    }
+
+    pub fn set_max_pitch_rate(&mut self, rate: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| profile.pitch_rate = rate)
+    }
+
+    pub fn set_max_yaw_rate(&mut self, rate: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| profile.yaw_rate = rate)
+    }
+
+    pub fn max_roll_rate(&self) -> T {
+        self.active_profile().roll_rate
+    }
+
+    pub fn max_pitch_rate(&self) -> T {
+        self.active_profile().pitch_rate
+    }
+
+    pub fn max_yaw_rate(&self) -> T {
+        self.active_profile().yaw_rate
+    }
+
+    /// Expo factor for each stick axis, from 0 (linear) to 1 (cubic).
+    /// Changes the active rate profile.
+    pub fn set_expo(&mut self, roll: T, pitch: T, yaw: T) -> Result<(), ConfigError> {
+        self.edit_active_profile(|profile| {
+            profile.roll_expo = roll;
+            profile.pitch_expo = pitch;
+            profile.yaw_expo = yaw;
+        })
+    }
+
+    /// Fraction of stick deflection around center on the roll, pitch and yaw
+    /// axes that is read as centered.
+    pub fn set_deadzone(&mut self, deadzone: T) {
+        self.deadzone = deadzone;
+    }
+
+    fn stick(&self, val: T, expo: T) -> T {
+        apply_expo(apply_deadzone(stick_axis(val), self.deadzone), expo)
+    }
+
+    /// Rate in rad/s commanded per radian of attitude error in angle mode, and
+    /// of heading error in heading hold.
+    pub fn set_angle_gain(&mut self, angle_gain: T) {
+        self.angle_gain = angle_gain;
+    }
+
+    /// Largest change of the rate setpoint on any axis, in rad/s². Zero
+    /// disables the limit.
+    pub fn set_setpoint_slew(&mut self, slew: T) {
+        self.setpoint_slew = slew;
+    }
+
+    /// Largest change of the collective throttle fed into the mixer, as a
+    /// fraction of full per second, so slamming the stick doesn't kick the
+    /// drone. Attitude corrections aren't limited. Zero disables the limit.
+    pub fn set_throttle_slew(&mut self, per_sec: T) {
+        self.throttle_slew = per_sec;
+    }
+
+    /// Adds `gain * d(setpoint)/dt` to each axis' torque, so the motors react
+    /// to stick movement before an error builds up. Zero, the default, turns
+    /// it off.
+    pub fn set_feedforward(&mut self, roll: T, pitch: T, yaw: T) {
+        self.feedforward = Vector3::new(roll, yaw, pitch);
+    }
+
+    /// Cutoff of the low-pass filter on the setpoint's rate of change, which
+    /// keeps stepped stick inputs from kicking the motors.
+    pub fn set_feedforward_lpf_hz(&mut self, cutoff_hz: T) {
+        self.feedforward_lpf.set_cutoff_hz(cutoff_hz);
+    }
+
+    /// Holds back the integral while the setpoint moves faster than the
+    /// sticks' low-passed position at `cutoff_hz` can follow, so a quick flick
+    /// doesn't wind up I and bounce back at the end. Zero disables it.
+    pub fn set_iterm_relax(&mut self, cutoff_hz: T) {
+        self.iterm_relax_lpf.set_cutoff_hz(cutoff_hz);
+    }
+
+    /// Rotation rate the inner PID loop was last asked to hold.
+    pub fn rate_setpoint(&self) -> Vector3<T> {
+        self.rate_setpoint
+    }
+
+    /// The last loop's setpoint, filtered gyro, PID terms and motor outputs,
+    /// for logging with `BlackboxEncoder`.
+    pub fn blackbox_frame(&self) -> BlackboxFrame<T, N> {
+        let latest = self.imu.get_data_point();
+        let terms = self.last_pid_terms();
+        BlackboxFrame {
+            time: latest.time_point,
+            setpoint: self.rate_setpoint,
+            gyro: latest.gyro,
+            p: terms.p,
+            i: terms.i,
+            d: terms.d,
+            motors: core::array::from_fn(|i| self.motors.get(i)),
+        }
+    }
+
+    /// P, I, D and feed-forward contributions to the torque of the last
+    /// `calculate_motor_speeds` call, before mixing. All zero while disarmed.
+    pub fn last_pid_terms(&self) -> PidTerms<T> {
+        let term = |i: usize| Vector3::from_fn(|axis, _| self.pids[axis].terms()[i]);
+        PidTerms {
+            p: term(0),
+            i: term(1),
+            d: term(2),
+            ff: self.feedforward_term,
+        }
+    }
+
+    pub fn set_attitude_time_constant(&mut self, time_constant: T) {
+        self.attitude.set_time_constant(time_constant);
+    }
+
+    /// Estimated (roll, pitch) in radians.
+    pub fn attitude(&self) -> (T, T) {
+        (self.attitude.roll(), self.attitude.pitch())
+    }
+
+    pub fn set_madgwick_beta(&mut self, beta: T) {
+        self.madgwick.set_beta(beta);
+    }
+
+    /// Standard deviation of the accelerometer magnitude, in m/s², over the
+    /// recent IMU history. A clean build sits close to zero.
+    pub fn accel_vibration(&self) -> T {
+        self.imu.accel_std_dev()
+    }
+
+    /// Body-to-world rotation estimated by the Madgwick filter.
+    pub fn orientation(&self) -> UnitQuaternion<T> {
+        self.madgwick.orientation()
+    }
+
+    /// Replaces the rate loop gains, keeping the integrators, so they can be
+    /// retuned in flight.
+    pub fn set_gains(&mut self, roll: PidGains<T>, pitch: PidGains<T>, yaw: PidGains<T>) {
+        self.pids[0].set_gains(roll);
+        self.pids[1].set_gains(yaw);
+        self.pids[2].set_gains(pitch);
+    }
+
+    pub fn set_i_limit(&mut self, i_limit: T) {
+        for pid in &mut self.pids {
+            pid.set_i_limit(i_limit);
+        }
+    }
+
+    /// Whether the last step asked the motors for more than they could give,
+    /// so some motor was clipped or air mode had to scale the attitude
+    /// command down.
+    pub fn is_saturated(&self) -> bool {
+        self.saturated
+    }
+
+    pub fn integral(&self) -> Vector3<T> {
+        Vector3::new(
+            self.pids[0].integral(),
+            self.pids[1].integral(),
+            self.pids[2].integral(),
+        )
+    }
+
+    /// Forgets everything learned from past samples: integrals, attitude
+    /// estimates, filter state and the IMU history. Gains, limits, the gyro
+    /// calibration and the arm state are kept.
+    pub fn reset(&mut self) {
+        self.motors = MotorSpeeds::new();
+        self.reset_hover_throttle();
+        self.imu = IMUData::new();
+        for pid in &mut self.pids {
+            pid.reset();
+        }
+        self.feedforward_term = Vector3::zeros();
+        self.saturated = false;
+        self.attitude.reset();
+        self.madgwick.reset();
+        self.heading.reset();
+        self.last_mag_time = None;
+        self.held_heading = None;
+        self.altitude.reset();
+        self.altitude_pid.reset();
+        self.altitude_saturated = false;
+        self.position.reset();
+        self.last_gps_time = None;
+        self.target_position = None;
+        self.mission.clear();
+        self.return_phase = ReturnPhase::Climb;
+        self.baro_reference = None;
+        self.last_baro = None;
+        self.rate_setpoint = Vector3::zeros();
+        self.last_setpoint = None;
+        self.feedforward_lpf.reset();
+        self.iterm_relax_lpf.reset();
+        self.gyro_lpf.reset();
+        self.gyro_notch.reset();
+        self.motor_hz = None;
+        self.gesture_time = T::zero();
+        self.dt = T::zero();
+        self.last_frame = None;
+        self.in_failsafe = false;
+        self.failsafe_mode = None;
+    }
+
+    /// Rotation rates that tilt the drone towards the mode's roll and pitch
+    /// targets.
+    fn angle_rotation(&mut self, roll_stick: T, pitch_stick: T, yaw_rate: T) -> Vector3<T> {
+        let (roll_target, pitch_target) = match self.mode {
+            FlightMode::PositionHold if self.last_gps_time.is_some() => {
+                self.position_hold_tilt(roll_stick, pitch_stick)
+            }
+            FlightMode::ReturnToHome if self.last_gps_time.is_some() => {
+                let position = self.position.position();
+                let target = *self.target_position.get_or_insert(position);
+                let (forward, right) = self.heading_basis();
+                self.tilt_towards(target, forward, right)
+            }
+            FlightMode::ReturnToHome => (T::zero(), T::zero()),
+            _ => (roll_stick * self.max_angle, pitch_stick * self.max_angle),
+        };
+        let (roll, pitch) = self.attitude();
+        // The error is taken against the clamped target, so at full stick the
+        // drone settles on the limit instead of leaning on.
+        let clamp = |angle: T| angle.clamp(-self.max_angle, self.max_angle);
+        Vector3::new(
+            self.angle_gain * (clamp(roll_target) - roll),
+            yaw_rate,
+            self.angle_gain * (clamp(pitch_target) - pitch),
+        )
+    }
+
+    /// Turtle mode outputs, for reversed motors. Each motor gets the share of
+    /// `throttle` its reversed thrust contributes to rolling the drone upright
+    /// along the shortest way, read off the mixer's roll and pitch columns.
+    fn turtle(&mut self, throttle: T) {
+        self.saturated = false;
+        self.rate_setpoint = Vector3::zeros();
+        let up = self.madgwick.orientation().inverse() * Vector3::y();
+        if up.y >= cast::<T>(TURTLE_UPRIGHT_TILT).cos() {
+            self.motors = MotorSpeeds::new();
+            return;
+        }
+        // Turning about this axis brings the body's y axis onto up. Flat on
+        // its back any axis does, so roll.
+        let axis = Vector3::y()
+            .cross(&up)
+            .try_normalize(cast(1e-3))
+            .unwrap_or_else(Vector3::x);
+        // Reversed, a motor pushes the other way round from what the mixer
+        // expects of it.
+        let rows = self.mixer.rows();
+        let lift = core::array::from_fn::<T, N, _>(|i| {
+            if self.motor_enabled[i] {
+                -(rows[i][1] * axis.x + rows[i][2] * axis.z)
+            } else {
+                T::zero()
+            }
+        });
+        let most = lift.iter().fold(T::zero(), |most, &lift| max(most, lift));
+        for (motor, lift) in self.motors.motors.iter_mut().zip(lift) {
+            motor.speed = if most > T::zero() {
+                constrain(max(lift, T::zero()) / most * throttle)
+            } else {
+                T::zero()
+            };
+        }
+    }
+
+    fn calculate_torque(&mut self, desired_rotation: Vector3<T>, throttle: T) -> Vector3<T> {
+        let dt = self.dt;
+        let pd_scale = self.tpa_scale(throttle);
+        let gyro = self.imu.get_data_point().gyro;
+        let last_gyro = self
+            .imu
+            .get_previous(1)
+            .map_or(gyro, |previous| previous.gyro);
+        let error = desired_rotation - gyro;
+        let gyro_delta = gyro - last_gyro;
+        let setpoint_rate = match self.last_setpoint.replace(desired_rotation) {
+            Some(last) if dt > T::zero() => (desired_rotation - last) / dt,
+            _ => Vector3::zeros(),
+        };
+        let setpoint_rate = self.feedforward_lpf.update(setpoint_rate, dt);
+        self.feedforward_term = self.feedforward.component_mul(&setpoint_rate);
+        let mut torque = self.feedforward_term;
+        let setpoint_high_pass =
+            desired_rotation - self.iterm_relax_lpf.update(desired_rotation, dt);
+        let relax = setpoint_high_pass
+            .map(|hp| max(T::one() - hp.abs() / cast(ITERM_RELAX_THRESHOLD), T::zero()));
+        for (i, pid) in self.pids.iter_mut().enumerate() {
+            torque[i] += pid.update(
+                error[i],
+                gyro_delta[i],
+                dt,
+                self.saturated,
+                pd_scale,
+                relax[i],
+            );
+        }
+        torque
+    }
+
+    /// Polls `imu` and runs one control step if it has a new sample. Passes
+    /// on `WouldBlock` and driver errors without touching the controller.
+    pub fn step<I: ImuSource<T>>(
+        &mut self,
+        imu: &mut I,
+        transmitter_state: &TransmitterState<T>,
+    ) -> nb::Result<&MotorSpeeds<T, N>, I::Error> {
+        let imu_data_point = imu.read()?;
+        Ok(self.calculate_motor_speeds(imu_data_point, transmitter_state))
+    }
+
+    pub fn calculate_motor_speeds(
+        &mut self,
+        mut imu_data_point: IMUDataPoint<T>,
+        transmitter_state: &TransmitterState<T>,
+    ) -> &MotorSpeeds<T, N> {
+        let failsafe = self.failsafe_sticks(imu_data_point.time_point);
+        if failsafe.is_some() != self.in_failsafe {
+            self.in_failsafe = failsafe.is_some();
+            trace!("failsafe: {}", self.in_failsafe);
+        }
+        let transmitter_state = failsafe.as_ref().unwrap_or(transmitter_state);
+        self.dt = match self.imu.get_previous(0) {
+            Some(latest) => clamp_dt(imu_data_point.time_point - latest.time_point),
+            None => T::zero(),
+        };
+        let dt = self.dt;
+        let notch_hz = match self.motor_hz {
+            Some(motor_hz) if self.rpm_notch => motor_hz,
+            _ => self.gyro_notch_hz,
+        };
+        self.gyro_notch.set_center_hz(notch_hz);
+        let gyro = self
+            .gyro_notch
+            .update(imu_data_point.gyro - self.gyro_bias, dt);
+        imu_data_point.gyro = self.gyro_lpf.update(gyro, dt);
+        self.imu.add_data_point(imu_data_point);
+        let current = self.imu.get_data_point();
+        self.attitude.update(current.gyro, current.accel, dt);
+        self.madgwick.update(current.gyro, current.accel, dt);
+        let (gyro, accel) = (current.gyro, current.accel);
+        self.heading.update(&self.madgwick.orientation(), gyro, dt);
+        self.altitude
+            .update(&self.madgwick.orientation(), accel, dt);
+        self.position.update(&self.nav_orientation(), accel, dt);
+        self.update_arm_gesture(transmitter_state);
+        if failsafe.is_some() && self.home.is_some() && self.last_gps_time.is_some() {
+            // On the ground there's nothing to return from.
+            if self.mode != FlightMode::ReturnToHome && self.arm_state == ArmState::Armed {
+                self.failsafe_mode = Some(self.mode);
+                self.set_mode(FlightMode::ReturnToHome);
+            }
+        } else if failsafe.is_some_and(|sticks| sticks.up_down <= T::zero()) {
+            self.disarm();
+        }
+        if failsafe.is_none() {
+            if let Some(mode) = self.failsafe_mode.take() {
+                // The link is back: the pilot gets their mode back, holding
+                // the altitude the return got to.
+                self.set_mode(mode);
+                self.target_altitude = self.altitude.altitude();
+            }
+        }
+        if self.mode == FlightMode::ReturnToHome && self.arm_state == ArmState::Armed {
+            self.update_return_to_home();
+        }
+        if self.killed || self.arm_state == ArmState::Disarmed {
+            // Keep the estimators running but don't let the integrals wind up
+            // while the drone sits on the ground.
+            for pid in &mut self.pids {
+                pid.reset();
+            }
+            self.feedforward_term = Vector3::zeros();
+            self.saturated = false;
+            self.rate_setpoint = Vector3::zeros();
+            self.collective = T::zero();
+            self.last_setpoint = None;
+            self.feedforward_lpf.reset();
+            self.iterm_relax_lpf.reset();
+            self.held_heading = None;
+            self.target_position = None;
+            // Altitude is measured from wherever the drone gets armed.
+            self.altitude.reset();
+            self.altitude_pid.reset();
+            self.altitude_saturated = false;
+            self.motors = MotorSpeeds::new();
+            return &self.motors;
+        }
+        if self.mode == FlightMode::Turtle {
+            self.turtle(transmitter_state.up_down);
+            return &self.motors;
+        }
+        let profile = *self.active_profile();
+        // Roll turns about the body x axis, which points forward, and pitch
+        // about z, which points right: the mixer's roll column tells left
+        // motors from right ones, its pitch column front from rear.
+        let roll_stick = self.stick(transmitter_state.left_right, profile.roll_expo);
+        let pitch_stick = self.stick(transmitter_state.forward_backward, profile.pitch_expo);
+        let yaw_stick = self.stick(transmitter_state.rotate_pos_neg, profile.yaw_expo);
+        let yaw_rate = self.yaw_rate(yaw_stick, profile.yaw_rate);
+        let rate_rotation = Vector3::new(
+            profile.roll_rate * roll_stick,
+            yaw_rate,
+            profile.pitch_rate * pitch_stick,
+        );
+        let desired_rotation = match self.mode {
+            FlightMode::Rate => rate_rotation,
+            FlightMode::Horizon => {
+                let deflection = max(roll_stick.abs(), pitch_stick.abs());
+                let level = if self.horizon_transition > T::zero() {
+                    max(T::one() - deflection / self.horizon_transition, T::zero())
+                } else {
+                    T::zero()
+                };
+                let angle_rotation = self.angle_rotation(roll_stick, pitch_stick, yaw_rate);
+                angle_rotation * level + rate_rotation * (T::one() - level)
+            }
+            FlightMode::Angle
+            | FlightMode::AltitudeHold
+            | FlightMode::PositionHold
+            | FlightMode::ReturnToHome
+            | FlightMode::Turtle => self.angle_rotation(roll_stick, pitch_stick, yaw_rate),
+        };
+        let desired_rotation = if self.setpoint_slew > T::zero() {
+            let max_step = self.setpoint_slew * dt;
+            let previous = self.rate_setpoint;
+            desired_rotation.zip_map(&previous, |desired, previous| {
+                previous + (desired - previous).clamp(-max_step, max_step)
+            })
+        } else {
+            desired_rotation
+        };
+        self.rate_setpoint = desired_rotation;
+
+        let throttle = match self.mode {
+            FlightMode::AltitudeHold | FlightMode::PositionHold => {
+                self.altitude_hold_throttle(transmitter_state.up_down)
+            }
+            FlightMode::ReturnToHome => self.altitude_hold_throttle(cast(0.5)),
+            FlightMode::Rate | FlightMode::Angle | FlightMode::Horizon | FlightMode::Turtle => {
+                transmitter_state.up_down
+            }
+        };
+        let throttle = self.tilt_compensated(throttle);
+        let throttle = if self.throttle_slew > T::zero() {
+            let max_step = self.throttle_slew * dt;
+            self.collective + (throttle - self.collective).clamp(-max_step, max_step)
+        } else {
+            throttle
+        };
+        self.collective = throttle;
+        let desired_torque: Vector3<T> = self.calculate_torque(desired_rotation, throttle);
+        let mut command = [
+            throttle,
+            desired_torque.x,
+            desired_torque.z,
+            desired_torque.y,
+        ];
+        if let Some(battery) = self.battery {
+            let ratio = battery.thrust_ratio();
+            if ratio > T::zero() {
+                command = command.map(|v| v / ratio);
+            }
+        }
+        let was_saturated = self.saturated;
+        let (speeds, scaled) = if self.motor_enabled.contains(&false) {
+            (self.mixer.mix_degraded(command, &self.motor_enabled), false)
+        } else {
+            match self.saturation {
+                Saturation::Clip => (self.mixer.mix(command), false),
+                Saturation::AirMode => self.mixer.mix_air_mode(command),
+            }
+        };
+        self.saturated = scaled;
+        self.armed_time += dt;
+        let ceiling = if self.arm_ramp > T::zero() {
+            min(self.armed_time / self.arm_ramp, T::one())
+        } else {
+            T::one()
+        };
+        let outputs = self.motors.motors.iter_mut().zip(speeds);
+        for ((motor, thrust), enabled) in outputs.zip(self.motor_enabled) {
+            let (thrust, clamped) = constrain_reporting(thrust);
+            self.saturated |= clamped;
+            let speed = constrain(self.thrust_curve.inverse(thrust));
+            let speed = if enabled {
+                max(speed, self.idle)
+            } else {
+                speed
+            };
+            // Held down by the arm ramp counts as saturated too.
+            self.saturated |= speed > ceiling;
+            motor.speed = min(speed, ceiling);
+        }
+        if self.saturated && !was_saturated {
+            trace!("motor outputs saturated: {}", self.motors);
+        }
+        &self.motors
+    }
+}
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(4, 4);
+    }
+
+    #[test]
+    fn integral_respects_limit_under_sustained_error() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+        );
+        controller.arm();
+        controller.set_i_limit(0.3);
+        let full_roll = TransmitterState::new(1.0, 0.5, 0.5, 1.0);
+        for i in 1..=100 {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &full_roll);
+            assert!(controller.integral().x <= 0.3);
+        }
+        assert_eq!(controller.integral().x, 0.3);
+    }
+
+    #[test]
+    fn set_gains_keeps_integral() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+        );
+        controller.arm();
+        let full_roll = TransmitterState::new(1.0, 0.5, 0.5, 1.0);
+        for i in 1..=10 {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &full_roll);
+        }
+        let integral = controller.integral().x;
+        assert!(integral > 0.0);
+
+        let softer = PidGains::new(0.1, 0.5, 0.0);
+        controller.set_gains(softer, softer, softer);
+        assert_eq!(controller.integral().x, integral);
+    }
+
+    #[test]
+    fn stick_step_does_not_spike_motors() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.5, 0.0, 0.05),
+            PidGains::new(0.5, 0.0, 0.05),
+            PidGains::new(0.5, 0.0, 0.05),
+        );
+        controller.arm();
+        let centered = TransmitterState::new(0.3, 0.5, 0.5, 0.5);
+        let full_roll = TransmitterState::new(0.3, 0.5, 0.5, 1.0);
+        let mut outputs = [0.0; 6];
+        for (i, output) in outputs.iter_mut().enumerate() {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), (i + 1) as f32 * 0.01);
+            let sticks = if i < 3 { &centered } else { &full_roll };
+            *output = controller
+                .calculate_motor_speeds(imu, sticks)
+                .get_front_left();
+        }
+        assert!((outputs[3] - outputs[4]).abs() < 1e-6);
+        assert!((outputs[4] - outputs[5]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn feedforward_follows_stick_ramp_rate() {
+        let ramp = |rate: f32| {
+            let zero = PidGains::new(0.0, 0.0, 0.0);
+            let mut controller = Controller::with_gains(zero, zero, zero);
+            controller.arm();
+            controller.set_mode(FlightMode::Rate);
+            controller.set_feedforward(0.1, 0.1, 0.1);
+            let mut differential = 0.0;
+            for i in 1..=50 {
+                let time_point = i as f32 * 0.002;
+                let roll = 0.5 + rate * time_point;
+                let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), time_point);
+                let sticks = TransmitterState::new(0.5, 0.5, 0.5, roll);
+                let motors = controller.calculate_motor_speeds(imu, &sticks);
+                differential = motors.get_front_left() - motors.get_front_right();
+            }
+            differential
+        };
+        // With no PID gains only the feed-forward term moves the motors.
+        assert_eq!(ramp(0.0), 0.0);
+        let slow = ramp(1.0);
+        let fast = ramp(2.0);
+        assert!(slow > 0.01);
+        assert!((fast / slow - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn pitch_stick_splits_front_from_rear() {
+        let step = |sticks: TransmitterState| {
+            let zero = PidGains::new(0.0, 0.0, 0.0);
+            let p = PidGains::new(0.5, 0.0, 0.0);
+            let mut controller = Controller::with_gains(p, p, zero);
+            controller.arm();
+            controller.set_mode(FlightMode::Rate);
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.0);
+            let motors = controller.calculate_motor_speeds(imu, &sticks);
+            [
+                motors.get_front_left(),
+                motors.get_front_right(),
+                motors.get_rear_left(),
+                motors.get_rear_right(),
+            ]
+        };
+        // Stick back raises the nose: the front motors speed up, evenly.
+        let [fl, fr, rl, rr] = step(TransmitterState::new(0.5, 0.5, 1.0, 0.5));
+        assert!(fl > rl + 0.05);
+        assert_eq!((fl, rl), (fr, rr));
+        // Stick right rolls right: the left motors speed up, evenly.
+        let [fl, fr, rl, rr] = step(TransmitterState::new(0.5, 0.5, 0.5, 1.0));
+        assert!(fl > fr + 0.05);
+        assert_eq!((fl, fr), (rl, rr));
+    }
+
+    #[test]
+    fn full_throttle_and_roll_saturate() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::Rate);
+        let imu = |time_point| {
+            IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point)
+        };
+        controller.calculate_motor_speeds(imu(0.0), &TransmitterState::new(0.5, 0.5, 0.5, 0.5));
+        assert!(!controller.is_saturated());
+        controller.calculate_motor_speeds(imu(0.002), &TransmitterState::new(1.0, 0.5, 0.5, 1.0));
+        assert!(controller.is_saturated());
+    }
+
+    #[test]
+    fn motors_ramp_up_after_arming_and_idle() {
+        let mut controller = Controller::new();
+        controller.set_idle(0.05);
+        controller.set_arm_ramp(0.1);
+        let level = Vector3::new(0.0, 9.81, 0.0);
+        let step = |controller: &mut Controller, i: usize, throttle: f32| {
+            let imu = IMUDataPoint::new(Vector3::zeros(), level, i as f32 * 0.01);
+            let sticks = TransmitterState::new(throttle, 0.5, 0.5, 0.5);
+            controller.calculate_motor_speeds(imu, &sticks).get(0)
+        };
+        step(&mut controller, 0, 1.0);
+        controller.arm();
+        // Full throttle, held back to 0.1, 0.2, ... of full output.
+        for i in 1..=4 {
+            assert!((step(&mut controller, i, 1.0) - 0.1 * i as f32).abs() < 1e-5);
+            assert!(controller.is_saturated());
+        }
+        step(&mut controller, 5, 1.0);
+        assert_eq!(step(&mut controller, 6, 1.0), 0.5);
+        // Zero throttle keeps the motors at idle.
+        assert_eq!(step(&mut controller, 7, 0.0), 0.05);
+
+        // Arming again starts the ramp over.
+        controller.disarm();
+        step(&mut controller, 8, 0.0);
+        controller.arm();
+        assert!((step(&mut controller, 9, 1.0) - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pid_terms_add_up_to_torque() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.2, 0.1, 0.0),
+        );
+        controller.arm();
+        controller.set_mode(FlightMode::Rate);
+        controller.set_feedforward(0.01, 0.01, 0.01);
+        for i in 1..=20 {
+            let time_point = i as f32 * 0.002;
+            let gyro = Vector3::new(0.2, -0.1, 0.01 * i as f32);
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), time_point);
+            let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5 + 5.0 * time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+        }
+        let terms = controller.last_pid_terms();
+        assert!(terms.p.z < 0.0 && terms.i.z < 0.0 && terms.d.z < 0.0);
+        assert!(terms.ff.x > 0.0);
+        assert_eq!(terms.p, controller.blackbox_frame().p);
+
+        // Read the torque back off the motors: each quad X column is ±1 per
+        // motor, so its dot product with the outputs is four times the torque
+        // on that axis, and the collective cancels out.
+        assert!(!controller.is_saturated());
+        let motors: [f32; 4] = core::array::from_fn(|i| controller.motors.get(i));
+        let column = |signs: [f32; 4]| -> f32 {
+            motors.iter().zip(signs).map(|(m, s)| m * s).sum::<f32>() / 4.0
+        };
+        let roll = column([1.0, -1.0, 1.0, -1.0]);
+        let pitch = column([1.0, 1.0, -1.0, -1.0]);
+        let yaw = column([1.0, -1.0, -1.0, 1.0]);
+        let torque = Vector3::new(roll, yaw, pitch);
+        assert!((terms.sum() - torque).norm() < 1e-6);
+
+        controller.disarm();
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.1);
+        controller.calculate_motor_speeds(imu, &TransmitterState::new(0.0, 0.5, 0.5, 0.5));
+        assert_eq!(controller.last_pid_terms().sum(), Vector3::zeros());
+    }
+
+    #[test]
+    fn tilt_compensation_boosts_throttle_when_banked() {
+        let banked = |roll: f32, compensate: bool| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_mode(FlightMode::Rate);
+            controller.set_tilt_compensation(compensate);
+            controller.set_attitude_time_constant(0.05);
+            // Gravity seen from a drone rolled right by `roll`.
+            let accel = Vector3::new(0.0, roll.cos(), -roll.sin()) * 9.81;
+            let sticks = TransmitterState::new(0.4, 0.5, 0.5, 0.5);
+            for i in 0..100 {
+                let imu = IMUDataPoint::new(Vector3::zeros(), accel, i as f32 * 0.01);
+                controller.calculate_motor_speeds(imu, &sticks);
+            }
+            (0..4).map(|m| controller.motors.get(m)).sum::<f32>() / 2.0
+        };
+        assert!((banked(0.5, false) - 0.4).abs() < 1e-5);
+        assert!((banked(0.0, true) - 0.4).abs() < 1e-5);
+        assert!((banked(0.5, true) - 0.4 / 0.5_f32.cos()).abs() < 1e-3);
+        // Past the limit the boost stays at its 60° value.
+        assert!((banked(1.3, true) - 0.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rpm_notch_follows_motor_telemetry() {
+        let tone_peak = |rpm: Option<[f32; 4]>| {
+            let mut controller = Controller::new();
+            controller.set_rpm_notch(true);
+            let mut peak = 0.0_f32;
+            for i in 0..2000 {
+                if let Some(rpm) = rpm {
+                    controller.feed_motor_rpm(rpm);
+                }
+                let t = i as f32 / 2000.0;
+                let gyro = Vector3::new(0.3 * (2.0 * PI * 200.0 * t).sin(), 0.0, 0.0);
+                let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), t);
+                controller.calculate_motor_speeds(imu, &TransmitterState::default());
+                if i > 1000 {
+                    peak = peak.max(controller.blackbox_frame().gyro.x.abs());
+                }
+            }
+            peak
+        };
+        // 12000 rpm is 200 Hz.
+        assert!(tone_peak(Some([11800.0, 12200.0, 12100.0, 11900.0])) < 0.02);
+        // Without telemetry, or with the motors stopped, the notch is off.
+        assert!(tone_peak(None) > 0.25);
+        assert!(tone_peak(Some([0.0; 4])) > 0.25);
+    }
+
+    #[test]
+    fn iterm_relax_holds_integral_during_fast_inputs() {
+        // Roll integral after `steps` loops of rolling the stick to full over
+        // `ramp` of them.
+        let integral = |ramp: usize, steps: usize, relax_hz: f32| {
+            let i_only = PidGains::new(0.0, 1.0, 0.0);
+            let mut controller = Controller::with_gains(i_only, i_only, i_only);
+            controller.arm();
+            controller.set_mode(FlightMode::Rate);
+            controller.set_iterm_relax(relax_hz);
+            for step in 0..steps {
+                let roll = 0.5 + 0.5 * (step as f32 / ramp as f32).min(1.0);
+                let imu =
+                    IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), step as f32 * 0.002);
+                controller.calculate_motor_speeds(imu, &TransmitterState::new(0.5, 0.5, 0.5, roll));
+            }
+            controller.integral().x
+        };
+        // A flick to full stick barely winds up I while the sticks move...
+        assert!(integral(1, 10, 5.0) < 0.1 * integral(1, 10, 0.0));
+        // ...while a steady roll in over most of a second is hardly touched.
+        assert!(integral(400, 400, 5.0) > 0.9 * integral(400, 400, 0.0));
+    }
+
+    #[test]
+    fn imu_data_wraps_around() {
+        let mut imu: IMUData<f32> = IMUData::new();
+        for i in 0..25 {
+            imu.add_data_point(IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::zeros(),
+                i as f32,
+            ));
+        }
+        assert_eq!(imu.get_data_point().time_point, 24.0);
+    }
+
+    #[test]
+    fn imu_data_previous_samples() {
+        let mut imu: IMUData<f32> = IMUData::new();
+        assert!(imu.get_previous(0).is_none());
+        for i in 0..3 {
+            imu.add_data_point(IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::zeros(),
+                i as f32,
+            ));
+        }
+        assert_eq!(imu.get_previous(0).unwrap().time_point, 2.0);
+        assert_eq!(imu.get_previous(2).unwrap().time_point, 0.0);
+        assert!(imu.get_previous(3).is_none());
+
+        for i in 3..14 {
+            imu.add_data_point(IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::zeros(),
+                i as f32,
+            ));
+        }
+        assert_eq!(imu.get_previous(1).unwrap().time_point, 12.0);
+        assert_eq!(imu.get_previous(9).unwrap().time_point, 4.0);
+        assert!(imu.get_previous(10).is_none());
+    }
+
+    #[test]
+    fn imu_history_length_is_a_parameter() {
+        let mut short: IMUData<f32, 3> = IMUData::new();
+        for i in 0..5 {
+            let accel = Vector3::new(0.0, i as f32, 0.0);
+            short.add_data_point(IMUDataPoint::new(Vector3::zeros(), accel, i as f32));
+        }
+        assert_eq!(short.get_previous(2).unwrap().time_point, 2.0);
+        assert!(short.get_previous(3).is_none());
+        // Only the last three samples, 2, 3 and 4 m/s², count.
+        assert!((short.accel_std_dev() - (2.0_f32 / 3.0).sqrt()).abs() < 1e-6);
+
+        let mut controller: Controller<f32, 4, 64> = Controller::with_mixer(MotorMixer::quad_x());
+        controller.arm();
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        for i in 0..100 {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), i as f32);
+            controller.calculate_motor_speeds(imu, &sticks);
+        }
+        assert_eq!(controller.imu.get_previous(63).unwrap().time_point, 36.0);
+    }
+
+    #[test]
+    fn angle_mode_levels_with_centered_sticks() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::Angle);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        // Treat the inner rate loop as ideal: the drone rotates at whatever
+        // rate the angle loop asked for on the previous tick.
+        let mut roll = 0.4_f32;
+        let mut rate = 0.0;
+        for i in 0..1000 {
+            roll += rate * 0.01;
+            let accel = Vector3::new(0.0, roll.cos(), -roll.sin()) * 9.81;
+            let imu = IMUDataPoint::new(Vector3::new(rate, 0.0, 0.0), accel, i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &centered);
+            rate = controller.rate_setpoint().x;
+        }
+        assert!(roll.abs() < 0.01);
+        assert!(controller.attitude().0.abs() < 0.01);
+    }
+
+    #[test]
+    fn turtle_mode_reverses_the_motors_on_the_side_to_lift() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::Turtle);
+        assert_eq!(controller.mode(), FlightMode::Rate);
+
+        controller.disarm();
+        controller.set_mode(FlightMode::Turtle);
+        // Rolled right past the point of no return, left side up.
+        let roll = 150.0_f32.to_radians();
+        let accel = Vector3::new(0.0, roll.cos(), -roll.sin()) * 9.81;
+        let sticks = TransmitterState::new(0.8, 0.5, 0.5, 0.5);
+        let mut time_point = 0.0;
+        let mut step = |controller: &mut Controller| {
+            time_point += 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), accel, time_point);
+            *controller.calculate_motor_speeds(imu, &sticks)
+        };
+        // Let the attitude estimate settle while the drone lies there.
+        for _ in 0..5000 {
+            step(&mut controller);
+        }
+        assert!(!controller.motors_reversed());
+        controller.arm();
+        assert!(controller.motors_reversed());
+        let motors = step(&mut controller);
+        // Pushed down, the left side turns the drone back the way it rolled.
+        assert!((motors.get_front_left() - 0.8).abs() < 1e-3);
+        assert!((motors.get_rear_left() - 0.8).abs() < 1e-3);
+        assert_eq!(motors.get_front_right(), 0.0);
+        assert_eq!(motors.get_rear_right(), 0.0);
+
+        // Leaving needs a disarm too.
+        controller.set_mode(FlightMode::Rate);
+        assert_eq!(controller.mode(), FlightMode::Turtle);
+    }
+
+    #[test]
+    fn horizon_mode_levels_at_center_and_flips_at_full_stick() {
+        // Rolls the drone for 10 s with an ideal rate loop, as in the angle
+        // mode test, and returns the final roll.
+        let fly = |roll_stick: f32| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_mode(FlightMode::Horizon);
+            let sticks = TransmitterState::new(0.5, 0.5, 0.5, roll_stick);
+            let mut roll = 0.4_f32;
+            let mut rate = 0.0;
+            for i in 0..1000 {
+                roll += rate * 0.01;
+                let accel = Vector3::new(0.0, roll.cos(), -roll.sin()) * 9.81;
+                let imu = IMUDataPoint::new(Vector3::new(rate, 0.0, 0.0), accel, i as f32 * 0.01);
+                controller.calculate_motor_speeds(imu, &sticks);
+                rate = controller.rate_setpoint().x;
+            }
+            roll
+        };
+        assert!(fly(0.5).abs() < 0.01);
+        // Full stick keeps rolling at the full rate, right over the top.
+        let full = fly(1.0);
+        assert!(full > 0.4 + 9.9 * 30.0_f32.to_radians() - 0.01);
+
+        // The setpoint moves smoothly with the stick through the transition.
+        let setpoint = |roll_stick: f32| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_mode(FlightMode::Horizon);
+            let accel = Vector3::new(0.0, 0.9_f32.cos(), -0.9_f32.sin()) * 9.81;
+            let sticks = TransmitterState::new(0.5, 0.5, 0.5, roll_stick);
+            controller
+                .calculate_motor_speeds(IMUDataPoint::new(Vector3::zeros(), accel, 0.0), &sticks);
+            controller.rate_setpoint().x
+        };
+        for i in 0..50 {
+            let stick = 0.5 + i as f32 * 0.01;
+            assert!((setpoint(stick + 0.01) - setpoint(stick)).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn transmitter_try_new_names_bad_channel() {
+        assert!(TransmitterState::try_new(0.0, 0.5, 1.0, 0.5).is_ok());
+        assert_eq!(
+            TransmitterState::try_new(0.0, 0.5, 1.2, 0.5).err(),
+            Some(TransmitterError::OutOfRange(Channel::ForwardBackward))
+        );
+        assert_eq!(
+            TransmitterState::try_new(f32::NAN, 0.5, 0.5, 0.5).err(),
+            Some(TransmitterError::OutOfRange(Channel::UpDown))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "LeftRight")]
+    fn transmitter_new_panics_on_bad_channel() {
+        TransmitterState::new(0.0, 0.5, 0.5, -0.1);
+    }
+
+    #[test]
+    fn transmitter_default_is_idle_and_centered() {
+        let state = TransmitterState::<f32>::default();
+        assert_eq!(state.up_down(), 0.0);
+        assert_eq!(state.rotate_pos_neg(), 0.5);
+        assert_eq!(state.forward_backward(), 0.5);
+        assert_eq!(state.left_right(), 0.5);
+    }
+
+    #[test]
+    fn yaw_command_spins_up_one_diagonal() {
+        let mut controller = Controller::new();
+        controller.arm();
+        let yaw = TransmitterState::new(0.5, 1.0, 0.5, 0.5);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let motors = controller.calculate_motor_speeds(imu, &yaw);
+        assert!(motors.get_front_left() > motors.get_front_right());
+        assert!(motors.get_rear_right() > motors.get_rear_left());
+        assert_eq!(motors.get_front_left(), motors.get_rear_right());
+        assert_eq!(motors.get_front_right(), motors.get_rear_left());
+        assert!(controller.rate_setpoint().y > 0.0);
+    }
+
+    #[test]
+    fn motor_positions_match_named_accessors() {
+        let mut motors = Quad::<f32>::new();
+        motors.set(MotorPosition::FrontRight, 0.25);
+        motors.set_rear_left(0.75);
+        assert_eq!(motors.get_front_right(), 0.25);
+        assert_eq!(motors.get(MotorPosition::RearLeft), 0.75);
+        assert_eq!(motors.get(2), 0.75);
+        assert!(motors.iter().eq([
+            (MotorPosition::FrontLeft, 0.0),
+            (MotorPosition::FrontRight, 0.25),
+            (MotorPosition::RearLeft, 0.75),
+            (MotorPosition::RearRight, 0.0),
+        ]));
+    }
+
+    #[test]
+    fn motor_outputs_map_to_esc_protocols() {
+        let mut motors = Quad::<f32>::new();
+        motors.set_front_right(1.0);
+        motors.set_rear_left(0.5);
+        motors.set_rear_right(0.3337);
+        assert_eq!(motors.to_pwm(1000, 2000), [1000, 2000, 1500, 1334]);
+        assert_eq!(
+            motors.to_dshot(),
+            [DSHOT_MIN_THROTTLE, DSHOT_MAX_THROTTLE, 1048, 715]
+        );
+    }
+
+    #[test]
+    fn hexacopter_spreads_throttle_evenly() {
+        let mut controller = Controller::<f32, 6>::with_mixer(MotorMixer::hex_x());
+        controller.arm();
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let motors =
+            controller.calculate_motor_speeds(imu, &TransmitterState::new(0.6, 0.5, 0.5, 0.5));
+        for i in 0..6 {
+            assert!((motors.get(i) - 0.3).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn air_mode_preserves_roll_differential() {
+        let sticks = TransmitterState::new(0.2, 0.5, 0.5, 0.75);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let roll_torque = (1.0 / 6.0) * PI * 0.5;
+
+        let mut clipped = Controller::new();
+        clipped.arm();
+        let motors = clipped.calculate_motor_speeds(imu, &sticks);
+        let clipped_diff = motors.get_front_left() - motors.get_front_right();
+        assert!(clipped_diff < 2.0 * roll_torque - 0.1);
+
+        let mut air_mode = Controller::new();
+        air_mode.arm();
+        air_mode.set_saturation(Saturation::AirMode);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let motors = air_mode.calculate_motor_speeds(imu, &sticks);
+        let diff = motors.get_front_left() - motors.get_front_right();
+        assert!((diff - 2.0 * roll_torque).abs() < 1e-5);
+        assert_eq!(motors.get_front_right(), 0.0);
+    }
+
+    #[test]
+    fn battery_sag_raises_motor_commands() {
+        let sticks = TransmitterState::new(0.6, 0.5, 0.5, 0.5);
+        let mut controller = Controller::new();
+        controller.arm();
+        let mut last = 0.0;
+        for (i, voltage) in [16.8, 15.4, 14.0].into_iter().enumerate() {
+            controller
+                .set_battery(BatteryState::new(voltage, 4))
+                .unwrap();
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), i as f32 * 0.01);
+            let motor = controller
+                .calculate_motor_speeds(imu, &sticks)
+                .get_front_left();
+            assert!(motor > last);
+            assert!((motor * voltage / 16.8 - 0.3).abs() < 1e-6);
+            last = motor;
+        }
+    }
+
+    #[test]
+    fn bad_battery_readings_are_rejected() {
+        let sticks = TransmitterState::new(0.6, 0.5, 0.5, 0.5);
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_battery(BatteryState::new(15.4, 4)).unwrap();
+        for battery in [
+            BatteryState::new(16.0, 0),
+            BatteryState::new(f32::NAN, 4),
+            BatteryState::new(0.0, 4),
+        ] {
+            assert_eq!(controller.set_battery(battery), Err(ConfigError::Battery));
+        }
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let motor = controller
+            .calculate_motor_speeds(imu, &sticks)
+            .get_front_left();
+        assert!((motor * 15.4 / 16.8 - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tpa_reduces_p_at_high_throttle() {
+        let roll_differential = |throttle: f32| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_tpa(0.5, 0.5).unwrap();
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+            let sticks = TransmitterState::new(throttle, 0.5, 0.5, 0.6);
+            let motors = controller.calculate_motor_speeds(imu, &sticks);
+            motors.get_front_left() - motors.get_front_right()
+        };
+        let low = roll_differential(0.3);
+        let high = roll_differential(1.0);
+        assert!((low - 2.0 * (1.0 / 6.0) * PI * 0.2).abs() < 1e-5);
+        assert!((high - 0.5 * low).abs() < 1e-5);
+
+        // A factor above 1 would flip P and D at full throttle.
+        let mut controller = Controller::new();
+        assert_eq!(controller.set_tpa(0.5, 1.5), Err(ConfigError::Tpa));
+        assert_eq!(controller.set_tpa(-0.1, 0.5), Err(ConfigError::Tpa));
+    }
+
+    #[test]
+    fn quadratic_thrust_curve_raises_hover_command() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_thrust_curve(ThrustCurve::Quadratic);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let motors = controller.calculate_motor_speeds(imu, &sticks);
+        assert!((motors.get_front_left() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gyro_calibration_removes_bias() {
+        let bias = Vector3::new(0.01, -0.02, 0.005);
+        let still = Vector3::new(0.0, 9.81, 0.0);
+        let samples: [IMUDataPoint; 20] =
+            core::array::from_fn(|i| IMUDataPoint::new(bias, still, i as f32 * 0.001));
+        let mut controller = Controller::new();
+        assert_eq!(controller.calibrate_gyro(&samples), Ok(()));
+        assert!((controller.gyro_bias() - bias).norm() < 1e-6);
+
+        let imu = IMUDataPoint::new(bias, still, 0.1);
+        controller.calculate_motor_speeds(imu, &TransmitterState::default());
+        assert!(controller.imu.get_data_point().gyro.norm() < 1e-6);
+
+        controller.reset_gyro_bias();
+        assert_eq!(controller.gyro_bias(), Vector3::zeros());
+    }
+
+    #[test]
+    fn reset_clears_history_but_keeps_calibration() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+        );
+        controller.arm();
+        let bias = Vector3::new(0.01, 0.0, 0.0);
+        let still = Vector3::new(0.0, 9.81, 0.0);
+        controller
+            .calibrate_gyro(&[IMUDataPoint::new(bias, still, 0.0)])
+            .unwrap();
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 1.0);
+        for i in 0..10 {
+            let imu = IMUDataPoint::new(bias, Vector3::new(0.0, 9.0, 3.0), i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &sticks);
+        }
+        assert!(controller.integral().x > 0.0);
+        assert!(controller.attitude().0 != 0.0);
+
+        controller.reset();
+        assert_eq!(controller.integral(), Vector3::zeros());
+        assert_eq!(controller.attitude(), (0.0, 0.0));
+        assert_eq!(controller.gyro_bias(), bias);
+        assert!(controller.imu.get_previous(0).is_none());
+        // The first sample after a reset must not see a dt from before it.
+        let imu = IMUDataPoint::new(bias, still, 50.0);
+        controller.calculate_motor_speeds(imu, &TransmitterState::default());
+        assert_eq!(controller.dt, 0.0);
+    }
+
+    #[test]
+    fn gyro_calibration_rejects_movement() {
+        let samples: [IMUDataPoint; 20] = core::array::from_fn(|i| {
+            let shake = if i % 2 == 0 { 2.0 } else { -2.0 };
+            IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81 + shake, 0.0), 0.0)
+        });
+        let mut controller = Controller::new();
+        assert_eq!(
+            controller.calibrate_gyro(&samples),
+            Err(CalibrationError::NotStationary)
+        );
+        assert_eq!(
+            controller.calibrate_gyro(&[]),
+            Err(CalibrationError::NoSamples)
+        );
+        assert_eq!(controller.gyro_bias(), Vector3::zeros());
+    }
+
+    #[test]
+    fn dt_follows_irregular_timestamps() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+            PidGains::new(0.0, 1.0, 0.0),
+        );
+        controller.arm();
+        let sticks = TransmitterState::new(1.0, 0.5, 0.5, 0.5);
+        let rate_error = Vector3::new(-0.1, 0.0, 0.0);
+        let mut integral = 0.0;
+        for (time_point, expected_dt) in [
+            (5.0, 0.0),
+            (5.001, 0.001),
+            (5.004, 0.003),
+            (5.5, MAX_DT as f32),
+            (5.5, MIN_DT as f32),
+            (5.49, MIN_DT as f32),
+            (5.51, 0.02),
+        ] {
+            let imu = IMUDataPoint::new(rate_error, Vector3::zeros(), time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+            assert!((controller.dt - expected_dt).abs() < 1e-6);
+            integral += 0.1 * expected_dt;
+            assert!((controller.integral().x - integral).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn accel_vibration_separates_noisy_from_clean() {
+        let mut clean = Controller::new();
+        let mut noisy = Controller::new();
+        let sticks = TransmitterState::default();
+        for i in 0..30 {
+            let shake = if i % 2 == 0 { 1.5 } else { -1.5 };
+            let time_point = i as f32 * 0.001;
+            clean.calculate_motor_speeds(
+                IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point),
+                &sticks,
+            );
+            noisy.calculate_motor_speeds(
+                IMUDataPoint::new(
+                    Vector3::zeros(),
+                    Vector3::new(0.0, 9.81 + shake, 0.0),
+                    time_point,
+                ),
+                &sticks,
+            );
+        }
+        assert!(clean.accel_vibration() < 1e-5);
+        assert!((noisy.accel_vibration() - 1.5).abs() < 1e-4);
+    }
+
+    // Pins the accurate math; the micromath approximations move the digits.
+    #[test]
+    #[cfg(not(feature = "micromath"))]
+    fn fixed_input_regression() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_saturation(Saturation::AirMode);
+        let sticks = TransmitterState::new(0.8, 0.55, 0.45, 0.6);
+        let mut outputs = [[0.0; 4]; 3];
+        for (i, output) in outputs.iter_mut().enumerate() {
+            let imu = IMUDataPoint::new(
+                Vector3::new(0.1, -0.05, 0.2) * i as f32,
+                Vector3::new(0.5, 9.7, -0.3),
+                i as f32 * 0.002,
+            );
+            let motors = controller.calculate_motor_speeds(imu, &sticks);
+            *output = core::array::from_fn(|m| motors.get(m));
+        }
+        let expected = [
+            [0.5047198, 0.19056045, 0.5047198, 0.4],
+            [0.2547198, 0.040560454, 0.5547198, 0.75],
+            [0.0943903, 0.0, 0.59048784, 1.0],
+        ];
+        for (output, expected) in outputs.iter().zip(expected) {
+            for (motor, expected) in output.iter().zip(expected) {
+                assert!((motor - expected).abs() < 1e-6);
+            }
+        }
+        let (roll, pitch) = controller.attitude();
+        assert!((roll - 0.0008426818).abs() < 1e-7);
+        assert!((pitch - 0.0016029812).abs() < 1e-7);
+    }
+
+    #[test]
+    fn double_precision_matches_single() {
+        let mut single = Controller::new();
+        single.arm();
+        let mut double = Controller::<f64>::with_mixer(MotorMixer::quad_x());
+        double.arm();
+        for i in 0..5 {
+            let gyro = Vector3::new(0.1, -0.05, 0.2) * i as f64;
+            let accel = Vector3::new(0.5, 9.7, -0.3);
+            let time_point = i as f64 * 0.002;
+            let expected = single.calculate_motor_speeds(
+                IMUDataPoint::new(gyro.cast(), accel.cast(), time_point as f32),
+                &TransmitterState::new(0.6, 0.55, 0.45, 0.6),
+            );
+            let motors = double.calculate_motor_speeds(
+                IMUDataPoint::new(gyro, accel, time_point),
+                &TransmitterState::new(0.6, 0.55, 0.45, 0.6),
+            );
+            for m in 0..4 {
+                assert!((motors.get(m) - expected.get(m) as f64).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn step_runs_only_when_the_imu_has_a_sample() {
+        struct Polled {
+            ready: bool,
+            time_point: f32,
+        }
+        impl ImuSource for Polled {
+            type Error = ();
+
+            fn read(&mut self) -> nb::Result<IMUDataPoint, ()> {
+                if !self.ready {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.ready = false;
+                self.time_point += 0.01;
+                Ok(IMUDataPoint::new(
+                    Vector3::zeros(),
+                    Vector3::new(0.0, 9.81, 0.0),
+                    self.time_point,
+                ))
+            }
+        }
+
+        let mut controller = Controller::new();
+        controller.arm();
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let mut imu = Polled {
+            ready: false,
+            time_point: 0.0,
+        };
+        assert!(matches!(
+            controller.step(&mut imu, &sticks),
+            Err(nb::Error::WouldBlock)
+        ));
+        assert_eq!(controller.motors.get(0), 0.0);
+        imu.ready = true;
+        let motors = controller.step(&mut imu, &sticks).unwrap();
+        assert!((motors.get(0) - 0.25).abs() < 1e-6);
+
+        // Recorded samples play back through the same path.
+        let mut recording =
+            [IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.02)].into_iter();
+        assert!(controller.step(&mut recording, &sticks).is_ok());
+        assert!(controller.step(&mut recording, &sticks).is_err());
+    }
+
+    #[test]
+    fn motors_stay_off_until_armed() {
+        let mut controller = Controller::new();
+        let sticks = TransmitterState::new(0.8, 0.7, 0.3, 0.6);
+        for i in 0..10 {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), i as f32 * 0.01);
+            let motors = controller.calculate_motor_speeds(imu, &sticks);
+            assert!((0..4).all(|m| motors.get(m) == 0.0));
+        }
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+
+        controller.arm();
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.1);
+        let motors = controller.calculate_motor_speeds(imu, &sticks);
+        assert!((0..4).any(|m| motors.get(m) > 0.0));
+    }
+
+    #[test]
+    fn yaw_gesture_arms_and_disarms() {
+        let mut controller = Controller::new();
+        let mut time_point = 0.0;
+        let mut hold = |controller: &mut Controller, sticks: TransmitterState, seconds: f32| {
+            for _ in 0..(seconds / 0.01) as usize {
+                time_point += 0.01;
+                let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), time_point);
+                controller.calculate_motor_speeds(imu, &sticks);
+            }
+        };
+        let arm = TransmitterState::new(0.0, 0.0, 0.5, 0.5);
+        let disarm = TransmitterState::new(0.0, 1.0, 0.5, 0.5);
+
+        hold(&mut controller, arm, 0.5);
+        hold(&mut controller, TransmitterState::default(), 0.1);
+        hold(&mut controller, arm, 0.5);
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+        hold(&mut controller, arm, 0.6);
+        assert_eq!(controller.arm_state(), ArmState::Armed);
+
+        // Full yaw with throttle up is a normal flight command.
+        hold(
+            &mut controller,
+            TransmitterState::new(0.5, 1.0, 0.5, 0.5),
+            2.0,
+        );
+        assert_eq!(controller.arm_state(), ArmState::Armed);
+        hold(&mut controller, disarm, 1.1);
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+    }
+
+    #[test]
+    fn expo_softens_center_but_keeps_full_scale() {
+        let linear = [0.1, 0.9].map(|v: f32| apply_expo(v, 0.0));
+        let soft = [0.1, 0.9].map(|v: f32| apply_expo(v, 0.5));
+        assert_eq!(linear, [0.1, 0.9]);
+        assert!((soft[0] - 0.0505).abs() < 1e-6);
+        assert!((soft[1] - 0.8145).abs() < 1e-6);
+        assert!(soft[0] / linear[0] < soft[1] / linear[1]);
+        assert_eq!(apply_expo(1.0_f32, 0.5), 1.0);
+        assert_eq!(apply_expo(-1.0_f32, 0.5), -1.0);
+
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_expo(0.5, 0.5, 0.5).unwrap();
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        controller.calculate_motor_speeds(imu, &TransmitterState::new(0.5, 0.5, 0.5, 0.55));
+        assert!((controller.rate_setpoint().x - PI / 6.0 * 0.0505).abs() < 1e-6);
+    }
+
+    #[test]
+    fn deadzone_zeroes_small_inputs() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_deadzone(0.05);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        controller.calculate_motor_speeds(imu, &TransmitterState::new(0.5, 0.51, 0.51, 0.51));
+        assert_eq!(controller.rate_setpoint(), Vector3::zeros());
+
+        assert_eq!(apply_deadzone(1.0_f32, 0.05), 1.0);
+        assert_eq!(apply_deadzone(-1.0_f32, 0.05), -1.0);
+        assert!((apply_deadzone(0.525_f32, 0.05) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn angle_mode_holds_max_angle_at_full_stick() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::Angle);
+        controller.set_max_angle(0.3);
+        let full_roll = TransmitterState::new(0.5, 0.5, 0.5, 1.0);
+        // Ideal rate loop, as in angle_mode_levels_with_centered_sticks.
+        let mut roll = 0.0_f32;
+        let mut rate = 0.0;
+        for i in 0..2000 {
+            roll += rate * 0.01;
+            let accel = Vector3::new(0.0, roll.cos(), -roll.sin()) * 9.81;
+            let imu = IMUDataPoint::new(Vector3::new(rate, 0.0, 0.0), accel, i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &full_roll);
+            rate = controller.rate_setpoint().x;
+            assert!(roll <= 0.3 + 1e-3);
+        }
+        assert!((roll - 0.3).abs() < 1e-3);
+        assert!(rate.abs() < 1e-3);
+    }
+
+    #[test]
+    fn yaw_setpoint_scales_with_max_yaw_rate() {
+        let yaw_setpoint = |max_yaw_rate: f32| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_max_rates(1.0, 1.0, max_yaw_rate).unwrap();
+            assert_eq!(controller.max_yaw_rate(), max_yaw_rate);
+            let sticks = TransmitterState::new(0.5, 0.8, 0.6, 0.5);
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.0);
+            controller.calculate_motor_speeds(imu, &sticks);
+            controller.rate_setpoint()
+        };
+        let slow = yaw_setpoint(2.0);
+        let fast = yaw_setpoint(6.0);
+        assert!((slow.y - 2.0 * 0.6).abs() < 1e-6);
+        assert!((fast.y - 3.0 * slow.y).abs() < 1e-6);
+        // The other axes don't move.
+        assert_eq!((fast.x, fast.z), (slow.x, slow.z));
+    }
+
+    #[test]
+    fn per_axis_max_rates_validate() {
+        let mut controller = Controller::new();
+        controller.set_max_pitch_rate(4.0).unwrap();
+        assert_eq!(controller.max_pitch_rate(), 4.0);
+        assert_eq!(
+            controller.set_max_roll_rate(0.0),
+            Err(ConfigError::Rate(Axis::Roll))
+        );
+        assert_eq!(
+            controller.set_max_rates(1.0, 1.0, f32::NAN),
+            Err(ConfigError::Rate(Axis::Yaw))
+        );
+        assert_eq!(controller.max_roll_rate(), 30.0_f32.to_radians());
+        assert_eq!(controller.max_yaw_rate(), 30.0_f32.to_radians());
+    }
+
+    #[test]
+    fn setpoint_slew_ramps_yaw_step() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_setpoint_slew(10.0);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let full_yaw = TransmitterState::new(0.5, 1.0, 0.5, 0.5);
+        let mut setpoints = [0.0; 8];
+        for (i, setpoint) in setpoints.iter_mut().enumerate() {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), i as f32 * 0.01);
+            let sticks = if i == 0 { &centered } else { &full_yaw };
+            controller.calculate_motor_speeds(imu, sticks);
+            *setpoint = controller.rate_setpoint().y;
+        }
+        for (i, setpoint) in setpoints.iter().enumerate().take(6) {
+            assert!((setpoint - 0.1 * i as f32).abs() < 1e-5);
+        }
+        assert!((setpoints[6] - PI / 6.0).abs() < 1e-6);
+        assert_eq!(setpoints[7], setpoints[6]);
+    }
+
+    #[test]
+    fn throttle_slew_ramps_collective_but_not_corrections() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_throttle_slew(2.0);
+        let idle = TransmitterState::new(0.0, 0.5, 0.5, 0.5);
+        let full = TransmitterState::new(1.0, 0.5, 0.5, 0.5);
+        let mut step = |i: usize, gyro: Vector3<f32>| {
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), i as f32 * 0.01);
+            let sticks = if i == 0 { &idle } else { &full };
+            let motors = controller.calculate_motor_speeds(imu, sticks);
+            [0, 1, 2, 3].map(|motor| motors.get(motor))
+        };
+        step(0, Vector3::zeros());
+        // Half a second from idle to full, 0.02 a step, which the quad X
+        // mixer halves.
+        for i in 1..=10 {
+            for motor in step(i, Vector3::zeros()) {
+                assert!((motor - 0.01 * i as f32).abs() < 1e-5);
+            }
+        }
+        // A sudden roll is answered in the same step, on top of the ramp.
+        let [fl, fr, rl, rr] = step(11, Vector3::new(0.1, 0.0, 0.0));
+        assert!(((fl + fr) / 2.0 - 0.11).abs() < 1e-5);
+        assert!(fr - fl > 0.1);
+        assert_eq!((fl, fr), (rl, rr));
+        for i in 12..60 {
+            step(i, Vector3::zeros());
+        }
+        assert_eq!(step(60, Vector3::zeros()), [0.5; 4]);
+    }
+
+    #[test]
+    fn kill_holds_until_cleared() {
+        let mut controller = Controller::new();
+        controller.arm();
+        let hover = TransmitterState::new(0.6, 0.5, 0.5, 0.5);
+        let mut time_point = 0.0;
+        let mut step = |controller: &mut Controller, sticks: &TransmitterState| {
+            time_point += 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point);
+            let motors = controller.calculate_motor_speeds(imu, sticks);
+            [0, 1, 2, 3].map(|motor| motors.get(motor))
+        };
+        assert!(step(&mut controller, &hover)[0] > 0.0);
+
+        controller.kill();
+        // Every stick corner, held long enough for the arming gesture, and
+        // direct arming leave the motors stopped.
+        controller.arm();
+        for corner in 0..16 {
+            let at = |bit: u32| if corner & (1 << bit) == 0 { 0.0 } else { 1.0 };
+            let sticks = TransmitterState::new(at(0), at(1), at(2), at(3));
+            for _ in 0..300 {
+                assert_eq!(step(&mut controller, &sticks), [0.0; 4]);
+            }
+            assert!(controller.is_killed());
+        }
+
+        controller.clear_kill();
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+        controller.arm();
+        assert!(step(&mut controller, &hover)[0] > 0.0);
+    }
+
+    #[test]
+    fn stale_link_triggers_failsafe() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_failsafe_timeout(0.5);
+        let sticks = TransmitterState::new(0.6, 0.5, 0.5, 0.55);
+        controller.feed_transmitter(sticks, 0.0);
+        let throttle = |controller: &mut Controller, time_point: f32| {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), time_point);
+            let motors = controller.calculate_motor_speeds(imu, &sticks);
+            (0..4).map(|m| motors.get(m)).sum::<f32>() / 2.0
+        };
+
+        // The caller keeps passing the old sticks, but they're ignored once the
+        // link goes quiet.
+        assert!((throttle(&mut controller, 0.4) - 0.6).abs() < 1e-6);
+        assert!(controller.rate_setpoint().x > 0.0);
+        let mut last = 0.6;
+        for i in 1..=10 {
+            let now = throttle(&mut controller, 0.5 + i as f32 * 0.3);
+            assert!(now < last);
+            assert_eq!(controller.rate_setpoint().x, 0.0);
+            last = now;
+        }
+        assert!(last < 0.01);
+        throttle(&mut controller, 3.6);
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+
+        // A fresh frame restores control but not the arm state.
+        controller.feed_transmitter(sticks, 3.7);
+        assert_eq!(throttle(&mut controller, 3.7), 0.0);
+        controller.arm();
+        assert!((throttle(&mut controller, 3.71) - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dead_motor_is_left_out_of_the_mix() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_motor_enabled(0, false).unwrap();
+        assert_eq!(controller.motor_enabled(0), Some(false));
+        assert_eq!(
+            controller.set_motor_enabled(4, false),
+            Err(ConfigError::Motor(4))
+        );
+        assert_eq!(controller.motor_enabled(4), None);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::zeros(), 0.01);
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let motors = controller.calculate_motor_speeds(imu, &sticks);
+        assert_eq!(motors.get_front_left(), 0.0);
+        assert_eq!(motors.get_rear_right(), 0.0);
+        assert!((motors.get_front_right() - 0.5).abs() < 1e-6);
+        assert!((motors.get_rear_left() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn altitude_hold_climbs_to_target() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::AltitudeHold);
+        controller.set_target_altitude(1.0);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        // Point mass that hovers at half throttle.
+        let (mut altitude, mut velocity) = (0.0_f32, 0.0);
+        let mut specific_force = 9.81;
+        for i in 0..1000 {
+            let imu = IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::new(0.0, specific_force, 0.0),
+                i as f32 * 0.01,
+            );
+            let motors = controller.calculate_motor_speeds(imu, &centered);
+            let throttle: f32 = (0..4).map(|m| motors.get(m)).sum::<f32>() / 2.0;
+            specific_force = 9.81 * throttle / 0.5;
+            velocity += (specific_force - 9.81) * 0.01;
+            altitude += velocity * 0.01;
+        }
+        assert!((altitude - 1.0).abs() < 0.05);
+        assert!((controller.altitude() - altitude).abs() < 0.05);
+        assert_eq!(controller.target_altitude(), 1.0);
+    }
+
+    #[test]
+    fn hover_auto_trim_learns_hover_throttle() {
+        let mut controller = Controller::new();
+        controller.set_hover_auto_trim(true);
+        controller.arm();
+        controller.set_mode(FlightMode::AltitudeHold);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        // Point mass that hovers at 0.6 throttle, heavier than the default
+        // hover throttle assumes.
+        let (mut altitude, mut velocity) = (0.0_f32, 0.0);
+        let mut specific_force = 9.81;
+        for i in 0..6000 {
+            let imu = IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::new(0.0, specific_force, 0.0),
+                i as f32 * 0.01,
+            );
+            let motors = controller.calculate_motor_speeds(imu, &centered);
+            let throttle: f32 = (0..4).map(|m| motors.get(m)).sum::<f32>() / 2.0;
+            specific_force = 9.81 * throttle / 0.6;
+            velocity += (specific_force - 9.81) * 0.01;
+            altitude += velocity * 0.01;
+        }
+        assert!((controller.hover_throttle() - 0.6).abs() < 0.01);
+        assert!(altitude.abs() < 0.05);
+
+        controller.reset_hover_throttle();
+        assert_eq!(controller.hover_throttle(), 0.5);
+    }
+
+    #[test]
+    fn altitude_hold_stick_moves_target() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::AltitudeHold);
+        let climb = TransmitterState::new(1.0, 0.5, 0.5, 0.5);
+        for i in 0..101 {
+            let imu = IMUDataPoint::new(
+                Vector3::zeros(),
+                Vector3::new(0.0, 9.81, 0.0),
+                i as f32 * 0.01,
+            );
+            controller.calculate_motor_speeds(imu, &climb);
+        }
+        assert!((controller.target_altitude() - MAX_CLIMB_RATE as f32).abs() < 1e-4);
+    }
+
+    #[test]
+    fn barometer_keeps_altitude_from_drifting() {
+        let mut controller = Controller::new();
+        let ground = 101_325.0;
+        controller.feed_baro(BaroSample::new(ground, 0.0));
+        controller.arm();
+        // Hovering still at ground level with a biased accelerometer.
+        let biased = Vector3::new(0.0, 9.91, 0.0);
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        for i in 1..=3000 {
+            let time_point = i as f32 * 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), biased, time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+            controller.feed_baro(BaroSample::new(ground, time_point));
+        }
+        assert!(controller.altitude().abs() < 0.2);
+
+        // Climbing a meter shows up within a few time constants.
+        for i in 3001..=4000 {
+            let time_point = i as f32 * 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), biased, time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+            controller.feed_baro(BaroSample::new(ground - 12.0, time_point));
+        }
+        assert!((controller.altitude() - 1.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn heading_hold_turns_back_to_held_heading() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_heading_hold(true);
+        let north = Vector3::new(0.2, -0.4, 0.0);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let feed = |controller: &mut Controller, gyro: f32, heading: f32, time_point: f32| {
+            let imu = IMUDataPoint::new(
+                Vector3::new(0.0, gyro, 0.0),
+                Vector3::new(0.0, 9.81, 0.0),
+                time_point,
+            );
+            controller.calculate_motor_speeds(imu, &centered);
+            let to_body = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -heading);
+            controller.feed_mag(MagSample::new(to_body * north, time_point));
+        };
+        feed(&mut controller, 0.0, 0.0, 0.0);
+        assert!(controller.heading().abs() < 1e-5);
+
+        // Knocked 0.3 rad to the left, it yaws back right.
+        feed(&mut controller, 0.0, 0.3, 0.01);
+        feed(&mut controller, 0.0, 0.3, 0.02);
+        assert!(controller.heading() > 0.0);
+        assert!(controller.rate_setpoint().y < 0.0);
+
+        // The held heading follows the stick.
+        controller.calculate_motor_speeds(
+            IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.03),
+            &TransmitterState::new(0.5, 1.0, 0.5, 0.5),
+        );
+        assert!(controller.rate_setpoint().y > 0.0);
+        feed(&mut controller, 0.0, 0.3, 0.04);
+        assert_eq!(controller.rate_setpoint().y, 0.0);
+    }
+
+    #[test]
+    fn position_hold_leans_towards_target() {
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let level = Vector3::new(0.0, 9.81, 0.0);
+        let tilt_towards = |target: Vector3<f32>| {
+            let mut controller = Controller::new();
+            controller.arm();
+            controller.set_mode(FlightMode::PositionHold);
+            controller.feed_gps(GpsSample::new(Vector3::zeros(), 0.0));
+            controller.set_target_position(target);
+            let imu = IMUDataPoint::new(Vector3::zeros(), level, 0.0);
+            controller.calculate_motor_speeds(imu, &centered);
+            controller.rate_setpoint()
+        };
+        // North is straight ahead at zero heading: nose down.
+        let north = tilt_towards(Vector3::new(5.0, 0.0, 0.0));
+        assert!(north.z < 0.0);
+        assert!(north.x.abs() < 1e-6);
+        // East is to the right: right side down.
+        let east = tilt_towards(Vector3::new(0.0, 0.0, 5.0));
+        assert!(east.x > 0.0);
+        assert!(east.z.abs() < 1e-6);
+        // Height is left to altitude hold.
+        assert_eq!(tilt_towards(Vector3::new(0.0, 5.0, 0.0)), Vector3::zeros());
+    }
+
+    #[test]
+    fn position_hold_without_gps_flies_like_altitude_hold() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::PositionHold);
+        controller.set_target_position(Vector3::new(5.0, 0.0, 0.0));
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.0);
+        controller.calculate_motor_speeds(imu, &TransmitterState::new(0.5, 0.5, 0.5, 0.5));
+        assert_eq!(controller.rate_setpoint(), Vector3::zeros());
+    }
+
+    #[test]
+    fn return_to_home_climbs_before_heading_home() {
+        let mut controller = Controller::new();
+        let ground = 101_325.0;
+        controller.feed_baro(BaroSample::new(ground, 0.0));
+        controller.arm();
+        controller.set_home(Vector3::zeros());
+        controller.set_return_altitude(3.0);
+        controller.feed_gps(GpsSample::new(Vector3::new(5.0, 0.0, 0.0), 0.0));
+        controller.set_mode(FlightMode::ReturnToHome);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let level = Vector3::new(0.0, 9.81, 0.0);
+        let imu = IMUDataPoint::new(Vector3::zeros(), level, 0.01);
+        controller.calculate_motor_speeds(imu, &centered);
+        // Climbing in place first, whatever the sticks say.
+        assert_eq!(controller.return_phase(), ReturnPhase::Climb);
+        assert_eq!(controller.target_altitude(), 3.0);
+        assert_eq!(controller.rate_setpoint(), Vector3::zeros());
+
+        // About 12 Pa per meter.
+        for i in 2..=800 {
+            let time_point = i as f32 * 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), level, time_point);
+            controller.calculate_motor_speeds(imu, &centered);
+            controller.feed_baro(BaroSample::new(ground - 36.0, time_point));
+        }
+        // Home is to the south, behind the drone: nose up.
+        assert_eq!(controller.return_phase(), ReturnPhase::Transit);
+        assert!(controller.target_position().unwrap().x < 5.0);
+        assert!(controller.rate_setpoint().z > 0.0);
+    }
+
+    #[test]
+    fn return_to_home_lands_and_disarms_at_home() {
+        let mut controller = Controller::new();
+        let ground = 101_325.0;
+        controller.feed_baro(BaroSample::new(ground, 0.0));
+        controller.arm();
+        controller.set_return_altitude(0.0);
+        // The first fix after arming becomes home.
+        controller.feed_gps(GpsSample::new(Vector3::new(1.0, 0.0, 2.0), 0.0));
+        assert_eq!(controller.home(), Some(Vector3::new(1.0, 0.0, 2.0)));
+        controller.set_mode(FlightMode::ReturnToHome);
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let level = Vector3::new(0.0, 9.81, 0.0);
+        let step = |controller: &mut Controller, i: i32| {
+            let time_point = i as f32 * 0.01;
+            let imu = IMUDataPoint::new(Vector3::zeros(), level, time_point);
+            let motors = controller.calculate_motor_speeds(imu, &centered);
+            let throttle = (0..4).map(|m| motors.get(m)).sum::<f32>();
+            controller.feed_baro(BaroSample::new(ground, time_point));
+            throttle
+        };
+        for i in 1..=3 {
+            step(&mut controller, i);
+        }
+        assert_eq!(controller.return_phase(), ReturnPhase::Descend);
+        // The altitude target sinks steadily while the drone is still flying.
+        let target = controller.target_altitude();
+        for i in 4..=103 {
+            assert!(step(&mut controller, i) > 0.0);
+        }
+        assert!((target - controller.target_altitude() - 0.5).abs() < 1e-4);
+
+        // Stuck on the ground, the target runs away and the drone disarms.
+        let mut landed = None;
+        for i in 104..=400 {
+            let throttle = step(&mut controller, i);
+            if controller.return_phase() == ReturnPhase::Landed {
+                landed.get_or_insert(i);
+                assert_eq!(throttle, 0.0);
+            }
+        }
+        assert!((190..=210).contains(&landed.unwrap()));
+        assert_eq!(controller.arm_state(), ArmState::Disarmed);
+    }
+
+    #[test]
+    fn lost_link_with_gps_returns_home() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_failsafe_timeout(0.5);
+        controller.set_mode(FlightMode::PositionHold);
+        controller.feed_gps(GpsSample::new(Vector3::zeros(), 0.0));
+        let sticks = TransmitterState::new(0.6, 0.5, 0.5, 0.5);
+        controller.feed_transmitter(sticks, 0.0);
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.4);
+        controller.calculate_motor_speeds(imu, &sticks);
+        assert_eq!(controller.mode(), FlightMode::PositionHold);
+
+        // Rather than sinking where it is, the drone heads home.
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.6);
+        controller.calculate_motor_speeds(imu, &sticks);
+        assert_eq!(controller.mode(), FlightMode::ReturnToHome);
+        assert_eq!(controller.return_phase(), ReturnPhase::Climb);
+        assert_eq!(controller.arm_state(), ArmState::Armed);
+    }
+
+    #[test]
+    fn link_recovery_hands_control_back() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_failsafe_timeout(0.5);
+        controller.set_mode(FlightMode::PositionHold);
+        controller.feed_gps(GpsSample::new(Vector3::zeros(), 0.0));
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        controller.feed_transmitter(sticks, 0.0);
+        let step = |controller: &mut Controller, time_point: f32| {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+        };
+        step(&mut controller, 0.6);
+        assert_eq!(controller.mode(), FlightMode::ReturnToHome);
+
+        controller.feed_transmitter(sticks, 0.7);
+        step(&mut controller, 0.7);
+        assert_eq!(controller.mode(), FlightMode::PositionHold);
+        assert_eq!(controller.target_altitude(), controller.altitude());
+        assert_eq!(controller.arm_state(), ArmState::Armed);
+    }
+
+    #[test]
+    fn dropout_while_disarmed_leaves_the_mode_alone() {
+        let mut controller = Controller::new();
+        controller.set_failsafe_timeout(0.5);
+        controller.set_mode(FlightMode::PositionHold);
+        controller.arm();
+        controller.feed_gps(GpsSample::new(Vector3::zeros(), 0.0));
+        let sticks = TransmitterState::new(0.0, 0.5, 0.5, 0.5);
+        controller.feed_transmitter(sticks, 0.0);
+        let step = |controller: &mut Controller, time_point: f32| {
+            let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point);
+            controller.calculate_motor_speeds(imu, &sticks);
+        };
+        step(&mut controller, 0.1);
+        controller.disarm();
+
+        step(&mut controller, 1.0);
+        assert_eq!(controller.mode(), FlightMode::PositionHold);
+        controller.feed_transmitter(sticks, 1.1);
+        step(&mut controller, 1.1);
+        controller.arm();
+        step(&mut controller, 1.2);
+        assert_eq!(controller.mode(), FlightMode::PositionHold);
+        // The throttle stick, not the return, moves the altitude target.
+        assert!(controller.target_altitude() <= 0.0);
+    }
+
+    #[test]
+    fn waypoints_steer_position_hold() {
+        let mut controller = Controller::new();
+        controller.arm();
+        controller.set_mode(FlightMode::PositionHold);
+        controller.feed_gps(GpsSample::new(Vector3::zeros(), 0.0));
+        assert!(!controller.mission_complete());
+        controller
+            .push_waypoint(Waypoint::new(Vector3::new(0.0, 0.0, 10.0), 0.0))
+            .unwrap();
+        let centered = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+        let level = Vector3::new(0.0, 9.81, 0.0);
+        for i in 0..10 {
+            let imu = IMUDataPoint::new(Vector3::zeros(), level, i as f32 * 0.01);
+            controller.calculate_motor_speeds(imu, &centered);
+        }
+        // Heading east, the target creeps along at cruise speed.
+        let target = controller.target_position().unwrap();
+        assert!((target.z - 0.18).abs() < 1e-4);
+        assert!(controller.rate_setpoint().x > 0.0);
+        assert_eq!(
+            controller.current_waypoint().map(|w| w.position),
+            Some(Vector3::new(0.0, 0.0, 10.0))
+        );
+
+        controller.clear_waypoints();
+        let imu = IMUDataPoint::new(Vector3::zeros(), level, 0.1);
+        controller.calculate_motor_speeds(imu, &centered);
+        assert!(controller.current_waypoint().is_none());
+        assert!(controller.target_position().unwrap().z.abs() < 0.01);
     }
 }
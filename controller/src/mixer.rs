@@ -0,0 +1,362 @@
+use nalgebra::{RealField, SMatrix, Vector3, Vector4};
+
+use crate::cast;
+
+/// Relation between a normalized motor command and the normalized thrust the
+/// motor produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ThrustCurve {
+    #[default]
+    Linear,
+    /// Thrust grows with the square of the command, like a fixed-pitch prop
+    /// spinning at an rpm proportional to it.
+    Quadratic,
+}
+impl ThrustCurve {
+    pub fn map<T: RealField + Copy>(&self, command: T) -> T {
+        match self {
+            ThrustCurve::Linear => command,
+            ThrustCurve::Quadratic => command * command,
+        }
+    }
+
+    /// Command needed to produce `thrust`. Negative thrust maps to zero.
+    pub fn inverse<T: RealField + Copy>(&self, thrust: T) -> T {
+        match self {
+            ThrustCurve::Linear => thrust,
+            ThrustCurve::Quadratic => {
+                if thrust > T::zero() {
+                    thrust.sqrt()
+                } else {
+                    T::zero()
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Saturation {
+    /// Clamp each motor to 0..1 on its own, distorting the torque produced.
+    Clip,
+    /// Shift all motors by a common offset, scaling the attitude part down
+    /// first if it alone spans more than the motor range, so the differential
+    /// between motors survives.
+    AirMode,
+}
+
+/// Gram determinant, relative to the healthy frame, below which the remaining
+/// motors are taken to have lost control of an axis.
+const DEGRADED_RANK_THRESHOLD: f64 = 1e-4;
+
+/// Which way a prop turns, seen from above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropSpin {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Maps a `[throttle, roll, pitch, yaw]` command onto `N` motors, one row per
+/// motor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotorMixer<T = f32, const N: usize = 4> {
+    rows: [[T; 4]; N],
+}
+impl<T: RealField + Copy, const N: usize> MotorMixer<T, N> {
+    pub fn new(rows: [[T; 4]; N]) -> Self {
+        Self { rows }
+    }
+
+    pub fn rows(&self) -> &[[T; 4]; N] {
+        &self.rows
+    }
+
+    /// Mixer for a frame with the motors at `motors`, measured from the center
+    /// of mass in the body frame: x forward, y up and z right, in any unit.
+    /// Each motor's roll and pitch share follows from its lever arm, scaled
+    /// so the longest arm component gets 1, and its yaw share from the way
+    /// its prop turns: clockwise props push the frame counterclockwise, which
+    /// is positive yaw. The unit corners give `quad_x`.
+    pub fn from_geometry(motors: [(Vector3<T>, PropSpin); N]) -> Self {
+        let reach = motors.iter().fold(T::zero(), |reach, (position, _)| {
+            reach.max(position.x.abs()).max(position.z.abs())
+        });
+        let scale = if reach > T::zero() {
+            T::one() / reach
+        } else {
+            T::zero()
+        };
+        Self::new(motors.map(|(position, spin)| {
+            let yaw = match spin {
+                PropSpin::Clockwise => T::one(),
+                PropSpin::CounterClockwise => -T::one(),
+            };
+            // Thrust up at (x, y, z) rolls by -z and pitches by x.
+            [cast(0.5), -position.z * scale, position.x * scale, yaw]
+        }))
+    }
+
+    pub(crate) fn mix(&self, command: [T; 4]) -> [T; N] {
+        self.rows.map(|row| {
+            row.iter()
+                .zip(command.iter())
+                .fold(T::zero(), |acc, (coefficient, value)| {
+                    acc + *coefficient * *value
+                })
+        })
+    }
+
+    /// Mixes with air mode desaturation. Returns the motor outputs and whether
+    /// the attitude command had to be scaled down to fit.
+    pub(crate) fn mix_air_mode(&self, command: [T; 4]) -> ([T; N], bool) {
+        let throttle = self.rows.map(|row| row[0] * command[0]);
+        let mut attitude = self.mix([T::zero(), command[1], command[2], command[3]]);
+        let span = attitude.iter().fold(T::zero(), |acc, v| acc.max(*v))
+            - attitude.iter().fold(T::zero(), |acc, v| acc.min(*v));
+        let scaled = span > T::one();
+        if scaled {
+            attitude = attitude.map(|v| v / span);
+        }
+
+        let mut speeds = [T::zero(); N];
+        for (speed, (t, a)) in speeds.iter_mut().zip(throttle.iter().zip(attitude)) {
+            *speed = *t + a;
+        }
+        let highest = speeds.iter().copied().reduce(T::max).unwrap_or(T::zero());
+        let lowest = speeds.iter().copied().reduce(T::min).unwrap_or(T::zero());
+        let shift = if highest > T::one() {
+            T::one() - highest
+        } else if lowest < T::zero() {
+            -lowest
+        } else {
+            T::zero()
+        };
+        (speeds.map(|v| v + shift), scaled)
+    }
+
+    /// Spreads the command over the enabled motors only, with disabled ones
+    /// held at zero. Each motor's contribution to the four axes is recovered
+    /// from the mixer rows and the smallest outputs that still produce the
+    /// command are solved for. When the remaining motors can't control all
+    /// four axes, as on a quad with one motor out, yaw is dropped so thrust,
+    /// roll and pitch can still be held.
+    pub(crate) fn mix_degraded(&self, command: [T; 4], enabled: &[bool; N]) -> [T; N] {
+        let rows = SMatrix::<T, N, 4>::from_fn(|i, j| self.rows[i][j]);
+        let Some(gram_inverse) = (rows.transpose() * rows).try_inverse() else {
+            return [T::zero(); N];
+        };
+        let healthy = gram_inverse * rows.transpose();
+        let mut effectiveness = healthy;
+        for (i, enabled) in enabled.iter().enumerate() {
+            if !enabled {
+                effectiveness.set_column(i, &Vector4::zeros());
+            }
+        }
+        let command = Vector4::from(command);
+
+        let healthy_det = (healthy * healthy.transpose()).determinant();
+        let gram = effectiveness * effectiveness.transpose();
+        let controls_all_axes =
+            gram.determinant().abs() > healthy_det.abs() * cast(DEGRADED_RANK_THRESHOLD);
+        let outputs = if controls_all_axes {
+            gram.try_inverse()
+                .map(|inverse| effectiveness.transpose() * (inverse * command))
+        } else {
+            let effectiveness = effectiveness.fixed_rows::<3>(0);
+            (effectiveness * effectiveness.transpose())
+                .try_inverse()
+                .map(|inverse| effectiveness.transpose() * (inverse * command.fixed_rows::<3>(0)))
+        };
+        match outputs {
+            Some(outputs) => core::array::from_fn(|i| outputs[i]),
+            None => [T::zero(); N],
+        }
+    }
+}
+impl<T: RealField + Copy> MotorMixer<T, 4> {
+    /// Square X frame with motors in front left, front right, rear left, rear
+    /// right order. Positive roll lifts the left motors, positive pitch the
+    /// front ones, and positive yaw the front left / rear right diagonal.
+    pub fn quad_x() -> Self {
+        Self::new(
+            [
+                [0.5, 1.0, 1.0, 1.0],
+                [0.5, -1.0, 1.0, -1.0],
+                [0.5, 1.0, -1.0, -1.0],
+                [0.5, -1.0, -1.0, 1.0],
+            ]
+            .map(|row| row.map(cast)),
+        )
+    }
+}
+impl<T: RealField + Copy> MotorMixer<T, 6> {
+    /// Hexacopter with arms every 60 degrees starting 30 degrees right of the
+    /// nose, numbered clockwise seen from above: front right, right, rear
+    /// right, rear left, left, front left. Neighbouring props spin in opposite
+    /// directions.
+    pub fn hex_x() -> Self {
+        const SIN_60: f64 = 0.866_025_403_784_438_6;
+        Self::new(
+            [
+                [0.5, -0.5, SIN_60, -1.0],
+                [0.5, -1.0, 0.0, 1.0],
+                [0.5, -0.5, -SIN_60, -1.0],
+                [0.5, 0.5, -SIN_60, 1.0],
+                [0.5, 1.0, 0.0, -1.0],
+                [0.5, 0.5, SIN_60, 1.0],
+            ]
+            .map(|row| row.map(cast)),
+        )
+    }
+}
+impl<T: RealField + Copy> Default for MotorMixer<T, 4> {
+    fn default() -> Self {
+        Self::quad_x()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MotorPosition;
+
+    #[test]
+    fn thrust_curve_inverse_round_trips() {
+        for curve in [ThrustCurve::Linear, ThrustCurve::Quadratic] {
+            for command in [0.0_f32, 0.25, 0.5, 1.0] {
+                assert!((curve.inverse(curve.map(command)) - command).abs() < 1e-6);
+            }
+        }
+        assert_eq!(ThrustCurve::Quadratic.inverse(-0.2), 0.0);
+    }
+
+    #[test]
+    fn quad_x_throttle_is_even() {
+        let out = MotorMixer::<f32>::quad_x().mix([0.6, 0.0, 0.0, 0.0]);
+        assert_eq!(out, [0.3; 4]);
+    }
+
+    #[test]
+    fn unit_corners_give_quad_x() {
+        let spins = [
+            PropSpin::Clockwise,
+            PropSpin::CounterClockwise,
+            PropSpin::CounterClockwise,
+            PropSpin::Clockwise,
+        ];
+        let corners = MotorPosition::ALL.map(MotorPosition::unit_position);
+        let mixer = MotorMixer::from_geometry(core::array::from_fn(|i| (corners[i], spins[i])));
+        assert_eq!(mixer, MotorMixer::<f32>::quad_x());
+    }
+
+    #[test]
+    fn stretched_x_pitches_harder_than_it_rolls() {
+        // Arms 160 mm apart front to back, 120 mm side to side.
+        let mixer = MotorMixer::from_geometry([
+            (Vector3::new(0.08_f32, 0.0, -0.06), PropSpin::Clockwise),
+            (Vector3::new(0.08, 0.0, 0.06), PropSpin::CounterClockwise),
+            (Vector3::new(-0.08, 0.0, -0.06), PropSpin::CounterClockwise),
+            (Vector3::new(-0.08, 0.0, 0.06), PropSpin::Clockwise),
+        ]);
+        let rows = mixer.rows();
+        assert!((rows[0][1] - 0.75).abs() < 1e-6);
+        assert!((rows[0][2] - 1.0).abs() < 1e-6);
+        // Torques still cancel: a pure roll command leaves pitch and yaw
+        // alone.
+        let out = mixer.mix([0.0, 0.1, 0.0, 0.0]);
+        assert_eq!(out[0], out[2]);
+        assert_eq!(out[0], -out[1]);
+    }
+
+    #[test]
+    fn quad_x_roll_lifts_left_side() {
+        let out = MotorMixer::<f32>::quad_x().mix([0.0, 0.1, 0.0, 0.0]);
+        assert_eq!(out, [0.1, -0.1, 0.1, -0.1]);
+    }
+
+    #[test]
+    fn hex_x_is_balanced() {
+        let mixer = MotorMixer::<f32, 6>::hex_x();
+        for axis in 1..4 {
+            let mut command = [0.0; 4];
+            command[axis] = 1.0;
+            let total: f32 = mixer.mix(command).iter().sum();
+            assert!(total.abs() < 1e-6);
+        }
+        assert_eq!(mixer.mix([1.0, 0.0, 0.0, 0.0]), [0.5; 6]);
+    }
+
+    #[test]
+    fn air_mode_keeps_differential_at_low_throttle() {
+        let (out, scaled) = MotorMixer::<f32>::quad_x().mix_air_mode([0.2, 0.3, 0.0, 0.0]);
+        assert!(!scaled);
+        assert!((out[0] - out[1] - 0.6).abs() < 1e-6);
+        assert!(out.iter().all(|v| (0.0..=1.0).contains(v)));
+    }
+
+    #[test]
+    fn air_mode_scales_oversized_attitude() {
+        let (out, scaled) = MotorMixer::<f32>::quad_x().mix_air_mode([1.0, 0.8, 0.0, 0.0]);
+        assert!(scaled);
+        assert!((out[0] - 1.0).abs() < 1e-6);
+        assert!(out[1].abs() < 1e-6);
+    }
+
+    /// Axis commands the healthy frame would read back from `outputs`.
+    fn produced(mixer: &MotorMixer<f32, 4>, outputs: [f32; 4]) -> [f32; 4] {
+        let scale = [1.0, 0.25, 0.25, 0.25];
+        core::array::from_fn(|axis| {
+            let sum: f32 = (0..4).map(|m| mixer.rows()[m][axis] * outputs[m]).sum();
+            sum * scale[axis]
+        })
+    }
+
+    #[test]
+    fn degraded_quad_holds_attitude_without_yaw() {
+        let mixer = MotorMixer::<f32>::quad_x();
+        assert_eq!(
+            mixer.mix_degraded([0.5, 0.0, 0.0, 0.0], &[false, true, true, true]),
+            [0.0, 0.5, 0.5, 0.0]
+        );
+
+        let command = [0.6, 0.05, -0.03, 0.1];
+        let out = mixer.mix_degraded(command, &[true, true, false, true]);
+        assert_eq!(out[2], 0.0);
+        let produced = produced(&mixer, out);
+        for (produced, command) in produced.iter().zip(command).take(3) {
+            assert!((produced - command).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn degraded_matches_mix_when_healthy() {
+        let mixer = MotorMixer::<f32>::quad_x();
+        let command = [0.6, 0.05, -0.03, 0.1];
+        let out = mixer.mix_degraded(command, &[true; 4]);
+        for (degraded, healthy) in out.iter().zip(mixer.mix(command)) {
+            assert!((degraded - healthy).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn degraded_hex_keeps_yaw() {
+        let mixer = MotorMixer::<f32, 6>::hex_x();
+        let command = [0.6, 0.05, -0.03, 0.1];
+        let out = mixer.mix_degraded(command, &[true, true, true, false, true, true]);
+        assert_eq!(out[3], 0.0);
+        let healthy = mixer.mix_degraded(command, &[true; 6]);
+        // Whatever the healthy frame reads back from its own outputs, the
+        // degraded one must read back the same from its outputs.
+        let read = |outputs: [f32; 6], axis: usize| -> f32 {
+            mixer
+                .rows()
+                .iter()
+                .zip(outputs)
+                .map(|(row, output)| row[axis] * output)
+                .sum()
+        };
+        for axis in 0..4 {
+            assert!((read(out, axis) - read(healthy, axis)).abs() < 1e-4);
+        }
+    }
+}
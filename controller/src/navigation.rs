@@ -0,0 +1,262 @@
+use nalgebra::{RealField, UnitQuaternion, Vector3};
+
+use crate::attitude::GRAVITY;
+use crate::{cast, Waypoint};
+
+/// Most waypoints a mission can hold at once.
+pub(crate) const MAX_WAYPOINTS: usize = 32;
+/// Horizontal distance in meters from a waypoint that counts as having reached
+/// it.
+const ARRIVAL_RADIUS: f64 = 0.5;
+
+/// Position and velocity in the navigation frame (x north, y up, z east) from
+/// double integrating the accelerometer, held to GPS fixes with a second order
+/// complementary filter on each axis.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub(crate) struct PositionEstimator<T> {
+    position: Vector3<T>,
+    velocity: Vector3<T>,
+    /// Seconds over which GPS overrides the inertial estimate.
+    time_constant: T,
+}
+impl<T: RealField + Copy> PositionEstimator<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            position: Vector3::zeros(),
+            velocity: Vector3::zeros(),
+            time_constant: cast(2.0),
+        }
+    }
+
+    pub(crate) fn set_time_constant(&mut self, time_constant: T) {
+        self.time_constant = time_constant;
+    }
+
+    pub(crate) fn position(&self) -> Vector3<T> {
+        self.position
+    }
+
+    pub(crate) fn velocity(&self) -> Vector3<T> {
+        self.velocity
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.position = Vector3::zeros();
+        self.velocity = Vector3::zeros();
+    }
+
+    /// `orientation` rotates body vectors into the navigation frame. `accel`
+    /// is the accelerometer's specific force in the body frame.
+    pub(crate) fn update(&mut self, orientation: &UnitQuaternion<T>, accel: Vector3<T>, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+        let acceleration = orientation * accel - Vector3::y() * cast::<T>(GRAVITY);
+        self.velocity += acceleration * dt;
+        self.position += self.velocity * dt;
+    }
+
+    /// Blends in a GPS fix taken `dt` after the previous one. The first fix is
+    /// taken as is.
+    pub(crate) fn correct(&mut self, measured: Vector3<T>, dt: Option<T>) {
+        let Some(dt) = dt else {
+            self.position = measured;
+            self.velocity = Vector3::zeros();
+            return;
+        };
+        if dt <= T::zero() || self.time_constant <= T::zero() {
+            return;
+        }
+        let error = measured - self.position;
+        let tau = self.time_constant;
+        self.position += error * (cast::<T>(2.0) / tau * dt);
+        self.velocity += error * (dt / (tau * tau));
+    }
+}
+
+/// Moves `target` up to `step` meters horizontally towards `goal`.
+pub(crate) fn approach<T: RealField + Copy>(target: &mut Vector3<T>, goal: Vector3<T>, step: T) {
+    let mut to_goal = goal - *target;
+    to_goal.y = T::zero();
+    *target += to_goal.cap_magnitude(step);
+}
+
+/// Whether `position` is horizontally within reach of `goal`.
+pub(crate) fn arrived<T: RealField + Copy>(position: Vector3<T>, goal: Vector3<T>) -> bool {
+    let mut offset = goal - position;
+    offset.y = T::zero();
+    offset.norm() <= cast(ARRIVAL_RADIUS)
+}
+
+/// Queue of waypoints flown in order. The position target is dragged towards
+/// the current waypoint at a steady speed, so the position loop always chases
+/// a nearby point rather than one far away.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: nalgebra::Scalar + serde::Serialize",
+        deserialize = "T: nalgebra::Scalar + serde::Deserialize<'de>"
+    ))
+)]
+pub(crate) struct Mission<T> {
+    waypoints: [Option<Waypoint<T>>; MAX_WAYPOINTS],
+    first: usize,
+    len: usize,
+    /// Seconds spent within reach of the current waypoint.
+    held: T,
+    complete: bool,
+}
+impl<T: RealField + Copy> Mission<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            waypoints: [None; MAX_WAYPOINTS],
+            first: 0,
+            len: 0,
+            held: T::zero(),
+            complete: false,
+        }
+    }
+
+    pub(crate) fn push(&mut self, waypoint: Waypoint<T>) -> Result<(), Waypoint<T>> {
+        if self.len == MAX_WAYPOINTS {
+            return Err(waypoint);
+        }
+        self.waypoints[(self.first + self.len) % MAX_WAYPOINTS] = Some(waypoint);
+        self.len += 1;
+        self.complete = false;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn current(&self) -> Option<&Waypoint<T>> {
+        if self.len == 0 {
+            return None;
+        }
+        self.waypoints[self.first].as_ref()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Moves `target` up to `speed * dt` towards the current waypoint and
+    /// advances once `position` has stayed within reach of it for its hold
+    /// time. Only the horizontal parts are used.
+    pub(crate) fn step(&mut self, position: Vector3<T>, target: &mut Vector3<T>, speed: T, dt: T) {
+        let Some(waypoint) = self.current().copied() else {
+            return;
+        };
+        approach(target, waypoint.position, speed * dt);
+        if !arrived(position, waypoint.position) {
+            return;
+        }
+        self.held += dt;
+        if self.held >= waypoint.hold_time {
+            self.waypoints[self.first] = None;
+            self.first = (self.first + 1) % MAX_WAYPOINTS;
+            self.len -= 1;
+            self.held = T::zero();
+            self.complete = self.len == 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_fix_sets_position() {
+        let mut estimator = PositionEstimator::new();
+        estimator.correct(Vector3::new(3.0_f32, 1.0, -2.0), None);
+        assert_eq!(estimator.position(), Vector3::new(3.0, 1.0, -2.0));
+    }
+
+    #[test]
+    fn gps_bounds_accel_bias_drift() {
+        let mut estimator = PositionEstimator::new();
+        let level = UnitQuaternion::identity();
+        let biased = Vector3::new(0.1, GRAVITY as f32, -0.1);
+        estimator.correct(Vector3::zeros(), None);
+        for _ in 0..6000 {
+            estimator.update(&level, biased, 0.01);
+            estimator.correct(Vector3::zeros(), Some(0.01));
+        }
+        // A constant bias b settles at offsets of b·τ² in position and 2b·τ in
+        // velocity, instead of growing without bound.
+        assert!(estimator.position().norm() < 0.6);
+        assert!(estimator.velocity().norm() < 0.6);
+    }
+
+    #[test]
+    fn follows_moving_fixes() {
+        let mut estimator = PositionEstimator::new();
+        let level = UnitQuaternion::identity();
+        let still = Vector3::new(0.0, GRAVITY as f32, 0.0);
+        estimator.correct(Vector3::zeros(), None);
+        for i in 1..=2000 {
+            estimator.update(&level, still, 0.01);
+            estimator.correct(Vector3::new(i as f32 * 0.01, 0.0, 0.0), Some(0.01));
+        }
+        assert!((estimator.velocity().x - 1.0).abs() < 0.05);
+        assert!((estimator.position().x - 20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn mission_flies_waypoints_in_order() {
+        let mut mission = Mission::new();
+        mission
+            .push(Waypoint::new(Vector3::new(2.0_f32, 0.0, 0.0), 0.5))
+            .unwrap();
+        mission
+            .push(Waypoint::new(Vector3::new(2.0, 0.0, 2.0), 0.0))
+            .unwrap();
+        // A perfect position loop: the drone is wherever the target is.
+        let mut target = Vector3::zeros();
+        let mut reached = [None; 2];
+        for step in 0..1000 {
+            let waypoints_left = mission.len();
+            mission.step(target, &mut target, 1.0, 0.01);
+            if mission.len() < waypoints_left {
+                reached[2 - waypoints_left] = Some(step);
+            }
+        }
+        // 1.5 m to come within reach, then half a second of hold.
+        assert!((198..=203).contains(&reached[0].unwrap()));
+        // Another 1.5 m with no hold.
+        assert!((348..=356).contains(&reached[1].unwrap()));
+        assert!(mission.complete());
+        assert!(mission.current().is_none());
+        assert!((target - Vector3::new(2.0, 0.0, 1.5)).norm() < 0.02);
+    }
+
+    #[test]
+    fn mission_rejects_waypoints_past_capacity() {
+        let mut mission = Mission::new();
+        let waypoint = Waypoint::new(Vector3::<f32>::zeros(), 0.0);
+        for _ in 0..MAX_WAYPOINTS {
+            assert!(mission.push(waypoint).is_ok());
+        }
+        assert_eq!(mission.push(waypoint), Err(waypoint));
+        mission.clear();
+        assert_eq!(mission.len(), 0);
+        assert!(!mission.complete());
+    }
+}
@@ -0,0 +1,154 @@
+use nalgebra::{RealField, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PidGains<T = f32> {
+    pub kp: T,
+    pub ki: T,
+    pub kd: T,
+}
+impl<T> PidGains<T> {
+    pub fn new(kp: T, ki: T, kd: T) -> Self {
+        Self { kp, ki, kd }
+    }
+}
+
+/// What each term of the rate loop contributed to the last step's torque.
+/// Indexed like the rotation vectors: x = roll, y = yaw, z = pitch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PidTerms<T = f32> {
+    pub p: Vector3<T>,
+    pub i: Vector3<T>,
+    pub d: Vector3<T>,
+    /// Feed-forward on the setpoint's rate of change.
+    pub ff: Vector3<T>,
+}
+impl<T: RealField + Copy> PidTerms<T> {
+    /// The torque the terms add up to.
+    pub fn sum(&self) -> Vector3<T> {
+        self.p + self.i + self.d + self.ff
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Pid<T> {
+    gains: PidGains<T>,
+    integral: T,
+    i_limit: Option<T>,
+    /// P, I and D contributions to the last output.
+    terms: [T; 3],
+}
+impl<T: RealField + Copy> Pid<T> {
+    pub(crate) fn new(gains: PidGains<T>) -> Self {
+        Self {
+            gains,
+            integral: T::zero(),
+            i_limit: None,
+            terms: [T::zero(); 3],
+        }
+    }
+
+    pub(crate) fn set_gains(&mut self, gains: PidGains<T>) {
+        self.gains = gains;
+    }
+
+    pub(crate) fn set_i_limit(&mut self, i_limit: T) {
+        self.i_limit = Some(i_limit);
+        self.integral = self.integral.clamp(-i_limit, i_limit);
+    }
+
+    pub(crate) fn integral(&self) -> T {
+        self.integral
+    }
+
+    pub(crate) fn terms(&self) -> [T; 3] {
+        self.terms
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.integral = T::zero();
+        self.terms = [T::zero(); 3];
+    }
+
+    /// The D term acts on the change in the measurement rather than the error, so a
+    /// step in the setpoint doesn't kick the output. While the output is saturated
+    /// the integral may only shrink, never grow. `pd_scale` attenuates the P
+    /// and D terms only, `i_scale` how fast the integral moves.
+    pub(crate) fn update(
+        &mut self,
+        error: T,
+        measurement_delta: T,
+        dt: T,
+        saturated: bool,
+        pd_scale: T,
+        i_scale: T,
+    ) -> T {
+        let p = pd_scale * self.gains.kp * error;
+        let mut d = T::zero();
+        if dt > T::zero() {
+            if !saturated || error * self.integral < T::zero() {
+                self.integral += error * dt * i_scale;
+                if let Some(i_limit) = self.i_limit {
+                    self.integral = self.integral.clamp(-i_limit, i_limit);
+                }
+            }
+            d = -pd_scale * self.gains.kd * measurement_delta / dt;
+        }
+        let i = self.gains.ki * self.integral;
+        self.terms = [p, i, d];
+        p + i + d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integral_accumulates_over_dt() {
+        let mut pid = Pid::<f32>::new(PidGains::new(0.0, 2.0, 0.0));
+        pid.update(0.5, 0.0, 0.1, false, 1.0, 1.0);
+        let out = pid.update(0.5, 0.0, 0.1, false, 1.0, 1.0);
+        assert!((out - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn derivative_opposes_measurement_change() {
+        let mut pid = Pid::<f32>::new(PidGains::new(1.0, 0.0, 0.1));
+        let out = pid.update(0.2, 0.05, 0.01, false, 1.0, 1.0);
+        assert!((out - (0.2 - 0.1 * 0.05 / 0.01)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn setpoint_step_does_not_kick_derivative() {
+        let mut pid = Pid::<f32>::new(PidGains::new(0.0, 0.0, 0.1));
+        assert_eq!(pid.update(1.0, 0.0, 0.01, false, 1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn zero_dt_only_applies_proportional() {
+        let mut pid = Pid::<f32>::new(PidGains::new(3.0, 1.0, 1.0));
+        assert_eq!(pid.update(0.5, 0.0, 0.0, false, 1.0, 1.0), 1.5);
+    }
+
+    #[test]
+    fn integral_clamped_to_limit() {
+        let mut pid = Pid::<f32>::new(PidGains::new(0.0, 1.0, 0.0));
+        pid.set_i_limit(0.3);
+        for _ in 0..100 {
+            pid.update(1.0, 0.0, 0.1, false, 1.0, 1.0);
+        }
+        assert_eq!(pid.integral(), 0.3);
+    }
+
+    #[test]
+    fn saturation_stops_integral_growth() {
+        let mut pid = Pid::<f32>::new(PidGains::new(0.0, 1.0, 0.0));
+        pid.update(1.0, 0.0, 0.1, false, 1.0, 1.0);
+        pid.update(1.0, 0.0, 0.1, true, 1.0, 1.0);
+        assert!((pid.integral() - 0.1).abs() < 1e-6);
+        pid.update(-1.0, 0.0, 0.05, true, 1.0, 1.0);
+        assert!((pid.integral() - 0.05).abs() < 1e-6);
+    }
+}
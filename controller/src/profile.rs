@@ -0,0 +1,210 @@
+use nalgebra::RealField;
+
+use crate::{cast, Axis, ConfigError, Controller, Filter};
+
+/// Rate profiles a controller holds. Profile 0 is active from the start.
+pub const RATE_PROFILES: usize = 4;
+
+/// How the sticks feel: rates at full stick, expo and gyro filtering. Pilots
+/// keep a few and switch between them for different kinds of flying.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateProfile<T = f32> {
+    pub name: &'static str,
+    /// Rotation rates in rad/s at full stick in rate mode. The yaw rate
+    /// applies in every mode.
+    pub roll_rate: T,
+    pub pitch_rate: T,
+    pub yaw_rate: T,
+    /// From 0 (linear) to 1 (cubic).
+    pub roll_expo: T,
+    pub pitch_expo: T,
+    pub yaw_expo: T,
+    /// Gyro low-pass cutoff. Zero disables the filter.
+    pub gyro_lpf_hz: T,
+}
+impl<T: RealField + Copy> RateProfile<T> {
+    fn from_degrees(name: &'static str, rates: [f64; 3], expo: [f64; 3], gyro_lpf_hz: f64) -> Self {
+        let [roll_rate, pitch_rate, yaw_rate] = rates.map(|rate| cast::<T>(rate.to_radians()));
+        let [roll_expo, pitch_expo, yaw_expo] = expo.map(cast);
+        Self {
+            name,
+            roll_rate,
+            pitch_rate,
+            yaw_rate,
+            roll_expo,
+            pitch_expo,
+            yaw_expo,
+            gyro_lpf_hz: cast(gyro_lpf_hz),
+        }
+    }
+
+    /// 30°/s on every axis, linear, unfiltered: what a new controller flies.
+    pub fn standard() -> Self {
+        Self::from_degrees("standard", [30.0; 3], [0.0; 3], 0.0)
+    }
+
+    /// Gentle and soft around center, for smooth camera moves.
+    pub fn cinematic() -> Self {
+        Self::from_degrees("cinematic", [60.0, 60.0, 45.0], [0.5; 3], 40.0)
+    }
+
+    /// Fast flips and rolls, with less expo so the sticks stay direct.
+    pub fn acro() -> Self {
+        Self::from_degrees("acro", [360.0, 360.0, 270.0], [0.2; 3], 100.0)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let axes = [
+            (Axis::Roll, self.roll_rate, self.roll_expo),
+            (Axis::Pitch, self.pitch_rate, self.pitch_expo),
+            (Axis::Yaw, self.yaw_rate, self.yaw_expo),
+        ];
+        for (axis, rate, expo) in axes {
+            if !(rate > T::zero() && rate.is_finite()) {
+                return Err(ConfigError::Rate(axis));
+            }
+            if !(expo >= T::zero() && expo <= T::one()) {
+                return Err(ConfigError::Expo(axis));
+            }
+        }
+        if !(self.gyro_lpf_hz >= T::zero() && self.gyro_lpf_hz.is_finite()) {
+            return Err(ConfigError::Cutoff(Filter::GyroLowPass));
+        }
+        Ok(())
+    }
+}
+impl<T: RealField + Copy> Default for RateProfile<T> {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl<T: RealField + Copy, const N: usize, const H: usize> Controller<T, N, H> {
+    /// Stores `profile` in slot `index`. Takes effect right away if that's
+    /// the active slot.
+    pub fn set_rate_profile(
+        &mut self,
+        index: usize,
+        profile: RateProfile<T>,
+    ) -> Result<(), ConfigError> {
+        profile.validate()?;
+        *self
+            .profiles
+            .get_mut(index)
+            .ok_or(ConfigError::Profile(index))? = profile;
+        if index == self.profile {
+            self.gyro_lpf.set_cutoff_hz(profile.gyro_lpf_hz);
+        }
+        Ok(())
+    }
+
+    pub fn rate_profile(&self, index: usize) -> Option<&RateProfile<T>> {
+        self.profiles.get(index)
+    }
+
+    /// Switches to the profile in slot `index` between two loops. Integrals
+    /// and filter states carry over, only the feed-forward forgets the last
+    /// setpoint so the jump in rates doesn't kick the motors.
+    pub fn select_profile(&mut self, index: usize) -> Result<(), ConfigError> {
+        let profile = *self
+            .profiles
+            .get(index)
+            .ok_or(ConfigError::Profile(index))?;
+        if index != self.profile {
+            self.profile = index;
+            self.gyro_lpf.set_cutoff_hz(profile.gyro_lpf_hz);
+            self.last_setpoint = None;
+        }
+        Ok(())
+    }
+
+    /// Index of the active profile.
+    pub fn selected_profile(&self) -> usize {
+        self.profile
+    }
+
+    pub(crate) fn active_profile(&self) -> &RateProfile<T> {
+        &self.profiles[self.profile]
+    }
+
+    /// Stores a copy of the active profile with `edit` applied, if it's still
+    /// valid, in place of the one in the active slot. On an error the profile
+    /// is left as it was.
+    pub(crate) fn edit_active_profile(
+        &mut self,
+        edit: impl FnOnce(&mut RateProfile<T>),
+    ) -> Result<(), ConfigError> {
+        let mut profile = *self.active_profile();
+        edit(&mut profile);
+        self.set_rate_profile(self.profile, profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::{FlightMode, IMUDataPoint, PidGains, TransmitterState};
+
+    fn full_roll_rate(controller: &mut Controller, time_point: f32) -> f32 {
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point);
+        controller.calculate_motor_speeds(imu, &TransmitterState::new(0.5, 0.5, 0.5, 1.0));
+        controller.rate_setpoint().x
+    }
+
+    #[test]
+    fn switching_profiles_changes_rates_and_keeps_integrals() {
+        let gains = PidGains::new(0.1, 0.5, 0.0);
+        let mut controller = Controller::with_gains(gains, gains, gains);
+        controller.set_mode(FlightMode::Rate);
+        controller.arm();
+        controller.set_rate_profile(1, RateProfile::acro()).unwrap();
+        let standard = full_roll_rate(&mut controller, 0.0);
+        assert!((standard - 30.0_f32.to_radians()).abs() < 1e-5);
+        full_roll_rate(&mut controller, 0.01);
+        let integral = controller.integral();
+
+        controller.select_profile(1).unwrap();
+        assert_eq!(controller.integral(), integral);
+        let acro = full_roll_rate(&mut controller, 0.02);
+        assert!((acro - 360.0_f32.to_radians()).abs() < 1e-4);
+        assert_eq!(controller.selected_profile(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_profiles_and_slots() {
+        let mut controller = Controller::new();
+        let mut profile = RateProfile::cinematic();
+        profile.pitch_rate = 0.0;
+        assert_eq!(
+            controller.set_rate_profile(1, profile),
+            Err(ConfigError::Rate(Axis::Pitch))
+        );
+        assert_eq!(
+            controller.select_profile(RATE_PROFILES),
+            Err(ConfigError::Profile(RATE_PROFILES))
+        );
+        assert_eq!(controller.selected_profile(), 0);
+    }
+
+    #[test]
+    fn setters_validate_the_active_profile() {
+        let mut controller = Controller::new();
+        controller.set_rate_profile(1, RateProfile::acro()).unwrap();
+        controller.select_profile(1).unwrap();
+        assert_eq!(
+            controller.set_expo(0.2, 1.5, 0.2),
+            Err(ConfigError::Expo(Axis::Pitch))
+        );
+        assert_eq!(
+            controller.set_gyro_lpf_hz(-1.0),
+            Err(ConfigError::Cutoff(Filter::GyroLowPass))
+        );
+        assert_eq!(controller.rate_profile(1), Some(&RateProfile::acro()));
+
+        controller.set_gyro_lpf_hz(150.0).unwrap();
+        assert_eq!(controller.rate_profile(1).unwrap().gyro_lpf_hz, 150.0);
+        assert_eq!(controller.rate_profile(0), Some(&RateProfile::standard()));
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::vec::Vec;
+
+use nalgebra::RealField;
+
+use crate::blackbox::max_frame_len;
+use crate::{BlackboxEncoder, BlackboxError, BlackboxFrame, Controller};
+
+/// Keeps every control loop's frame in memory, for runs where there's a heap
+/// and no reason to squeeze the log into a fixed buffer. Frames are the same
+/// ones `Controller::blackbox_frame` returns, at full precision.
+pub struct FlightRecorder<T = f32, const N: usize = 4> {
+    frames: Vec<BlackboxFrame<T, N>>,
+}
+impl<T: RealField + Copy, const N: usize> FlightRecorder<T, N> {
+    pub fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    /// Records the loop `controller` just ran.
+    pub fn record<const H: usize>(&mut self, controller: &Controller<T, N, H>) {
+        self.push(controller.blackbox_frame());
+    }
+
+    pub fn push(&mut self, frame: BlackboxFrame<T, N>) {
+        self.frames.push(frame);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Recorded frames, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &BlackboxFrame<T, N>> {
+        self.frames.iter()
+    }
+
+    /// Removes and returns every recorded frame, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = BlackboxFrame<T, N>> + '_ {
+        self.frames.drain(..)
+    }
+
+    /// Encodes every recorded frame into a blackbox log, which
+    /// `BlackboxDecoder` reads back. The recorder is left as it was.
+    pub fn export(&self) -> Result<Vec<u8>, BlackboxError> {
+        let mut encoder = BlackboxEncoder::new();
+        let mut log = Vec::new();
+        for frame in &self.frames {
+            let start = log.len();
+            log.resize(start + max_frame_len(N), 0);
+            let len = encoder.encode(frame, &mut log[start..])?;
+            log.truncate(start + len);
+        }
+        Ok(log)
+    }
+}
+impl<T: RealField + Copy, const N: usize> Default for FlightRecorder<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::{BlackboxDecoder, IMUDataPoint, PidGains, TransmitterState};
+
+    #[test]
+    fn records_every_loop_and_exports() {
+        let gains = PidGains::new(0.1, 0.5, 0.01);
+        let mut controller = Controller::with_gains(gains, gains, gains);
+        controller.arm();
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 0.8);
+        let mut recorder = FlightRecorder::new();
+        for i in 0..200 {
+            let t = i as f32 * 0.002;
+            let gyro = Vector3::new((t * 30.0).sin(), 0.0, 0.1);
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), t);
+            controller.calculate_motor_speeds(imu, &sticks);
+            recorder.record(&controller);
+        }
+        assert_eq!(recorder.len(), 200);
+        assert_eq!(recorder.iter().last(), Some(&controller.blackbox_frame()));
+
+        let log = recorder.export().unwrap();
+        let decoded = BlackboxDecoder::<f32>::new(&log);
+        for (decoded, original) in decoded.zip(recorder.iter()) {
+            assert!((decoded.unwrap().gyro - original.gyro).amax() <= 5e-4);
+        }
+
+        assert_eq!(recorder.drain().count(), 200);
+        assert!(recorder.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use nalgebra::RealField;
+
+use crate::{cast, constrain, TransmitterError, TransmitterState};
+
+/// Bytes in an SBUS frame: start byte, 22 bytes of channel data, flags and
+/// end byte.
+pub const SBUS_FRAME_LEN: usize = 25;
+const START_BYTE: u8 = 0x0F;
+pub(crate) const CHANNELS: usize = 16;
+const CHANNEL_BITS: usize = 11;
+
+const FLAG_CH17: u8 = 1 << 0;
+const FLAG_CH18: u8 = 1 << 1;
+const FLAG_FRAME_LOST: u8 = 1 << 2;
+const FLAG_FAILSAFE: u8 = 1 << 3;
+
+/// FrSky's channel values at -100% and +100% stick.
+const DEFAULT_LOW: u16 = 172;
+const DEFAULT_HIGH: u16 = 1811;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbusError {
+    /// The buffer wasn't `SBUS_FRAME_LEN` bytes long.
+    Length(usize),
+    StartByte(u8),
+    EndByte(u8),
+    /// The receiver dropped this frame and repeated the last good channels.
+    FrameLost,
+    /// The receiver has lost the transmitter and is sending its failsafe
+    /// channels.
+    Failsafe,
+    Transmitter(TransmitterError),
+}
+impl core::fmt::Display for SbusError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SbusError::Length(len) => {
+                write!(
+                    f,
+                    "SBUS frame is {} bytes, expected {}",
+                    len, SBUS_FRAME_LEN
+                )
+            }
+            SbusError::StartByte(byte) => write!(f, "bad SBUS start byte {:#04x}", byte),
+            SbusError::EndByte(byte) => write!(f, "bad SBUS end byte {:#04x}", byte),
+            SbusError::FrameLost => write!(f, "receiver lost an SBUS frame"),
+            SbusError::Failsafe => write!(f, "receiver is in failsafe"),
+            SbusError::Transmitter(err) => err.fmt(f),
+        }
+    }
+}
+
+/// One decoded SBUS frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SbusFrame {
+    /// Proportional channels, 11 bits each.
+    pub channels: [u16; CHANNELS],
+    /// Digital channels 17 and 18.
+    pub ch17: bool,
+    pub ch18: bool,
+    pub frame_lost: bool,
+    pub failsafe: bool,
+}
+impl SbusFrame {
+    /// Decodes a frame, checking only its framing. The frame lost and
+    /// failsafe flags are left to the caller, or to `StickMap`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, SbusError> {
+        let frame: &[u8; SBUS_FRAME_LEN] = bytes
+            .try_into()
+            .map_err(|_| SbusError::Length(bytes.len()))?;
+        let &[start, ref data @ .., flags, end] = frame;
+        if start != START_BYTE {
+            return Err(SbusError::StartByte(start));
+        }
+        // SBUS2 receivers cycle the high nibble of the end byte through the
+        // telemetry slots.
+        if end != 0x00 && end & 0x0F != 0x04 {
+            return Err(SbusError::EndByte(end));
+        }
+
+        Ok(Self {
+            channels: unpack_channels(data),
+            ch17: flags & FLAG_CH17 != 0,
+            ch18: flags & FLAG_CH18 != 0,
+            frame_lost: flags & FLAG_FRAME_LOST != 0,
+            failsafe: flags & FLAG_FAILSAFE != 0,
+        })
+    }
+}
+
+/// Unpacks 16 11-bit channels stored least significant bit first, as both
+/// SBUS and CRSF send them. Missing bytes read as zero.
+pub(crate) fn unpack_channels(data: &[u8]) -> [u16; CHANNELS] {
+    let mut channels = [0; CHANNELS];
+    let mut bits: u32 = 0;
+    let mut len = 0;
+    let mut data = data.iter();
+    for channel in &mut channels {
+        while len < CHANNEL_BITS {
+            bits |= u32::from(*data.next().unwrap_or(&0)) << len;
+            len += 8;
+        }
+        *channel = (bits & ((1 << CHANNEL_BITS) - 1)) as u16;
+        bits >>= CHANNEL_BITS;
+        len -= CHANNEL_BITS;
+    }
+    channels
+}
+
+/// Which receiver channel drives a stick and the raw values at its two ends.
+/// `low` maps to 0 and `high` to 1, so swapping them reverses the channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelMap {
+    /// Zero based channel index.
+    pub channel: usize,
+    pub low: u16,
+    pub high: u16,
+}
+impl ChannelMap {
+    pub fn new(channel: usize, low: u16, high: u16) -> Self {
+        Self { channel, low, high }
+    }
+
+    fn value<T: RealField + Copy>(&self, channels: &[u16; CHANNELS]) -> T {
+        let raw: T = cast(channels[self.channel % CHANNELS] as f64);
+        let low: T = cast(self.low as f64);
+        let high: T = cast(self.high as f64);
+        constrain((raw - low) / (high - low))
+    }
+}
+
+/// Maps receiver channels onto the controller's sticks. SBUS and CRSF share
+/// the same channel range, so one map serves both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StickMap {
+    pub up_down: ChannelMap,
+    pub rotate_pos_neg: ChannelMap,
+    pub forward_backward: ChannelMap,
+    pub left_right: ChannelMap,
+}
+impl StickMap {
+    /// Refuses frames the receiver flagged as lost or failsafe, so the
+    /// controller's own failsafe takes over once they stop coming.
+    pub fn transmitter_state<T: RealField + Copy>(
+        &self,
+        frame: &SbusFrame,
+    ) -> Result<TransmitterState<T>, SbusError> {
+        if frame.failsafe {
+            return Err(SbusError::Failsafe);
+        }
+        if frame.frame_lost {
+            return Err(SbusError::FrameLost);
+        }
+        self.sticks(&frame.channels).map_err(SbusError::Transmitter)
+    }
+
+    pub(crate) fn sticks<T: RealField + Copy>(
+        &self,
+        channels: &[u16; CHANNELS],
+    ) -> Result<TransmitterState<T>, TransmitterError> {
+        TransmitterState::try_new(
+            self.up_down.value(channels),
+            self.rotate_pos_neg.value(channels),
+            self.forward_backward.value(channels),
+            self.left_right.value(channels),
+        )
+    }
+}
+impl Default for StickMap {
+    /// AETR channel order with FrSky endpoints. Pushing the elevator stick
+    /// forward pitches nose down and right rudder yaws clockwise, so those two
+    /// are reversed.
+    fn default() -> Self {
+        Self {
+            left_right: ChannelMap::new(0, DEFAULT_LOW, DEFAULT_HIGH),
+            forward_backward: ChannelMap::new(1, DEFAULT_HIGH, DEFAULT_LOW),
+            up_down: ChannelMap::new(2, DEFAULT_LOW, DEFAULT_HIGH),
+            rotate_pos_neg: ChannelMap::new(3, DEFAULT_HIGH, DEFAULT_LOW),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sticks centered, throttle down, every other channel centered.
+    const IDLE: [u8; SBUS_FRAME_LEN] = [
+        0x0F, 0xE0, 0x03, 0x1F, 0x2B, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xE0, 0x03, 0x1F,
+        0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x00, 0x00,
+    ];
+    /// Full right roll, full back pitch, some throttle and left yaw, with
+    /// both digital channels on.
+    const FLYING: [u8; SBUS_FRAME_LEN] = [
+        0x0F, 0x13, 0x67, 0x05, 0x5E, 0xB1, 0xC4, 0x0A, 0x56, 0xB0, 0x82, 0x15, 0xE0, 0x03, 0x1F,
+        0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x03, 0x00,
+    ];
+    /// What the receiver sends once the link is gone.
+    const FAILSAFE: [u8; SBUS_FRAME_LEN] = [
+        0x0F, 0xE0, 0x03, 0x1F, 0x2B, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xE0, 0x03, 0x1F,
+        0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x0C, 0x00,
+    ];
+
+    #[test]
+    fn unpacks_eleven_bit_channels() {
+        let frame = SbusFrame::parse(&FLYING).unwrap();
+        assert_eq!(
+            frame.channels[..8],
+            [1811, 172, 1400, 600, 172, 172, 172, 172]
+        );
+        assert_eq!(frame.channels[8..], [992; 8]);
+        assert!(frame.ch17 && frame.ch18);
+        assert!(!frame.frame_lost && !frame.failsafe);
+    }
+
+    #[test]
+    fn default_map_reads_aetr_sticks() {
+        let map = StickMap::default();
+        let idle: TransmitterState = map
+            .transmitter_state(&SbusFrame::parse(&IDLE).unwrap())
+            .unwrap();
+        assert_eq!(idle.up_down(), 0.0);
+        assert!((idle.left_right() - 0.5).abs() < 1e-3);
+        assert!((idle.forward_backward() - 0.5).abs() < 1e-3);
+        assert!((idle.rotate_pos_neg() - 0.5).abs() < 1e-3);
+
+        let flying: TransmitterState = map
+            .transmitter_state(&SbusFrame::parse(&FLYING).unwrap())
+            .unwrap();
+        assert_eq!(flying.left_right(), 1.0);
+        // Stick back is nose up.
+        assert_eq!(flying.forward_backward(), 1.0);
+        assert!((flying.up_down() - 0.749).abs() < 1e-3);
+        // Stick left is counterclockwise.
+        assert!(flying.rotate_pos_neg() > 0.5);
+    }
+
+    #[test]
+    fn rejects_bad_framing_and_failsafe() {
+        assert_eq!(SbusFrame::parse(&IDLE[1..]), Err(SbusError::Length(24)));
+        let mut bad = IDLE;
+        bad[0] = 0x0E;
+        assert_eq!(SbusFrame::parse(&bad), Err(SbusError::StartByte(0x0E)));
+        bad = IDLE;
+        bad[24] = 0x01;
+        assert_eq!(SbusFrame::parse(&bad), Err(SbusError::EndByte(0x01)));
+        bad[24] = 0x14;
+        assert!(SbusFrame::parse(&bad).is_ok());
+
+        let frame = SbusFrame::parse(&FAILSAFE).unwrap();
+        assert!(frame.failsafe && frame.frame_lost);
+        assert_eq!(
+            StickMap::default().transmitter_state::<f32>(&frame),
+            Err(SbusError::Failsafe)
+        );
+    }
+}
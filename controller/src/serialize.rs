@@ -0,0 +1,217 @@
+use core::fmt;
+use core::marker::PhantomData;
+
+use nalgebra::RealField;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{IMUData, IMUDataPoint, MotorSpeeds, TransmitterError, TransmitterState};
+
+/// Unvalidated stick positions, so deserialized transmitter states go
+/// through the same range check as `TransmitterState::try_new`.
+#[derive(Deserialize)]
+pub(crate) struct Channels<T> {
+    up_down: T,
+    rotate_pos_neg: T,
+    left_right: T,
+    /// Older logs carry the field under its misspelled name.
+    #[serde(alias = "forwar_backward")]
+    forward_backward: T,
+}
+impl<T: RealField + Copy> TryFrom<Channels<T>> for TransmitterState<T> {
+    type Error = TransmitterError;
+
+    fn try_from(channels: Channels<T>) -> Result<Self, Self::Error> {
+        Self::try_new(
+            channels.up_down,
+            channels.rotate_pos_neg,
+            channels.forward_backward,
+            channels.left_right,
+        )
+    }
+}
+
+/// Motor speeds are written as a plain tuple of `N` values.
+impl<T: Serialize, const N: usize> Serialize for MotorSpeeds<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for motor in &self.motors {
+            tuple.serialize_element(&motor.speed)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, T, const N: usize> Deserialize<'de> for MotorSpeeds<T, N>
+where
+    T: RealField + Copy + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(N, SpeedsVisitor(PhantomData))
+    }
+}
+
+struct SpeedsVisitor<T, const N: usize>(PhantomData<T>);
+impl<'de, T, const N: usize> Visitor<'de> for SpeedsVisitor<T, N>
+where
+    T: RealField + Copy + Deserialize<'de>,
+{
+    type Value = MotorSpeeds<T, N>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} motor speeds", N)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut speeds = MotorSpeeds::new();
+        for i in 0..N {
+            let speed = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            speeds.set(i, speed);
+        }
+        Ok(speeds)
+    }
+}
+
+/// The IMU history is written as a sequence of the recorded samples, oldest
+/// first, and read back by adding them in order. Samples past the history
+/// length push the oldest ones out.
+impl<T: RealField + Copy + Serialize, const N: usize> Serialize for IMUData<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for n in (0..self.len).rev() {
+            if let Some(sample) = self.get_previous(n) {
+                seq.serialize_element(sample)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de, T, const N: usize> Deserialize<'de> for IMUData<T, N>
+where
+    T: RealField + Copy + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(HistoryVisitor(PhantomData))
+    }
+}
+
+struct HistoryVisitor<T, const N: usize>(PhantomData<T>);
+impl<'de, T, const N: usize> Visitor<'de> for HistoryVisitor<T, N>
+where
+    T: RealField + Copy + Deserialize<'de>,
+{
+    type Value = IMUData<T, N>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a sequence of IMU samples")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut history = IMUData::new();
+        while let Some(sample) = seq.next_element::<IMUDataPoint<T>>()? {
+            history.add_data_point(sample);
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::{Controller, ControllerState, IMUDataPoint, PidGains};
+
+    #[test]
+    fn imu_data_point_round_trips_through_postcard() {
+        let point = IMUDataPoint::new(
+            Vector3::new(0.1_f32, -0.25, 3.5),
+            Vector3::new(0.5, 9.7, -0.3),
+            12.345,
+        );
+        let mut buffer = [0; 64];
+        let bytes = postcard::to_slice(&point, &mut buffer).unwrap();
+        let decoded: IMUDataPoint = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(decoded, point);
+    }
+
+    #[test]
+    fn transmitter_state_round_trips_and_rejects_out_of_range() {
+        let state = TransmitterState::new(0.8_f32, 0.55, 0.45, 0.6);
+        let mut buffer = [0; 32];
+        let bytes = postcard::to_slice(&state, &mut buffer).unwrap();
+        let decoded: TransmitterState = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(decoded, state);
+
+        let bytes = postcard::to_slice(&[1.5_f32, 0.5, 0.5, 0.5], &mut buffer).unwrap();
+        assert!(postcard::from_bytes::<TransmitterState>(bytes).is_err());
+    }
+
+    #[test]
+    fn transmitter_state_reads_old_field_name() {
+        use serde::de::value::{Error, MapDeserializer};
+
+        let fields = [
+            ("up_down", 0.8_f32),
+            ("rotate_pos_neg", 0.55),
+            ("left_right", 0.6),
+            ("forwar_backward", 0.45),
+        ];
+        let state = TransmitterState::<f32>::deserialize(MapDeserializer::<_, Error>::new(
+            fields.into_iter(),
+        ))
+        .unwrap();
+        assert_eq!(state, TransmitterState::new(0.8, 0.55, 0.45, 0.6));
+        assert_eq!(state.forward_backward(), 0.45);
+    }
+
+    #[test]
+    fn controller_state_round_trips() {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.2, 0.1, 0.0),
+        );
+        controller.arm();
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 1.0);
+        let step = |controller: &mut Controller, i: usize| {
+            let t = i as f32 * 0.002;
+            let gyro = Vector3::new((t * 40.0).sin(), 0.1, 0.0);
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), t);
+            controller.calculate_motor_speeds(imu, &sticks).get(0)
+        };
+        for i in 0..20 {
+            step(&mut controller, i);
+        }
+
+        let mut buffer = [0; 2048];
+        let bytes = postcard::to_slice(&controller.snapshot(), &mut buffer).unwrap();
+        let decoded: ControllerState = postcard::from_bytes(bytes).unwrap();
+        let mut restored = Controller::with_gains(
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.2, 0.1, 0.0),
+        );
+        restored.restore(decoded);
+        for i in 20..40 {
+            assert_eq!(step(&mut restored, i), step(&mut controller, i));
+        }
+    }
+
+    #[test]
+    fn motor_speeds_round_trip() {
+        let mut speeds = MotorSpeeds::<f32, 4>::new();
+        speeds.set_front_left(0.25);
+        speeds.set_rear_right(1.0);
+        let mut buffer = [0; 32];
+        let bytes = postcard::to_slice(&speeds, &mut buffer).unwrap();
+        let decoded: MotorSpeeds = postcard::from_bytes(bytes).unwrap();
+        for i in 0..4 {
+            assert_eq!(decoded.get(i), speeds.get(i));
+        }
+    }
+}
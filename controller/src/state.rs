@@ -0,0 +1,166 @@
+use nalgebra::{RealField, Vector3};
+
+use crate::altitude::AltitudeEstimator;
+use crate::attitude::{ComplementaryFilter, HeadingFilter, Madgwick};
+use crate::filter::{LowPass, Notch};
+use crate::navigation::{Mission, PositionEstimator};
+use crate::pid::Pid;
+use crate::{
+    ArmState, BaroSample, Controller, FlightMode, IMUData, MotorSpeeds, ReturnPhase,
+    TransmitterState, DEFAULT_IMU_HISTORY,
+};
+
+/// Declares `ControllerState` with the listed controller fields, and the
+/// snapshot and restore methods that copy them out and back.
+macro_rules! controller_state {
+    ($($field:ident: $ty:ty,)*) => {
+        /// Everything the controller has learned or accumulated while running:
+        /// estimator and filter states, integrals, the IMU history, arming and
+        /// failsafe state. Restoring it puts a controller back exactly where the
+        /// snapshot was taken. The gyro filters keep the cutoffs configured now,
+        /// other filters and estimators come back with the time constants and
+        /// cutoffs they had then; the rest of the configuration is left alone.
+        #[derive(Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(
+            feature = "serde",
+            serde(bound(
+                serialize = "T: RealField + Copy + serde::Serialize",
+                deserialize = "T: RealField + Copy + serde::Deserialize<'de>"
+            ))
+        )]
+        pub struct ControllerState<
+            T = f32,
+            const N: usize = 4,
+            const H: usize = DEFAULT_IMU_HISTORY,
+        > {
+            $($field: $ty,)*
+        }
+
+        impl<T: RealField + Copy, const N: usize, const H: usize> Controller<T, N, H> {
+            pub fn snapshot(&self) -> ControllerState<T, N, H> {
+                ControllerState {
+                    $($field: self.$field.clone(),)*
+                }
+            }
+
+            pub fn restore(&mut self, state: ControllerState<T, N, H>) {
+                let notch_q = self.gyro_notch.q();
+                $(self.$field = state.$field;)*
+                self.gyro_lpf.set_cutoff_hz(self.active_profile().gyro_lpf_hz);
+                self.gyro_notch.set(self.gyro_notch_hz, notch_q);
+            }
+        }
+    };
+}
+
+controller_state! {
+    arm_state: ArmState,
+    killed: bool,
+    gesture_time: T,
+    armed_time: T,
+    motors: MotorSpeeds<T, N>,
+    imu: IMUData<T, H>,
+    pids: [Pid<T>; 3],
+    saturated: bool,
+    attitude: ComplementaryFilter<T>,
+    madgwick: Madgwick<T>,
+    heading: HeadingFilter<T>,
+    last_mag_time: Option<T>,
+    held_heading: Option<T>,
+    altitude: AltitudeEstimator<T>,
+    position: PositionEstimator<T>,
+    last_gps_time: Option<T>,
+    target_position: Option<Vector3<T>>,
+    mission: Mission<T>,
+    home: Option<Vector3<T>>,
+    return_phase: ReturnPhase,
+    altitude_pid: Pid<T>,
+    hover_throttle: T,
+    altitude_saturated: bool,
+    target_altitude: T,
+    baro_reference: Option<T>,
+    last_baro: Option<BaroSample<T>>,
+    rate_setpoint: Vector3<T>,
+    collective: T,
+    feedforward_lpf: LowPass<T>,
+    feedforward_term: Vector3<T>,
+    iterm_relax_lpf: LowPass<T>,
+    last_setpoint: Option<Vector3<T>>,
+    gyro_lpf: LowPass<T>,
+    gyro_notch: Notch<T>,
+    motor_hz: Option<T>,
+    gyro_bias: Vector3<T>,
+    dt: T,
+    last_frame: Option<(TransmitterState<T>, T)>,
+    in_failsafe: bool,
+    failsafe_mode: Option<FlightMode>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IMUDataPoint, PidGains};
+
+    use super::*;
+
+    /// Motor outputs over `steps` steps of a wobbling gyro and full roll.
+    fn fly(controller: &mut Controller, from: usize, steps: usize) -> [f32; 4] {
+        let sticks = TransmitterState::new(0.5, 0.5, 0.5, 1.0);
+        let mut last = [0.0; 4];
+        for i in from..from + steps {
+            let t = i as f32 * 0.002;
+            let gyro = Vector3::new((t * 40.0).sin(), 0.1, (t * 25.0).cos());
+            let imu = IMUDataPoint::new(gyro, Vector3::new(0.0, 9.81, 0.0), t);
+            let speeds = controller.calculate_motor_speeds(imu, &sticks);
+            last = [0, 1, 2, 3].map(|motor| speeds.get(motor));
+        }
+        last
+    }
+
+    fn tuned() -> Controller {
+        let mut controller = Controller::with_gains(
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.1, 0.5, 0.01),
+            PidGains::new(0.2, 0.1, 0.0),
+        );
+        controller.set_gyro_lpf_hz(80.0).unwrap();
+        controller.arm();
+        controller
+    }
+
+    #[test]
+    fn restore_replays_exactly() {
+        let mut controller = tuned();
+        fly(&mut controller, 0, 50);
+        let state = controller.snapshot();
+        let first = fly(&mut controller, 50, 50);
+        let integral = controller.integral();
+
+        controller.restore(state);
+        assert_eq!(fly(&mut controller, 50, 50), first);
+        assert_eq!(controller.integral(), integral);
+    }
+
+    #[test]
+    fn restore_keeps_configuration() {
+        let mut controller = tuned();
+        fly(&mut controller, 0, 50);
+        let state = controller.snapshot();
+        controller.set_max_angle(0.2);
+        controller.set_gyro_lpf_hz(150.0).unwrap();
+        controller.set_gyro_notch(180.0, 3.0);
+        controller.disarm();
+        controller.restore(state.clone());
+        assert_eq!(controller.max_angle(), 0.2);
+        assert_eq!(controller.rate_profile(0).unwrap().gyro_lpf_hz, 150.0);
+        assert_eq!(controller.arm_state(), ArmState::Armed);
+
+        // The gyro filters run at the new cutoffs, as if they'd been set
+        // after the restore.
+        let mut twin = tuned();
+        twin.restore(state);
+        twin.set_gyro_lpf_hz(150.0).unwrap();
+        twin.set_gyro_notch(180.0, 3.0);
+        assert_eq!(fly(&mut controller, 50, 50), fly(&mut twin, 50, 50));
+    }
+}
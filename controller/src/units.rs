@@ -0,0 +1,118 @@
+use nalgebra::{RealField, Vector3};
+
+use crate::{Channel, Controller, IMUDataPoint, TransmitterError, TransmitterState};
+
+/// An angle, or a vector of them, in radians.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Radians<V>(pub V);
+
+/// An angular rate, or a vector of them, in radians per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RadPerSec<V>(pub V);
+
+/// A stick position in 0..1. Only built through `new`, so holding one means
+/// the range was checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normalized<T>(T);
+impl<T: RealField + Copy> Normalized<T> {
+    /// `None` outside 0..1 or for NaN.
+    pub fn new(value: T) -> Option<Self> {
+        (value >= T::zero() && value <= T::one()).then_some(Self(value))
+    }
+
+    pub fn get(self) -> T {
+        self.0
+    }
+}
+impl<T: RealField + Copy> TryFrom<(T, Channel)> for Normalized<T> {
+    type Error = TransmitterError;
+
+    fn try_from((value, channel): (T, Channel)) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(TransmitterError::OutOfRange(channel))
+    }
+}
+
+impl<T: RealField + Copy> TransmitterState<T> {
+    /// Can't fail, the channels were range checked when they were made.
+    pub fn from_normalized(
+        up_down: Normalized<T>,
+        rotate_pos_neg: Normalized<T>,
+        forward_backward: Normalized<T>,
+        left_right: Normalized<T>,
+    ) -> Self {
+        Self::new(
+            up_down.get(),
+            rotate_pos_neg.get(),
+            forward_backward.get(),
+            left_right.get(),
+        )
+    }
+
+    /// Throttle, yaw, pitch and roll, in the order `from_normalized` takes
+    /// them.
+    pub fn normalized(&self) -> [Normalized<T>; 4] {
+        [
+            self.up_down(),
+            self.rotate_pos_neg(),
+            self.forward_backward(),
+            self.left_right(),
+        ]
+        .map(Normalized)
+    }
+}
+
+impl<T> IMUDataPoint<T> {
+    /// `accel` is in m/s², `time_point` in seconds.
+    pub fn from_rates(gyro: RadPerSec<Vector3<T>>, accel: Vector3<T>, time_point: T) -> Self {
+        Self::new(gyro.0, accel, time_point)
+    }
+}
+
+impl<T: RealField + Copy, const N: usize, const H: usize> Controller<T, N, H> {
+    /// The body rates the rate loop is currently chasing, indexed like the
+    /// gyro.
+    pub fn desired_rotation(&self) -> RadPerSec<Vector3<T>> {
+        RadPerSec(self.rate_setpoint())
+    }
+
+    pub fn set_max_tilt(&mut self, max_angle: Radians<T>) {
+        self.set_max_angle(max_angle.0);
+    }
+
+    /// Estimated roll and pitch.
+    pub fn tilt(&self) -> (Radians<T>, Radians<T>) {
+        let (roll, pitch) = self.attitude();
+        (Radians(roll), Radians(pitch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_checks_range() {
+        assert_eq!(Normalized::new(0.25).map(Normalized::get), Some(0.25));
+        assert_eq!(Normalized::new(1.5), None);
+        assert_eq!(Normalized::new(f32::NAN), None);
+        assert_eq!(
+            Normalized::try_from((-0.1, Channel::UpDown)),
+            Err(TransmitterError::OutOfRange(Channel::UpDown))
+        );
+    }
+
+    #[test]
+    fn typed_constructors_match_untyped() {
+        let [throttle, yaw, pitch, roll] =
+            [0.3, 0.5, 0.7, 1.0].map(|v| Normalized::new(v).unwrap());
+        let sticks = TransmitterState::from_normalized(throttle, yaw, pitch, roll);
+        assert_eq!(sticks, TransmitterState::new(0.3, 0.5, 0.7, 1.0));
+        assert_eq!(sticks.normalized(), [throttle, yaw, pitch, roll]);
+
+        let gyro = Vector3::new(0.1, 0.2, 0.3);
+        assert_eq!(
+            IMUDataPoint::from_rates(RadPerSec(gyro), Vector3::y(), 0.01),
+            IMUDataPoint::new(gyro, Vector3::y(), 0.01)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Symmetry of the quad X mixing, checked through `calculate_motor_speeds`
+//! for random stick positions. Outputs that clipped aren't expected to stay
+//! symmetric and are skipped.
+
+use controller::{Controller, FlightMode, IMUDataPoint, TransmitterState};
+use nalgebra::Vector3;
+use proptest::prelude::*;
+
+const EPSILON: f32 = 1e-5;
+
+/// Front left, front right, rear left and rear right motor outputs after two
+/// loops on a level, still drone, and whether the second one saturated.
+fn motors(throttle: f32, yaw: f32, pitch: f32, roll: f32) -> ([f32; 4], bool) {
+    let mut controller = Controller::new();
+    controller.set_mode(FlightMode::Rate);
+    controller.arm();
+    let sticks = TransmitterState::new(throttle, yaw, pitch, roll);
+    for time_point in [0.0, 0.002] {
+        let imu = IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), time_point);
+        controller.calculate_motor_speeds(imu, &sticks);
+    }
+    let speeds = controller.calculate_motor_speeds(
+        IMUDataPoint::new(Vector3::zeros(), Vector3::new(0.0, 9.81, 0.0), 0.004),
+        &sticks,
+    );
+    let outputs = [
+        speeds.get_front_left(),
+        speeds.get_front_right(),
+        speeds.get_rear_left(),
+        speeds.get_rear_right(),
+    ];
+    (outputs, controller.is_saturated())
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < EPSILON
+}
+
+proptest! {
+    #[test]
+    fn throttle_alone_is_even(throttle in 0.0f32..=1.0) {
+        let ([fl, fr, rl, rr], _) = motors(throttle, 0.5, 0.5, 0.5);
+        prop_assert!(close(fl, fr) && close(fl, rl) && close(fl, rr), "{:?}", [fl, fr, rl, rr]);
+    }
+
+    #[test]
+    fn roll_splits_left_from_right(throttle in 0.0f32..=1.0, roll in 0.0f32..=1.0) {
+        let ([fl, fr, rl, rr], saturated) = motors(throttle, 0.5, 0.5, roll);
+        prop_assume!(!saturated);
+        let ([hover, ..], _) = motors(throttle, 0.5, 0.5, 0.5);
+        prop_assert!(close(fl, rl) && close(fr, rr));
+        prop_assert!(close(fl - hover, hover - fr));
+        // Right stick lifts the left side.
+        prop_assert_eq!(fl > fr, roll > 0.5);
+    }
+
+    #[test]
+    fn pitch_splits_front_from_rear(throttle in 0.0f32..=1.0, pitch in 0.0f32..=1.0) {
+        let ([fl, fr, rl, rr], saturated) = motors(throttle, 0.5, pitch, 0.5);
+        prop_assume!(!saturated);
+        let ([hover, ..], _) = motors(throttle, 0.5, 0.5, 0.5);
+        prop_assert!(close(fl, fr) && close(rl, rr));
+        prop_assert!(close(fl - hover, hover - rl));
+        // Nose up lifts the front.
+        prop_assert_eq!(fl > rl, pitch > 0.5);
+    }
+
+    #[test]
+    fn yaw_splits_the_diagonals(throttle in 0.0f32..=1.0, yaw in 0.0f32..=1.0) {
+        let ([fl, fr, rl, rr], saturated) = motors(throttle, yaw, 0.5, 0.5);
+        prop_assume!(!saturated);
+        let ([hover, ..], _) = motors(throttle, 0.5, 0.5, 0.5);
+        prop_assert!(close(fl, rr) && close(fr, rl));
+        prop_assert!(close(fl - hover, hover - fr));
+        // Counterclockwise yaw speeds up the clockwise props, front left and
+        // rear right.
+        prop_assert_eq!(fl > fr, yaw > 0.5);
+    }
+}
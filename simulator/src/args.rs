@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use clap::Parser;
+
+/// Every command line flag the simulator takes. Bad values and unknown flags
+/// stop the sim with a message instead of falling back to defaults.
+#[derive(Parser, Debug)]
+#[command(about = "Drone flight controller simulator")]
+pub struct Args {
+    /// Rate in Hz of the fixed step that runs the controller and the physics.
+    #[arg(long, default_value_t = 500.0, value_parser = positive::<f64>)]
+    pub loop_hz: f64,
+    /// Number of drones, spawned side by side.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub drones: u32,
+    /// Run this many fixed steps without a window, print how each drone
+    /// fared and exit.
+    #[arg(long, value_name = "STEPS")]
+    pub headless: Option<u32>,
+
+    /// Meters above the ground plane's center the drones start at.
+    #[arg(long, default_value_t = 0.6)]
+    pub spawn_altitude: f32,
+    /// Starting roll right, pitch nose up and yaw counterclockwise, in
+    /// degrees.
+    #[arg(long, value_name = "ROLL,PITCH,YAW", default_value = "0,0,0", value_parser = vec3)]
+    pub spawn_attitude: Vec3,
+    /// Whether the drones start armed.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub armed: bool,
+    /// Mass in kg, in place of the one derived from the hover point.
+    #[arg(long, value_parser = positive::<f32>)]
+    pub mass: Option<f32>,
+    /// Offset in meters of the center of mass from the frame's center, along
+    /// the body axes (x left, y up, z forward).
+    #[arg(long, value_name = "X,Y,Z", default_value = "0,0,0", value_parser = vec3)]
+    pub center_of_mass: Vec3,
+
+    /// Mean wind in m/s.
+    #[arg(long, value_name = "X,Y,Z", default_value = "0,0,0", value_parser = vec3)]
+    pub wind: Vec3,
+    /// Turbulence intensity in m/s.
+    #[arg(long, default_value_t = 0.0, value_parser = nonnegative::<f32>)]
+    pub gust: f32,
+    #[arg(long, default_value_t = 0)]
+    pub wind_seed: u64,
+
+    /// Gyro white noise in rad/s.
+    #[arg(long, default_value_t = 0.01, value_parser = nonnegative::<f32>)]
+    pub gyro_noise: f32,
+    /// Accelerometer white noise in m/s².
+    #[arg(long, default_value_t = 0.05, value_parser = nonnegative::<f32>)]
+    pub accel_noise: f32,
+    /// Constant gyro offset in rad/s.
+    #[arg(long, value_name = "X,Y,Z", default_value = "0,0,0", value_parser = vec3)]
+    pub gyro_bias: Vec3,
+    /// Constant accelerometer offset in m/s².
+    #[arg(long, value_name = "X,Y,Z", default_value = "0,0,0", value_parser = vec3)]
+    pub accel_bias: Vec3,
+    /// Motor vibration amplitude and frequency in Hz.
+    #[arg(long, value_name = "AMPLITUDE,HZ", value_parser = vec2)]
+    pub vibration: Option<Vec2>,
+    #[arg(long, default_value_t = 0)]
+    pub imu_seed: u64,
+    /// IMU delay in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 0.0, value_parser = nonnegative::<f32>)]
+    pub imu_delay: f32,
+
+    /// Barometer noise in pascals. About 12 Pa is a meter.
+    #[arg(long, default_value_t = 2.0, value_parser = nonnegative::<f32>)]
+    pub baro_noise: f32,
+    /// Barometer delay in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 0.0, value_parser = nonnegative::<f32>)]
+    pub baro_delay: f32,
+    #[arg(long, default_value_t = 0)]
+    pub baro_seed: u64,
+
+    /// GPS position noise in meters.
+    #[arg(long, default_value_t = 0.5, value_parser = nonnegative::<f32>)]
+    pub gps_noise: f32,
+    /// Seconds between a fix being taken and it reaching the controller.
+    #[arg(long, default_value_t = 0.1, value_parser = nonnegative::<f32>)]
+    pub gps_latency: f32,
+    #[arg(long, default_value_t = 0)]
+    pub gps_seed: u64,
+
+    /// RON tuning file, reapplied whenever it changes on disk.
+    #[arg(long)]
+    pub tuning: Option<PathBuf>,
+    /// CSV telemetry log to write.
+    #[arg(long)]
+    pub log: Option<PathBuf>,
+    /// File to record the stick inputs and the wind to.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Recording to play the stick inputs and the wind back from.
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+    /// Directory screenshots and frame captures go to.
+    #[arg(long, default_value = "capture")]
+    pub capture_dir: PathBuf,
+}
+
+fn positive<T: std::str::FromStr + PartialOrd + Default>(value: &str) -> Result<T, String> {
+    match value.parse() {
+        Ok(v) if v > T::default() => Ok(v),
+        Ok(_) => Err("must be positive".to_string()),
+        Err(_) => Err(format!("`{}` isn't a number", value)),
+    }
+}
+
+fn nonnegative<T: std::str::FromStr + PartialOrd + Default>(value: &str) -> Result<T, String> {
+    match value.parse() {
+        Ok(v) if v >= T::default() => Ok(v),
+        Ok(_) => Err("can't be negative".to_string()),
+        Err(_) => Err(format!("`{}` isn't a number", value)),
+    }
+}
+
+/// `N` comma separated numbers.
+fn floats<const N: usize>(value: &str) -> Result<[f32; N], String> {
+    let parts = value
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("`{}` isn't a list of numbers", value))?;
+    parts
+        .try_into()
+        .map_err(|parts: Vec<f32>| format!("expected {} numbers, got {}", N, parts.len()))
+}
+
+fn vec3(value: &str) -> Result<Vec3, String> {
+    floats(value).map(Vec3::from_array)
+}
+
+fn vec2(value: &str) -> Result<Vec2, String> {
+    floats(value).map(Vec2::from_array)
+}
@@ -0,0 +1,51 @@
+//! Synthetic barometer, fed to every drone's controller each fixed step.
+
+use bevy::prelude::*;
+use controller::BaroSample;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::args::Args;
+use crate::delay::SensorDelays;
+use crate::DroneController;
+
+/// Standard atmosphere pressure at the ground plane, in pascals.
+const GROUND_PRESSURE: f32 = 101_325.0;
+
+#[derive(Resource)]
+pub struct Barometer {
+    /// Standard deviation of the reading in pascals. About 12 Pa is a meter.
+    pub noise: f32,
+    rng: StdRng,
+}
+impl Barometer {
+    /// From `--baro-noise=pascals` and `--baro-seed=n`.
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            noise: args.baro_noise,
+            rng: StdRng::seed_from_u64(args.baro_seed),
+        }
+    }
+
+    /// Inverse of the barometric formula the controller uses.
+    fn pressure(&mut self, height: f32) -> f32 {
+        let clean = GROUND_PRESSURE * (1.0 - height / 44330.0).powf(5.255);
+        let noise: f32 = StandardNormal.sample(&mut self.rng);
+        clean + self.noise * noise
+    }
+}
+
+pub fn feed_baro(
+    time: Res<Time>,
+    mut baro: ResMut<Barometer>,
+    mut drones: Query<(&Transform, &mut DroneController, &mut SensorDelays)>,
+) {
+    let now = time.elapsed_seconds();
+    for (transform, mut controller, mut delays) in &mut drones {
+        let sample = BaroSample::new(baro.pressure(transform.translation.y), now);
+        for sample in delays.baro.push(now, sample) {
+            controller.c.feed_baro(sample);
+        }
+    }
+}
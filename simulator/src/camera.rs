@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+
+use crate::Drone;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Sits behind the drone's heading.
+    Chase,
+    /// Circles the drone regardless of its heading.
+    Orbit,
+}
+
+#[derive(Component)]
+pub struct FollowCamera {
+    pub mode: CameraMode,
+    /// Horizontal distance from the drone.
+    pub distance: f32,
+    /// Height above the drone.
+    pub height: f32,
+    /// Rate in 1/s at which the camera closes the gap to its target pose.
+    pub smoothing: f32,
+    /// Orbit angular speed in rad/s.
+    pub orbit_speed: f32,
+    orbit_angle: f32,
+}
+impl Default for FollowCamera {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Chase,
+            distance: 1.0,
+            height: 0.4,
+            smoothing: 5.0,
+            orbit_speed: 0.3,
+            orbit_angle: 0.0,
+        }
+    }
+}
+
+pub fn toggle_camera_mode(keys: Res<ButtonInput<KeyCode>>, mut cameras: Query<&mut FollowCamera>) {
+    if !keys.just_pressed(KeyCode::KeyC) {
+        return;
+    }
+    for mut camera in &mut cameras {
+        camera.mode = match camera.mode {
+            CameraMode::Chase => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Chase,
+        };
+    }
+}
+
+pub fn follow_drone(
+    time: Res<Time>,
+    drones: Query<(&Transform, &Drone)>,
+    mut cameras: Query<(&mut Transform, &mut FollowCamera), Without<Drone>>,
+) {
+    // With several drones the camera sticks to the first one.
+    let Some((drone, _)) = drones.iter().find(|(_, Drone(index))| *index == 0) else {
+        return;
+    };
+    let dt = time.delta_seconds();
+    for (mut transform, mut camera) in &mut cameras {
+        // The drone model faces +z; only its heading matters for the chase
+        // view so the camera doesn't roll with it.
+        let direction = match camera.mode {
+            CameraMode::Chase => {
+                let forward = drone.rotation * Vec3::Z;
+                Vec3::new(forward.x, 0.0, forward.z)
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z)
+            }
+            CameraMode::Orbit => {
+                camera.orbit_angle += camera.orbit_speed * dt;
+                Vec3::new(camera.orbit_angle.sin(), 0.0, camera.orbit_angle.cos())
+            }
+        };
+        let target = drone.translation - direction * camera.distance + Vec3::Y * camera.height;
+        let blend = 1.0 - (-camera.smoothing * dt).exp();
+        transform.translation = transform.translation.lerp(target, blend);
+        let look = transform.looking_at(drone.translation, Vec3::Y).rotation;
+        transform.rotation = transform.rotation.slerp(look, blend);
+    }
+}
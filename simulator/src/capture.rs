@@ -0,0 +1,109 @@
+//! P saves a screenshot, O starts and stops saving every frame. Images are
+//! numbered PNGs in the capture directory, `--capture-dir=<path>` or
+//! `capture` by default, and `frames.csv` next to them records the simulated
+//! time and every drone's motor commands for each one.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::window::PrimaryWindow;
+
+use crate::args::Args;
+use crate::{Drone, DroneMotors};
+
+const SIDECAR_HEADER: &str = "file,time,drone,motor_fl,motor_fr,motor_rl,motor_rr";
+
+#[derive(Resource)]
+pub struct Capture {
+    dir: PathBuf,
+    screenshots: u32,
+    frames: u32,
+    recording: bool,
+    /// Opened along with the directory on the first capture.
+    sidecar: Option<BufWriter<File>>,
+}
+impl Capture {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            dir: args.capture_dir.clone(),
+            screenshots: 0,
+            frames: 0,
+            recording: false,
+            sidecar: None,
+        }
+    }
+
+    fn sidecar(&mut self) -> std::io::Result<&mut BufWriter<File>> {
+        if self.sidecar.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let mut writer = BufWriter::new(File::create(self.dir.join("frames.csv"))?);
+            writeln!(writer, "{}", SIDECAR_HEADER)?;
+            self.sidecar = Some(writer);
+        }
+        Ok(self.sidecar.as_mut().unwrap())
+    }
+
+    fn note(
+        &mut self,
+        file: &str,
+        time: f32,
+        drones: &Query<(&Drone, &DroneMotors)>,
+    ) -> std::io::Result<()> {
+        let sidecar = self.sidecar()?;
+        for (Drone(index), motors) in drones {
+            let [fl, fr, rl, rr] = motors.speeds();
+            writeln!(
+                sidecar,
+                "{},{},{},{},{},{},{}",
+                file, time, index, fl, fr, rl, rr
+            )?;
+        }
+        sidecar.flush()
+    }
+}
+
+pub fn capture_frames(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut capture: ResMut<Capture>,
+    mut screenshots: ResMut<ScreenshotManager>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    drones: Query<(&Drone, &DroneMotors)>,
+) {
+    if keys.just_pressed(KeyCode::KeyO) {
+        capture.recording = !capture.recording;
+        info!(
+            "frame capture {}",
+            if capture.recording {
+                "started"
+            } else {
+                "stopped"
+            }
+        );
+    }
+    let mut files = Vec::new();
+    if keys.just_pressed(KeyCode::KeyP) {
+        capture.screenshots += 1;
+        files.push(format!("screenshot-{:04}.png", capture.screenshots));
+    }
+    if capture.recording {
+        capture.frames += 1;
+        files.push(format!("frame-{:06}.png", capture.frames));
+    }
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    for file in files {
+        if let Err(err) = capture.note(&file, time.elapsed_seconds(), &drones) {
+            error!("can't write to {}: {}", capture.dir.display(), err);
+            capture.recording = false;
+            return;
+        }
+        // Only one screenshot can be pending per frame; a screenshot taken
+        // while recording is the same image as the frame anyway.
+        let _ = screenshots.save_screenshot_to_disk(window, capture.dir.join(file));
+    }
+}
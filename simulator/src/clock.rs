@@ -0,0 +1,65 @@
+use bevy::{app::FixedMain, prelude::*};
+
+/// Space pauses and resumes the simulation.
+pub fn toggle_pause(keys: Res<ButtonInput<KeyCode>>, mut time: ResMut<Time<Virtual>>) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+    if time.is_paused() {
+        time.unpause();
+    } else {
+        time.pause();
+    }
+}
+
+/// While paused, `.` runs exactly one fixed step of the controller and the
+/// physics.
+pub fn single_step(world: &mut World) {
+    let requested = world.resource::<Time<Virtual>>().is_paused()
+        && world
+            .resource::<ButtonInput<KeyCode>>()
+            .just_pressed(KeyCode::Period);
+    if !requested {
+        return;
+    }
+    let timestep = world.resource::<Time<Fixed>>().timestep();
+    world.resource_mut::<Time<Fixed>>().advance_by(timestep);
+    *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+    world.run_schedule(FixedMain);
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+}
+
+const TIME_SCALES: [f64; 7] = [0.1, 0.25, 0.5, 1.0, 2.0, 3.0, 4.0];
+
+/// Index into `TIME_SCALES` of the current simulation speed. Since the
+/// controller takes its timestamps from the fixed step, it sees the same `dt`
+/// at every speed; only the number of steps per frame changes.
+#[derive(Resource)]
+pub struct TimeScale(usize);
+impl TimeScale {
+    pub fn speed(&self) -> f64 {
+        TIME_SCALES[self.0]
+    }
+}
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self(TIME_SCALES.iter().position(|&scale| scale == 1.0).unwrap())
+    }
+}
+
+/// `[` slows the simulation down, `]` speeds it up.
+pub fn change_time_scale(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut scale: ResMut<TimeScale>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        scale.0 = scale.0.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        scale.0 = (scale.0 + 1).min(TIME_SCALES.len() - 1);
+    }
+    if scale.is_changed() {
+        time.set_relative_speed_f64(scale.speed());
+    }
+}
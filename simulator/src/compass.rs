@@ -0,0 +1,20 @@
+//! Synthetic magnetometer, fed to every drone's controller each fixed step.
+
+use bevy::prelude::*;
+use controller::MagSample;
+
+use crate::{to_controller_frame, DroneController};
+
+/// Earth's field in the world frame, in gauss: north along z, the way the
+/// drones face when spawned, dipping into the ground.
+const EARTH_FIELD: Vec3 = Vec3::new(0.0, -0.4, 0.2);
+
+pub fn feed_mag(time: Res<Time>, mut drones: Query<(&Transform, &mut DroneController)>) {
+    for (transform, mut controller) in &mut drones {
+        let field = transform.rotation.inverse() * EARTH_FIELD;
+        controller.c.feed_mag(MagSample::new(
+            to_controller_frame(field),
+            time.elapsed_seconds(),
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+//! Sensor latency: samples are held back for a fixed time before they reach
+//! the controller, the way a real sensor's filtering and bus add delay.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use controller::{BaroSample, IMUDataPoint};
+
+use crate::args::Args;
+
+/// Samples waiting out their delay, oldest first. They keep the time they
+/// were taken at, like the GPS fixes do.
+pub struct DelayLine<T> {
+    delay: f32,
+    queue: VecDeque<(f32, T)>,
+}
+impl<T> DelayLine<T> {
+    pub fn new(delay: f32) -> Self {
+        Self {
+            delay,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queues `sample`, taken at `now`, and returns every sample whose delay
+    /// is over by `now`, oldest first.
+    pub fn push(&mut self, now: f32, sample: T) -> impl Iterator<Item = T> + '_ {
+        self.queue.push_back((now, sample));
+        std::iter::from_fn(move || {
+            let (taken, _) = self.queue.front()?;
+            if taken + self.delay > now {
+                return None;
+            }
+            self.queue.pop_front().map(|(_, sample)| sample)
+        })
+    }
+}
+
+/// Delays in seconds applied to each drone's sensor streams.
+#[derive(Resource, Clone, Copy)]
+pub struct SensorLatency {
+    pub imu: f32,
+    pub baro: f32,
+}
+impl SensorLatency {
+    /// From `--imu-delay=ms` and `--baro-delay=ms`. GPS has its own
+    /// `--gps-latency`. No delay by default.
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            imu: args.imu_delay / 1000.0,
+            baro: args.baro_delay / 1000.0,
+        }
+    }
+
+    pub fn delays(&self) -> SensorDelays {
+        SensorDelays {
+            imu: DelayLine::new(self.imu),
+            baro: DelayLine::new(self.baro),
+        }
+    }
+}
+
+/// One drone's sensor samples still in flight.
+#[derive(Component)]
+pub struct SensorDelays {
+    pub imu: DelayLine<IMUDataPoint>,
+    pub baro: DelayLine<BaroSample>,
+}
@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// Arrow lengths in meters per unit drawn.
+const FORCE_SCALE: f32 = 0.05;
+const TORQUE_SCALE: f32 = 5.0;
+const VELOCITY_SCALE: f32 = 0.1;
+
+const FORCE_COLOR: Color = Color::srgb(1.0, 0.8, 0.0);
+const TORQUE_COLOR: Color = Color::srgb(1.0, 0.0, 1.0);
+const VELOCITY_COLOR: Color = Color::srgb(0.0, 0.9, 1.0);
+
+/// Whether the force arrows are drawn.
+#[derive(Resource, Default)]
+pub struct ForceGizmos(bool);
+
+/// G shows and hides the force arrows.
+pub fn toggle_force_gizmos(keys: Res<ButtonInput<KeyCode>>, mut gizmos: ResMut<ForceGizmos>) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        gizmos.0 = !gizmos.0;
+    }
+}
+
+/// Draws what `calculate_forces` applied to each drone from its origin: the
+/// total force (thrust and drag) in yellow, the torque axis in magenta and the
+/// velocity in cyan.
+pub fn draw_force_gizmos(
+    enabled: Res<ForceGizmos>,
+    mut gizmos: Gizmos,
+    drones: Query<(&Transform, &ExternalForce, &Velocity)>,
+) {
+    if !enabled.0 {
+        return;
+    }
+    for (transform, force, velocity) in &drones {
+        let origin = transform.translation;
+        let arrows = [
+            (force.force * FORCE_SCALE, FORCE_COLOR),
+            (force.torque * TORQUE_SCALE, TORQUE_COLOR),
+            (velocity.linvel * VELOCITY_SCALE, VELOCITY_COLOR),
+        ];
+        for (arrow, color) in arrows {
+            gizmos.arrow(origin, origin + arrow, color);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Synthetic GPS: each drone's true position plus noise, delivered to its
+//! controller after a fixed latency.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use controller::GpsSample;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::args::Args;
+use crate::{to_controller_frame, DroneController};
+
+#[derive(Resource)]
+pub struct Gps {
+    /// Standard deviation of each axis of a fix, in meters.
+    pub noise: f32,
+    /// Seconds between a fix being taken and it reaching the controller.
+    pub latency: f32,
+    /// Fixes per second.
+    pub rate_hz: f32,
+    last_fix: Option<f32>,
+    /// Fixes taken but not yet delivered, oldest first.
+    in_flight: VecDeque<(Entity, GpsSample)>,
+    rng: StdRng,
+}
+impl Gps {
+    /// From `--gps-noise=meters`, `--gps-latency=seconds` and `--gps-seed=n`.
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            noise: args.gps_noise,
+            latency: args.gps_latency,
+            rate_hz: 10.0,
+            last_fix: None,
+            in_flight: VecDeque::new(),
+            rng: StdRng::seed_from_u64(args.gps_seed),
+        }
+    }
+
+    fn due(&self, now: f32) -> bool {
+        match self.last_fix {
+            Some(last) => now - last >= 1.0 / self.rate_hz,
+            None => true,
+        }
+    }
+}
+
+/// The sim's world frame has x left and z north, which lines up with the
+/// controller's navigation frame the same way the body frames do.
+pub fn feed_gps(
+    time: Res<Time>,
+    mut gps: ResMut<Gps>,
+    mut drones: Query<(Entity, &Transform, &mut DroneController)>,
+) {
+    let now = time.elapsed_seconds();
+    if gps.due(now) {
+        gps.last_fix = Some(now);
+        for (entity, transform, _) in &drones {
+            let noise =
+                Vec3::from_array(std::array::from_fn(|_| StandardNormal.sample(&mut gps.rng)));
+            let position = transform.translation + gps.noise * noise;
+            gps.in_flight
+                .push_back((entity, GpsSample::new(to_controller_frame(position), now)));
+        }
+    }
+    while let Some((entity, sample)) = gps.in_flight.front().copied() {
+        if sample.time_point + gps.latency > now {
+            break;
+        }
+        gps.in_flight.pop_front();
+        if let Ok((_, _, mut controller)) = drones.get_mut(entity) {
+            controller.c.feed_gps(sample);
+        }
+    }
+}
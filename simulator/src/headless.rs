@@ -0,0 +1,69 @@
+use bevy::{prelude::*, scene::ScenePlugin};
+
+use crate::args::Args;
+use crate::{spawn_transform, Crashed, Drone, DroneCount, SpawnPose};
+
+/// Set by `--headless=<steps>`: the sim runs that many fixed steps without a
+/// window or a GPU, then prints how each drone fared and exits.
+#[derive(Resource)]
+pub struct HeadlessRun {
+    steps_left: u32,
+    /// Largest tilt from level seen so far, in degrees, per drone.
+    max_tilt: Vec<f32>,
+}
+impl HeadlessRun {
+    pub fn from_args(args: &Args, drones: usize) -> Option<Self> {
+        Some(Self {
+            steps_left: args.headless?,
+            max_tilt: vec![0.0; drones],
+        })
+    }
+}
+
+/// What the physics and the controller need without rendering. Rapier's
+/// collider systems still look for meshes and scenes, so those asset types
+/// are registered too.
+pub fn headless_plugins(app: &mut App) {
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        HierarchyPlugin,
+        AssetPlugin::default(),
+        ScenePlugin,
+    ))
+    .init_asset::<Mesh>();
+}
+
+/// Tracks each drone's tilt and, once the steps are used up, prints a summary
+/// line per drone and exits.
+pub fn track_headless_run(
+    mut run: ResMut<HeadlessRun>,
+    count: Res<DroneCount>,
+    pose: Res<SpawnPose>,
+    drones: Query<(&Drone, &Transform, Has<Crashed>)>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if run.steps_left == 0 {
+        return;
+    }
+    for (&Drone(index), transform, _) in &drones {
+        let tilt = (transform.rotation * Vec3::Y).angle_between(Vec3::Y);
+        run.max_tilt[index] = run.max_tilt[index].max(tilt.to_degrees());
+    }
+    run.steps_left -= 1;
+    if run.steps_left > 0 {
+        return;
+    }
+
+    for (&Drone(index), transform, crashed) in &drones {
+        let spawn = spawn_transform(index, count.0, &pose).translation;
+        println!(
+            "drone {}: max tilt {:.1}°, position error {:.2} m, {}",
+            index,
+            run.max_tilt[index],
+            transform.translation.distance(spawn),
+            if crashed { "crashed" } else { "ok" }
+        );
+    }
+    exit.send(AppExit::Success);
+}
@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use nalgebra::Vector3;
+
+use crate::clock::TimeScale;
+use crate::{Drone, DroneController, DroneMotors, Sticks, GROUND_LEVEL};
+
+const BAR_HEIGHT: f32 = 80.0;
+
+/// Vertical bar showing one motor's output, indexed like `DroneMotors::speeds`.
+#[derive(Component)]
+pub struct MotorBar(usize);
+
+#[derive(Component)]
+pub struct HudText;
+
+pub fn setup_hud(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                top: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: Color::srgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        })
+        .with_children(|hud| {
+            hud.spawn(NodeBundle {
+                style: Style {
+                    height: Val::Px(BAR_HEIGHT),
+                    align_items: AlignItems::FlexEnd,
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|bars| {
+                for motor in 0..4 {
+                    bars.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(14.0),
+                                height: Val::Px(0.0),
+                                ..default()
+                            },
+                            background_color: Color::srgb(0.2, 0.8, 0.3).into(),
+                            ..default()
+                        },
+                        MotorBar(motor),
+                    ));
+                }
+            });
+            hud.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                HudText,
+            ));
+        });
+}
+
+pub fn update_hud(
+    sticks: Res<Sticks>,
+    scale: Res<TimeScale>,
+    time: Res<Time<Virtual>>,
+    drones: Query<(&Drone, &DroneController, &DroneMotors, &Transform)>,
+    mut bars: Query<(&mut Style, &MotorBar)>,
+    mut texts: Query<&mut Text, With<HudText>>,
+) {
+    // Shows the drone the camera follows.
+    let Some((_, controller, motors, transform)) =
+        drones.iter().find(|(Drone(index), ..)| *index == 0)
+    else {
+        return;
+    };
+    let speeds = motors.speeds();
+    for (mut style, MotorBar(motor)) in &mut bars {
+        style.height = Val::Px(speeds[*motor] * BAR_HEIGHT);
+    }
+
+    let (roll, pitch) = controller.c.attitude();
+    let terms = controller.c.last_pid_terms();
+    let profile = controller
+        .c
+        .rate_profile(controller.c.selected_profile())
+        .map_or("", |profile| profile.name);
+    // Roll, pitch, yaw order; the controller's vectors are roll, yaw, pitch.
+    let term =
+        |name: &str, v: Vector3<f32>| format!("{} {:+.3} {:+.3} {:+.3}", name, v.x, v.z, v.y);
+    let sticks = sticks.0;
+    let saturated = if controller.c.is_saturated() {
+        "\nSATURATED"
+    } else {
+        ""
+    };
+    let status = if controller.c.is_killed() {
+        "\nKILLED"
+    } else if controller.c.motors_reversed() {
+        "\nTURTLE"
+    } else {
+        ""
+    };
+    let speed = if time.is_paused() {
+        "paused".to_string()
+    } else {
+        format!("{}x", scale.speed())
+    };
+    let telemetry = format!(
+        "motors FL {:.2} FR {:.2} RL {:.2} RR {:.2}\n\
+         throttle {:.2} yaw {:.2} pitch {:.2} roll {:.2}\n\
+         roll {:.1}° pitch {:.1}°\n\
+         altitude {:.2} m\n\
+         rates {}\n\
+         roll/pitch/yaw\n{}\n{}\n{}\n{}\n\
+         {}{}{}",
+        speeds[0],
+        speeds[1],
+        speeds[2],
+        speeds[3],
+        sticks.up_down(),
+        sticks.rotate_pos_neg(),
+        sticks.forward_backward(),
+        sticks.left_right(),
+        roll.to_degrees(),
+        pitch.to_degrees(),
+        transform.translation.y - GROUND_LEVEL,
+        profile,
+        term("P ", terms.p),
+        term("I ", terms.i),
+        term("D ", terms.d),
+        term("FF", terms.ff),
+        speed,
+        saturated,
+        status,
+    );
+    for mut text in &mut texts {
+        text.sections[0].value.clone_from(&telemetry);
+    }
+}
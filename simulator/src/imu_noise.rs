@@ -0,0 +1,58 @@
+//! Sensor model that corrupts the synthesized gyro and accelerometer readings
+//! before the controller sees them.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::args::Args;
+
+#[derive(Resource)]
+pub struct ImuNoise {
+    /// Standard deviation of each gyro axis in rad/s.
+    pub gyro_noise: f32,
+    /// Standard deviation of each accelerometer axis in m/s².
+    pub accel_noise: f32,
+    /// Constant offsets along the body axes (x left, y up, z forward).
+    pub gyro_bias: Vec3,
+    pub accel_bias: Vec3,
+    /// Motor vibration: a sine of `vibration_hz` with `vibration` rad/s on
+    /// every gyro axis and `vibration` m/s² on every accelerometer axis. Zero
+    /// turns it off.
+    pub vibration: f32,
+    pub vibration_hz: f32,
+    rng: StdRng,
+}
+impl ImuNoise {
+    /// From `--gyro-noise=rad/s`, `--accel-noise=m/s²`, `--gyro-bias=x,y,z`,
+    /// `--accel-bias=x,y,z`, `--vibration=amplitude,hz` and `--imu-seed=n`.
+    pub fn from_args(args: &Args) -> Self {
+        let vibration = args.vibration.unwrap_or(Vec2::new(0.0, 200.0));
+        Self {
+            gyro_noise: args.gyro_noise,
+            accel_noise: args.accel_noise,
+            gyro_bias: args.gyro_bias,
+            accel_bias: args.accel_bias,
+            vibration: vibration.x,
+            vibration_hz: vibration.y,
+            rng: StdRng::seed_from_u64(args.imu_seed),
+        }
+    }
+
+    fn gaussian(&mut self) -> Vec3 {
+        Vec3::from_array([(); 3].map(|_| StandardNormal.sample(&mut self.rng)))
+    }
+
+    /// Body frame gyro and specific force as the sensor would report them at
+    /// `time`.
+    pub fn corrupt(&mut self, gyro: Vec3, specific_force: Vec3, time: f32) -> (Vec3, Vec3) {
+        let vibration = Vec3::splat(self.vibration * (TAU * self.vibration_hz * time).sin());
+        let gyro = gyro + self.gyro_bias + vibration + self.gyro_noise * self.gaussian();
+        let specific_force =
+            specific_force + self.accel_bias + vibration + self.accel_noise * self.gaussian();
+        (gyro, specific_force)
+    }
+}
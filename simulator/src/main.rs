@@ -1,14 +1,423 @@
 use bevy::{
     pbr::{CascadeShadowConfigBuilder, DirectionalLightShadowMap},
     prelude::*,
+    time::TimeUpdateStrategy,
 };
 use bevy_rapier3d::prelude::*;
+use clap::Parser;
 
 use std::f32::consts::*;
+use std::time::Duration;
 
-use controller::{Controller, MotorSpeeds};
+use controller::{
+    ArmState, Controller, FlightMode, IMUDataPoint, MotorMixer, MotorPosition, MotorSpeeds,
+    PropSpin, RateProfile, ThrustCurve, TransmitterState,
+};
+use nalgebra::Vector3;
+
+mod args;
+mod baro;
+mod camera;
+mod capture;
+mod clock;
+mod compass;
+mod delay;
+mod gizmos;
+mod gps;
+mod headless;
+mod hud;
+mod imu_noise;
+mod mission;
+mod propeller;
+mod replay;
+mod telemetry;
+mod trail;
+mod tuning;
+mod wind;
+
+use args::Args;
+use baro::{feed_baro, Barometer};
+use camera::{follow_drone, toggle_camera_mode, FollowCamera};
+use capture::{capture_frames, Capture};
+use clock::{change_time_scale, single_step, toggle_pause, TimeScale};
+use compass::feed_mag;
+use delay::{SensorDelays, SensorLatency};
+use gizmos::{draw_force_gizmos, toggle_force_gizmos, ForceGizmos};
+use gps::{feed_gps, Gps};
+use headless::{headless_plugins, track_headless_run, HeadlessRun};
+use hud::{setup_hud, update_hud};
+use imu_noise::ImuNoise;
+use mission::{report_mission, return_home, toggle_mission};
+use propeller::{propeller_bundle, spin_propellers};
+use replay::{flush_recording_on_exit, record_inputs, replay_inputs, InputRecorder, InputReplay};
+use telemetry::{flush_telemetry_on_exit, log_telemetry, TelemetryLog};
+use trail::{draw_trails, export_trails, record_trails, Trail};
+use tuning::{Tuning, TuningFile};
+use wind::{toggle_wind, update_wind, Wind};
+
+const GRAVITY: f32 = 9.81;
+
+/// Height of the top face of the ground plane.
+const GROUND_LEVEL: f32 = 0.1;
+
+#[derive(Resource)]
+struct DroneConfig {
+    /// Thrust in newtons of one motor at full command.
+    max_motor_thrust: f32,
+    /// Motor command at which the four motors together carry the drone's
+    /// weight. The drone's mass is derived from it.
+    hover_point: f32,
+    thrust_curve: ThrustCurve,
+    /// Reaction torque in newton meters about the motor axis per newton of thrust.
+    yaw_torque_coefficient: f32,
+    /// Time constant in seconds of the motors' spin-up. Zero makes them follow
+    /// their commands instantly.
+    motor_time_constant: f32,
+    /// kg/m³.
+    air_density: f32,
+    /// Drag coefficient along each body axis (x left, y up, z forward).
+    drag_coefficients: Vec3,
+    /// Area in m² presented to airflow along each body axis.
+    drag_areas: Vec3,
+    /// Altitude in meters below which ground effect starts adding thrust,
+    /// about one rotor diameter.
+    ground_effect_height: f32,
+    ground_effect_gain: f32,
+    /// Largest thrust multiplier ground effect can reach.
+    ground_effect_max: f32,
+    /// Impact impulse in N·s above which the drone counts as crashed and
+    /// disarms.
+    crash_impulse: f32,
+    /// Principal moments of inertia in kg·m² about the body axes (x left, y
+    /// up, z forward). The motors sit out on the arms, so yaw is the largest.
+    inertia: Vec3,
+    /// Offset in meters of the center of mass from the frame's geometric
+    /// center along the body axes, e.g. from where the battery is strapped.
+    center_of_mass: Vec3,
+    /// Mass in kg, in place of the one derived from `hover_point`.
+    mass: Option<f32>,
+}
+impl DroneConfig {
+    /// Defaults, with the center of mass moved by `--center-of-mass=x,y,z`
+    /// and the mass set by `--mass=<kg>`.
+    fn from_args(args: &Args) -> Self {
+        Self {
+            center_of_mass: args.center_of_mass,
+            mass: args.mass,
+            ..default()
+        }
+    }
+
+    fn motor_thrust(&self, command: f32) -> f32 {
+        self.thrust_curve.map(command.clamp(0.0, 1.0)) * self.max_motor_thrust
+    }
+
+    fn mass(&self) -> f32 {
+        self.mass
+            .unwrap_or_else(|| 4.0 * self.motor_thrust(self.hover_point) / GRAVITY)
+    }
+
+    /// The collider carries no mass of its own, all of it comes from here.
+    fn mass_properties(&self) -> MassProperties {
+        MassProperties {
+            local_center_of_mass: self.center_of_mass,
+            mass: self.mass(),
+            principal_inertia: self.inertia,
+            ..default()
+        }
+    }
+
+    /// World frame drag on a body with orientation `rotation` moving at
+    /// `airspeed` relative to the surrounding air.
+    fn drag(&self, rotation: Quat, airspeed: Vec3) -> Vec3 {
+        let body_velocity = rotation.inverse() * airspeed;
+        let body_drag = -0.5
+            * self.air_density
+            * self.drag_coefficients
+            * self.drag_areas
+            * body_velocity.length()
+            * body_velocity;
+        rotation * body_drag
+    }
+
+    /// Thrust multiplier at `altitude` above the ground. Grows like
+    /// `1 + k/h` close to the ground and is exactly 1 at the threshold.
+    fn ground_effect(&self, altitude: f32) -> f32 {
+        if altitude >= self.ground_effect_height {
+            return 1.0;
+        }
+        const EPSILON: f32 = 0.01;
+        let boost = self.ground_effect_gain
+            * (1.0 / (altitude.max(0.0) + EPSILON) - 1.0 / (self.ground_effect_height + EPSILON));
+        (1.0 + boost).min(self.ground_effect_max)
+    }
+}
+impl Default for DroneConfig {
+    fn default() -> Self {
+        Self {
+            max_motor_thrust: 4.0,
+            hover_point: 0.5,
+            thrust_curve: ThrustCurve::Quadratic,
+            yaw_torque_coefficient: 0.02,
+            motor_time_constant: 0.05,
+            air_density: 1.225,
+            drag_coefficients: Vec3::new(1.0, 1.2, 1.0),
+            drag_areas: Vec3::new(0.01, 0.047, 0.01),
+            ground_effect_height: 0.15,
+            ground_effect_gain: 0.01,
+            ground_effect_max: 1.4,
+            crash_impulse: 0.5,
+            // Four 30 g motors 15 cm from the center plus the frame and
+            // battery as a flat box.
+            inertia: Vec3::new(1.7e-3, 3.3e-3, 1.7e-3),
+            center_of_mass: Vec3::ZERO,
+            mass: None,
+        }
+    }
+}
+
+/// Channel travel per second while a key is held.
+const STICK_RATE: f32 = 2.0;
+const THROTTLE_RATE: f32 = 0.5;
+/// Channel travel per second of a released stick back to center.
+const STICK_RETURN_RATE: f32 = 4.0;
+
+#[derive(Resource)]
+struct GamepadConfig {
+    /// Fraction of stick travel around center that reads as zero.
+    deadzone: f32,
+}
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self { deadzone: 0.05 }
+    }
+}
+
+/// Index of the drone in spawn order.
+#[derive(Component)]
+struct Drone(usize);
+
+#[derive(Resource)]
+struct DroneCount(usize);
+
+/// How drones start out, and where R puts them back.
+#[derive(Resource)]
+struct SpawnPose {
+    /// Meters above the ground plane's center.
+    altitude: f32,
+    /// Roll right, pitch nose up and yaw counterclockwise, in degrees.
+    attitude: Vec3,
+    armed: bool,
+}
+impl SpawnPose {
+    /// From `--spawn-altitude=<m>`, `--spawn-attitude=roll,pitch,yaw` and
+    /// `--armed=<true|false>`. Level, armed and just off the ground by
+    /// default.
+    fn from_args(args: &Args) -> Self {
+        Self {
+            altitude: args.spawn_altitude,
+            attitude: args.spawn_attitude,
+            armed: args.armed,
+        }
+    }
+
+    /// Body x is left and z forward, so rolling right turns about z and
+    /// pitching nose up about -x.
+    fn rotation(&self) -> Quat {
+        let [roll, pitch, yaw] = self.attitude.to_array().map(f32::to_radians);
+        Quat::from_euler(EulerRot::YXZ, yaw, -pitch, roll)
+    }
+
+    fn controller(&self, config: &DroneConfig, tuning: &Tuning) -> Controller {
+        let mut controller = sim_controller(config, tuning);
+        if !self.armed {
+            controller.disarm();
+        }
+        controller
+    }
+}
+
+/// Each drone runs its own controller, with its own IMU history and filter
+/// state.
+#[derive(Component)]
+struct DroneController {
+    c: Controller,
+}
+
+const FLIGHT_MODE: FlightMode = FlightMode::Angle;
+
+/// A drone that hit something hard enough to disarm. Its motors stay off
+/// until it is reset.
+#[derive(Component)]
+struct Crashed;
+
+fn detect_crashes(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<DroneConfig>,
+    mut contacts: EventReader<ContactForceEvent>,
+    mut drones: Query<(&Drone, &mut DroneController), Without<Crashed>>,
+) {
+    for contact in contacts.read() {
+        // Contact forces are averaged over the step, so this is the impulse
+        // the step delivered.
+        let impulse = contact.total_force_magnitude * time.delta_seconds();
+        if impulse < config.crash_impulse {
+            continue;
+        }
+        for entity in [contact.collider1, contact.collider2] {
+            if let Ok((Drone(index), mut controller)) = drones.get_mut(entity) {
+                info!(
+                    "drone {} crashed with an impact of {:.2} N·s",
+                    index, impulse
+                );
+                controller.c.disarm();
+                commands.entity(entity).insert(Crashed);
+            }
+        }
+    }
+}
+
+/// Motor failed on every drone, in front left, front right, rear left, rear
+/// right order.
+#[derive(Resource, Default)]
+struct DeadMotor(Option<usize>);
+impl DeadMotor {
+    /// `speeds` with the failed motor stopped.
+    fn apply(&self, mut speeds: [f32; 4]) -> [f32; 4] {
+        if let Some(i) = self.0 {
+            speeds[i] = 0.0;
+        }
+        speeds
+    }
+}
+
+/// F fails the next motor in turn, then none again. The controllers are told
+/// straight away so they can fly on the remaining three.
+fn cycle_dead_motor(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut dead: ResMut<DeadMotor>,
+    mut drones: Query<&mut DroneController>,
+) {
+    if !keys.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    dead.0 = match dead.0 {
+        None => Some(0),
+        Some(i) if i < 3 => Some(i + 1),
+        Some(_) => None,
+    };
+    for mut controller in &mut drones {
+        for i in 0..4 {
+            controller
+                .c
+                .set_motor_enabled(i, dead.0 != Some(i))
+                .expect("the sim flies quads");
+        }
+    }
+    match dead.0 {
+        Some(i) => info!("motor {} failed", i),
+        None => info!("all motors working"),
+    }
+}
+
+/// Number keys pick a rate profile on every drone: 1 the tuning file's, 2
+/// cinematic and 3 acro.
+fn select_rate_profile(keys: Res<ButtonInput<KeyCode>>, mut drones: Query<&mut DroneController>) {
+    let profile = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3]
+        .into_iter()
+        .position(|key| keys.just_pressed(key));
+    let Some(profile) = profile else {
+        return;
+    };
+    for mut controller in &mut drones {
+        if let Err(err) = controller.c.select_profile(profile) {
+            error!("can't select rate profile: {}", err);
+            return;
+        }
+    }
+    info!("rate profile {}", profile + 1);
+}
+
+/// Escape cuts every drone's motors until R resets them.
+fn kill_switch(keys: Res<ButtonInput<KeyCode>>, mut drones: Query<&mut DroneController>) {
+    if !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    for mut controller in &mut drones {
+        controller.c.kill();
+    }
+    info!("motors killed, R to reset");
+}
+
+/// U puts crashed or disarmed drones into turtle mode and arms them, so the
+/// throttle flips them back over. Pressed again it disarms them and rearms
+/// them in the usual flight mode.
+fn toggle_turtle(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut drones: Query<(Entity, &mut DroneController, Has<Crashed>)>,
+) {
+    if !keys.just_pressed(KeyCode::KeyU) {
+        return;
+    }
+    for (entity, mut controller, crashed) in &mut drones {
+        let c = &mut controller.c;
+        if c.mode() == FlightMode::Turtle {
+            c.disarm();
+            c.set_mode(FLIGHT_MODE);
+            c.arm();
+            info!("turtle mode off");
+        } else if crashed || c.arm_state() == ArmState::Disarmed {
+            commands.entity(entity).remove::<Crashed>();
+            c.disarm();
+            c.set_mode(FlightMode::Turtle);
+            c.arm();
+            info!("turtle mode on");
+        }
+    }
+}
+
+/// Prop rotation seen from above.
+#[derive(Clone, Copy, Debug)]
+enum SpinDirection {
+    Clockwise,
+    CounterClockwise,
+}
+impl SpinDirection {
+    /// Sign of the reaction torque about the up axis, which opposes the spin.
+    fn reaction_sign(&self) -> f32 {
+        match self {
+            SpinDirection::Clockwise => 1.0,
+            SpinDirection::CounterClockwise => -1.0,
+        }
+    }
+
+    /// Sign of the prop's own rotation about the up axis.
+    fn spin_sign(&self) -> f32 {
+        -self.reaction_sign()
+    }
+
+    fn prop_spin(&self) -> PropSpin {
+        match self {
+            SpinDirection::Clockwise => PropSpin::Clockwise,
+            SpinDirection::CounterClockwise => PropSpin::CounterClockwise,
+        }
+    }
+}
 
-#[derive(Component, Clone, Debug)]
+/// Where each motor sits in the model's frame and which way its prop turns,
+/// ordered like `DroneMotors::speeds`. Diagonal pairs spin the same way, so
+/// positive yaw from the mixer (front left and rear right up) turns the drone
+/// counterclockwise. The controllers' mixer is worked out from it.
+const MOTOR_LAYOUT: [(Vec3, SpinDirection); 4] = [
+    (Vec3::new(1.8, 0.0, 1.8), SpinDirection::Clockwise),
+    (Vec3::new(-1.8, 0.0, 1.8), SpinDirection::CounterClockwise),
+    (Vec3::new(1.8, 0.0, -1.8), SpinDirection::CounterClockwise),
+    (Vec3::new(-1.8, 0.0, -1.8), SpinDirection::Clockwise),
+];
+
+#[derive(Component, Clone, Debug, Default)]
 struct DroneMotors {
     left_front: f32,
     right_front: f32,
@@ -16,11 +425,52 @@ struct DroneMotors {
     right_rear: f32,
 }
 impl DroneMotors {
-    fn readSpeeds(&mut self, m: &MotorSpeeds) {
-        self.left_front = m.get_front_left();
-        self.right_front = m.get_front_right();
-        self.left_rear = m.get_rear_left();
-        self.right_rear = m.get_rear_right();
+    fn read_speeds(&mut self, m: &MotorSpeeds) {
+        for (position, speed) in m.iter() {
+            match position {
+                MotorPosition::FrontLeft => self.left_front = speed,
+                MotorPosition::FrontRight => self.right_front = speed,
+                MotorPosition::RearLeft => self.left_rear = speed,
+                MotorPosition::RearRight => self.right_rear = speed,
+            }
+        }
+    }
+
+    /// Front left, front right, rear left, rear right.
+    fn speeds(&self) -> [f32; 4] {
+        [
+            self.left_front,
+            self.right_front,
+            self.left_rear,
+            self.right_rear,
+        ]
+    }
+
+    /// Moves every motor `alpha` of the way towards `target`.
+    fn chase(&mut self, target: &DroneMotors, alpha: f32) {
+        self.left_front += (target.left_front - self.left_front) * alpha;
+        self.right_front += (target.right_front - self.right_front) * alpha;
+        self.left_rear += (target.left_rear - self.left_rear) * alpha;
+        self.right_rear += (target.right_rear - self.right_rear) * alpha;
+    }
+}
+
+/// Motor speeds actually reached, lagging behind the commanded `DroneMotors`.
+#[derive(Component, Clone, Debug, Default)]
+struct SpunUpMotors(DroneMotors);
+
+fn spin_up_motors(
+    time: Res<Time>,
+    config: Res<DroneConfig>,
+    mut drones: Query<(&DroneMotors, &mut SpunUpMotors)>,
+) {
+    let alpha = if config.motor_time_constant > 0.0 {
+        (time.delta_seconds() / config.motor_time_constant).min(1.0)
+    } else {
+        1.0
+    };
+    for (command, mut actual) in &mut drones {
+        actual.0.chase(command, alpha);
     }
 }
 
@@ -28,123 +478,517 @@ fn vec_to_3d(v: Vec4) -> Vec3 {
     Vec3::new(v.x, v.y, v.z)
 }
 
-fn calculate_forces(mut drones: Query<(&mut ExternalForce, &DroneMotors, &Transform)>) {
-    for (mut force, motors, transform) in &mut drones {
+/// The drone model has x pointing left and z forward, the controller expects
+/// x forward, y up and z right.
+fn to_controller_frame(v: Vec3) -> Vector3<f32> {
+    Vector3::new(v.z, v.y, -v.x)
+}
+
+/// Linear velocity seen on the previous frame, to derive acceleration from.
+#[derive(Component, Default)]
+struct Imu {
+    last_linvel: Option<Vec3>,
+}
+
+#[derive(Resource, Default)]
+struct Sticks(TransmitterState);
+
+/// 1 while a `positive` key is held, -1 for a `negative` one, 0 for neither or both.
+fn key_axis(keys: &ButtonInput<KeyCode>, positive: &[KeyCode], negative: &[KeyCode]) -> f32 {
+    let mut axis = 0.0;
+    if keys.any_pressed(positive.iter().copied()) {
+        axis += 1.0;
+    }
+    if keys.any_pressed(negative.iter().copied()) {
+        axis -= 1.0;
+    }
+    axis
+}
+
+fn move_stick(value: f32, direction: f32, spring_back: bool, dt: f32) -> f32 {
+    let value = if direction != 0.0 {
+        value + direction * STICK_RATE * dt
+    } else if spring_back {
+        let step = STICK_RETURN_RATE * dt;
+        value + (0.5 - value).clamp(-step, step)
+    } else {
+        value
+    };
+    value.clamp(0.0, 1.0)
+}
+
+/// WASD tilts, Q/E yaws and Shift/Ctrl raise and lower the throttle. Positive
+/// pitch is nose up, so W pushes the pitch channel down.
+fn keyboard_sticks(time: Res<Time>, keys: Res<ButtonInput<KeyCode>>, mut sticks: ResMut<Sticks>) {
+    let dt = time.delta_seconds();
+    let current = sticks.0;
+    let throttle = key_axis(
+        &keys,
+        &[KeyCode::ShiftLeft, KeyCode::ShiftRight],
+        &[KeyCode::ControlLeft, KeyCode::ControlRight],
+    );
+    let up_down = (current.up_down() + throttle * THROTTLE_RATE * dt).clamp(0.0, 1.0);
+    // Yaw commands a rate in every mode, so it always recenters.
+    let rotate_pos_neg = move_stick(
+        current.rotate_pos_neg(),
+        key_axis(&keys, &[KeyCode::KeyQ], &[KeyCode::KeyE]),
+        true,
+        dt,
+    );
+    let spring_back = matches!(FLIGHT_MODE, FlightMode::Angle | FlightMode::Horizon);
+    let forward_backward = move_stick(
+        current.forward_backward(),
+        key_axis(&keys, &[KeyCode::KeyS], &[KeyCode::KeyW]),
+        spring_back,
+        dt,
+    );
+    let left_right = move_stick(
+        current.left_right(),
+        key_axis(&keys, &[KeyCode::KeyD], &[KeyCode::KeyA]),
+        spring_back,
+        dt,
+    );
+    sticks.0 = TransmitterState::new(up_down, rotate_pos_neg, forward_backward, left_right);
+}
+
+fn run_controller(
+    time: Res<Time>,
+    sticks: Res<Sticks>,
+    mut noise: ResMut<ImuNoise>,
+    mut drones: Query<(
+        &mut DroneController,
+        &mut DroneMotors,
+        &mut Imu,
+        &mut SensorDelays,
+        &Velocity,
+        &Transform,
+    )>,
+) {
+    let dt = time.delta_seconds();
+    for (mut controller, mut motors, mut imu, mut delays, velocity, transform) in &mut drones {
+        let accel = match imu.last_linvel {
+            Some(last) if dt > 0.0 => (velocity.linvel - last) / dt,
+            _ => Vec3::ZERO,
+        };
+        imu.last_linvel = Some(velocity.linvel);
+
+        // An accelerometer measures everything but gravity, so it reads 1g up
+        // while at rest.
+        let to_body = transform.rotation.inverse();
+        let gyro = to_body * velocity.angvel;
+        let specific_force = to_body * (accel + Vec3::Y * GRAVITY);
+        let (gyro, specific_force) = noise.corrupt(gyro, specific_force, time.elapsed_seconds());
+        let data_point = IMUDataPoint::new(
+            to_controller_frame(gyro),
+            to_controller_frame(specific_force),
+            time.elapsed_seconds(),
+        );
+        for data_point in delays.imu.push(time.elapsed_seconds(), data_point) {
+            motors.read_speeds(controller.c.calculate_motor_speeds(data_point, &sticks.0));
+        }
+    }
+}
+
+type ForceInputs<'a> = (
+    &'a mut ExternalForce,
+    &'a DroneController,
+    &'a SpunUpMotors,
+    &'a Transform,
+    &'a Velocity,
+    Has<Crashed>,
+);
+
+fn calculate_forces(
+    config: Res<DroneConfig>,
+    wind: Res<Wind>,
+    dead: Res<DeadMotor>,
+    mut drones: Query<ForceInputs>,
+) {
+    for (mut force, controller, SpunUpMotors(motors), transform, velocity, crashed) in &mut drones {
         let trans_mat = transform.compute_matrix();
-        let motor_speed_and_pos = [
-            (
-                motors.left_front,
-                vec_to_3d(trans_mat * Vec4::new(1.8, 0.0, 1.8, 0.0)),
-            ),
-            (
-                motors.right_front,
-                vec_to_3d(trans_mat * Vec4::new(-1.8, 0.0, 1.8, 0.0)),
-            ),
-            (
-                motors.left_rear,
-                vec_to_3d(trans_mat * Vec4::new(1.8, 0.0, -1.8, 0.0)),
-            ),
-            (
-                motors.right_rear,
-                vec_to_3d(trans_mat * Vec4::new(-1.8, 0.0, -1.8, 0.0)),
-            ),
-        ];
         force.force = Vec3::ZERO;
         force.torque = Vec3::ZERO;
 
-        for (motor_speed, motor_pos) in motor_speed_and_pos {
-            let motor_force = vec_to_3d(trans_mat * (motor_speed * Vec4::new(0.0, 1.0, 0.0, 0.0)));
-            force.torque += motor_pos.cross(motor_force);
+        let up = transform.rotation * Vec3::Y;
+        let ground_effect = config.ground_effect(transform.translation.y - GROUND_LEVEL);
+        // A crashed drone is disarmed and produces no thrust.
+        let thrust_scale = if crashed { 0.0 } else { ground_effect };
+        // Props spinning backwards in turtle mode push the other way, and so
+        // does their reaction torque.
+        let thrust_scale = if controller.c.motors_reversed() {
+            -thrust_scale
+        } else {
+            thrust_scale
+        };
+        // Rapier applies the torque about the center of mass, so the lever
+        // arms have to be measured from there.
+        let center_of_mass = transform.rotation * config.center_of_mass;
+        for (motor_speed, (offset, spin)) in
+            dead.apply(motors.speeds()).into_iter().zip(MOTOR_LAYOUT)
+        {
+            let motor_pos = vec_to_3d(trans_mat * offset.extend(0.0));
+            let thrust = config.motor_thrust(motor_speed) * thrust_scale;
+            let motor_force = thrust * up;
+            force.torque += (motor_pos - center_of_mass).cross(motor_force);
+            force.torque += spin.reaction_sign() * config.yaw_torque_coefficient * thrust * up;
             force.force += motor_force;
         }
+        force.force += config.drag(transform.rotation, velocity.linvel - wind.velocity());
+    }
+}
 
-        force.torque += 0.2
-            * vec_to_3d(
-                trans_mat
-                    * Vec4::new(
-                        0.0,
-                        motors.left_front + motors.right_rear
-                            - motors.right_front
-                            - motors.left_rear,
-                        0.0,
-                        0.0,
-                    ),
-            );
+/// Zeroes `value` inside the deadzone and rescales the rest back to -1..1.
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone {
+        return 0.0;
     }
+    value.signum() * (value.abs() - deadzone) / (1.0 - deadzone)
 }
 
-#[derive(Resource)]
-struct ResController {
-    c: Controller,
+/// Maps a -1..1 gamepad axis onto a 0..1 channel.
+fn axis_to_channel(value: f32) -> f32 {
+    ((value + 1.0) / 2.0).clamp(0.0, 1.0)
+}
+
+/// Mode 2 layout on the lowest numbered connected gamepad: the left stick is
+/// throttle and yaw, the right one pitch and roll. Overrides the keyboard
+/// while a gamepad is connected.
+fn gamepad_sticks(
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    config: Res<GamepadConfig>,
+    mut sticks: ResMut<Sticks>,
+) {
+    let Some(gamepad) = gamepads.iter().min_by_key(|gamepad| gamepad.id) else {
+        return;
+    };
+    let axis = |axis_type| {
+        let value = axes
+            .get(GamepadAxis::new(gamepad, axis_type))
+            .unwrap_or(0.0);
+        apply_deadzone(value, config.deadzone)
+    };
+    // Throttle uses the full stick travel, the rest are centered. Stick right
+    // yaws right and stick forward pitches nose down, both negative rotations.
+    sticks.0 = TransmitterState::new(
+        axis_to_channel(axis(GamepadAxisType::LeftStickY)),
+        axis_to_channel(-axis(GamepadAxisType::LeftStickX)),
+        axis_to_channel(-axis(GamepadAxisType::RightStickY)),
+        axis_to_channel(axis(GamepadAxisType::RightStickX)),
+    );
+}
+
+/// Distance between neighbouring drones on the spawn grid.
+const SPAWN_SPACING: f32 = 1.0;
+
+/// Drones are laid out on a square grid centered on the origin.
+fn spawn_transform(index: usize, count: usize, pose: &SpawnPose) -> Transform {
+    let side = (count as f32).sqrt().ceil() as usize;
+    let offset = (side - 1) as f32 * SPAWN_SPACING / 2.0;
+    let x = (index % side) as f32 * SPAWN_SPACING - offset;
+    let z = (index / side) as f32 * SPAWN_SPACING - offset;
+    Transform {
+        translation: Vec3::new(x, pose.altitude, z),
+        rotation: pose.rotation(),
+        scale: Vec3::new(0.06, 0.06, 0.06),
+    }
+}
+
+type DroneState<'a> = (
+    Entity,
+    &'a Drone,
+    &'a mut DroneController,
+    &'a mut Transform,
+    &'a mut Velocity,
+    &'a mut ExternalForce,
+    &'a mut Imu,
+    &'a mut SensorDelays,
+    &'a mut DroneMotors,
+    &'a mut SpunUpMotors,
+    &'a mut Trail,
+);
+
+/// R puts every drone back on its spawn pose at rest with a fresh controller
+/// and idle sticks, clearing any crash or kill.
+fn reset_drone(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    count: Res<DroneCount>,
+    pose: Res<SpawnPose>,
+    latency: Res<SensorLatency>,
+    mut sticks: ResMut<Sticks>,
+    mut drones: Query<DroneState>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    *sticks = Sticks::default();
+    for (
+        entity,
+        Drone(index),
+        mut controller,
+        mut transform,
+        mut velocity,
+        mut force,
+        mut imu,
+        mut delays,
+        mut motors,
+        mut spun_up,
+        mut trail,
+    ) in &mut drones
+    {
+        commands.entity(entity).remove::<Crashed>();
+        controller.c.reset();
+        controller.c.clear_kill();
+        if pose.armed {
+            controller.c.arm();
+        } else {
+            controller.c.disarm();
+        }
+        *transform = spawn_transform(*index, count.0, &pose);
+        *velocity = Velocity::zero();
+        *force = ExternalForce::default();
+        *imu = Imu::default();
+        *delays = latency.delays();
+        *motors = DroneMotors::default();
+        *spun_up = SpunUpMotors::default();
+        trail.clear();
+    }
+}
+
+fn sim_controller(config: &DroneConfig, tuning: &Tuning) -> Controller {
+    let mut controller = Controller::new();
+    tuning.apply(&mut controller);
+    let presets = [RateProfile::cinematic(), RateProfile::acro()];
+    for (index, profile) in presets.into_iter().enumerate() {
+        controller
+            .set_rate_profile(index + 1, profile)
+            .expect("preset rate profiles are valid");
+    }
+    controller.set_mixer(MotorMixer::from_geometry(
+        MOTOR_LAYOUT.map(|(offset, spin)| (to_controller_frame(offset), spin.prop_spin())),
+    ));
+    controller.set_mode(FLIGHT_MODE);
+    controller.set_thrust_curve(config.thrust_curve);
+    controller.set_heading_hold(true);
+    controller.set_tilt_compensation(true);
+    controller.arm();
+    controller
 }
 
 fn main() {
-    App::new()
-        .insert_resource(DirectionalLightShadowMap { size: 4096 })
-        .add_plugins(DefaultPlugins)
-        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugins(RapierDebugRenderPlugin::default())
-        .add_systems(Startup, setup_graphics)
-        .add_systems(Startup, setup_physics)
-        .add_systems(Update, animate_light_direction)
-        .add_systems(Update, calculate_forces)
-        .insert_resource(ResController {
-            c: Controller::new(),
+    let args = Args::parse();
+    let loop_hz = args.loop_hz;
+    let tuning_file = TuningFile::from_args(&args);
+    let mut app = App::new();
+    if let Some(file) = &tuning_file {
+        file.register_source(&mut app);
+    }
+    if let Some(run) = HeadlessRun::from_args(&args, args.drones as usize) {
+        app.add_plugins(headless_plugins)
+            // Every update advances exactly one fixed step, as fast as the
+            // machine allows.
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+                1.0 / loop_hz,
+            )))
+            .insert_resource(run)
+            .add_systems(FixedUpdate, track_headless_run.after(detect_crashes));
+    } else {
+        app.insert_resource(DirectionalLightShadowMap { size: 4096 })
+            .add_plugins(DefaultPlugins)
+            .add_plugins(RapierDebugRenderPlugin::default())
+            .add_systems(Startup, setup_graphics)
+            .add_systems(Startup, setup_visuals.after(setup_physics))
+            .add_systems(Startup, setup_hud)
+            .add_systems(Update, animate_light_direction)
+            .add_systems(Update, update_hud)
+            .add_systems(
+                PostUpdate,
+                (toggle_camera_mode, follow_drone)
+                    .chain()
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .add_systems(
+                Update,
+                (
+                    (toggle_pause, single_step).chain(),
+                    change_time_scale,
+                    (keyboard_sticks, gamepad_sticks).chain(),
+                    toggle_wind,
+                    cycle_dead_motor,
+                    select_rate_profile,
+                    toggle_turtle,
+                    kill_switch,
+                    (toggle_mission, return_home, report_mission).chain(),
+                    reset_drone,
+                    spin_propellers,
+                    (toggle_force_gizmos, draw_force_gizmos).chain(),
+                    (draw_trails, export_trails),
+                    capture_frames,
+                ),
+            )
+            .insert_resource(Capture::from_args(&args));
+    }
+    // The controller and the physics share one fixed step, decoupled from
+    // the frame rate so the loop behaves the same on any display and the
+    // simulation can be paused and stepped.
+    app.insert_resource(Time::<Fixed>::from_hz(loop_hz))
+        .insert_resource(RapierConfiguration {
+            timestep_mode: TimestepMode::Fixed {
+                dt: (1.0 / loop_hz) as f32,
+                substeps: 1,
+            },
+            ..RapierConfiguration::new(1.0)
         })
-        .run();
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default().in_fixed_schedule())
+        .add_systems(Startup, setup_physics)
+        .add_systems(
+            FixedUpdate,
+            (
+                feed_baro,
+                feed_mag,
+                feed_gps,
+                run_controller,
+                spin_up_motors,
+                update_wind,
+                calculate_forces,
+            )
+                .chain()
+                .before(PhysicsSet::SyncBackend),
+        )
+        .add_systems(
+            FixedUpdate,
+            (detect_crashes, record_trails).after(PhysicsSet::Writeback),
+        )
+        .insert_resource(DroneConfig::from_args(&args))
+        .insert_resource(DroneCount(args.drones as usize))
+        .insert_resource(SpawnPose::from_args(&args))
+        .insert_resource(Barometer::from_args(&args))
+        .insert_resource(Gps::from_args(&args))
+        .insert_resource(ImuNoise::from_args(&args))
+        .insert_resource(SensorLatency::from_args(&args))
+        .init_resource::<Sticks>()
+        .init_resource::<GamepadConfig>()
+        .init_resource::<DeadMotor>()
+        .init_resource::<ForceGizmos>()
+        .init_resource::<TimeScale>()
+        .init_resource::<Tuning>();
+    if let Some(file) = tuning_file {
+        file.watch(&mut app);
+    }
+
+    let mut wind = Wind::from_args(&args);
+    if let Some((replay, recorded_wind)) = InputReplay::from_args(&args) {
+        wind = recorded_wind;
+        app.insert_resource(replay)
+            .add_systems(FixedUpdate, replay_inputs.before(run_controller));
+    }
+    if let Some(recorder) = InputRecorder::from_args(&args, &wind) {
+        app.insert_resource(recorder)
+            .add_systems(
+                FixedUpdate,
+                record_inputs.after(replay_inputs).before(run_controller),
+            )
+            .add_systems(Last, flush_recording_on_exit);
+    }
+    app.insert_resource(wind);
+
+    if let Some(log) = TelemetryLog::from_args(&args) {
+        app.insert_resource(log)
+            .add_systems(FixedUpdate, log_telemetry.after(PhysicsSet::Writeback))
+            .add_systems(Last, flush_telemetry_on_exit);
+    }
+    app.run();
 }
 
 fn setup_physics(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
+    config: Res<DroneConfig>,
+    count: Res<DroneCount>,
+    pose: Res<SpawnPose>,
+    tuning: Res<Tuning>,
+    latency: Res<SensorLatency>,
 ) {
     // Spawn ground plane entity
     commands
         .spawn(RigidBody::Fixed)
-        .insert(Collider::cuboid(100.0, 0.1, 100.0))
-        .insert(PbrBundle {
-            mesh: meshes.add(Cuboid::new(100.0, 0.1, 100.0)),
-            material: materials.add(Color::srgb(0.5, 0.5647, 1.0)),
-            ..default()
-        })
+        .insert(Collider::cuboid(100.0, GROUND_LEVEL, 100.0))
         .insert(TransformBundle::from(Transform::from_xyz(0.0, 0.0, 0.0)));
 
-    let my_mesh = asset_server.load("uploads_files_4453673_FPV+DRONE.gltf#Scene0");
-
-    // Spawn drone entity
-    commands
-        .spawn(RigidBody::Dynamic)
-        .insert(Collider::cuboid(3.6, 0.8, 3.6))
-        .insert(SceneBundle {
-            scene: my_mesh,
-            ..default()
-        })
-        .insert(ColliderMassProperties::Mass(0.5))
-        .insert(TransformBundle::from(Transform {
-            translation: Vec3::new(0.0, 0.6, 0.0),
-            scale: Vec3::new(0.06, 0.06, 0.06),
-            ..Default::default()
-        }))
-        .insert(ExternalForce {
-            force: Vec3::new(0.0, 0.0, 0.0),
-            torque: Vec3::new(0.0, 0.0, 0.0),
-        })
-        .insert(DroneMotors {
-            left_front: 10.0,
-            right_front: 15.0,
-            left_rear: 15.0,
-            right_rear: 10.0,
-        });
+    // Spawn drone entities
+    for index in 0..count.0 {
+        commands
+            .spawn(Drone(index))
+            .insert(DroneController {
+                c: pose.controller(&config, &tuning),
+            })
+            .insert(RigidBody::Dynamic)
+            .insert(Collider::cuboid(3.6, 0.8, 3.6))
+            .insert(ActiveEvents::CONTACT_FORCE_EVENTS)
+            .insert(ContactForceEventThreshold(0.0))
+            .insert(ColliderMassProperties::Density(0.0))
+            .insert(AdditionalMassProperties::MassProperties(
+                config.mass_properties(),
+            ))
+            .insert(TransformBundle::from(spawn_transform(
+                index, count.0, &pose,
+            )))
+            .insert(ExternalForce {
+                force: Vec3::new(0.0, 0.0, 0.0),
+                torque: Vec3::new(0.0, 0.0, 0.0),
+            })
+            .insert(Velocity::zero())
+            .insert(Imu::default())
+            .insert(latency.delays())
+            .insert(DroneMotors::default())
+            .insert(SpunUpMotors::default())
+            .insert(Trail::default());
+    }
 }
 
-fn setup_graphics(mut commands: Commands) {
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0.7, 0.7, 1.0).looking_at(Vec3::new(0.0, 0.3, 0.0), Vec3::Y),
+/// Gives the ground and the drones spawned by `setup_physics` something to
+/// look at.
+fn setup_visuals(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    drones: Query<Entity, With<Drone>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Cuboid::new(100.0, 0.1, 100.0)),
+        material: materials.add(Color::srgb(0.5, 0.5647, 1.0)),
         ..default()
     });
 
+    let my_mesh: Handle<Scene> = asset_server.load("uploads_files_4453673_FPV+DRONE.gltf#Scene0");
+    let prop_mesh = meshes.add(propeller::blade_mesh());
+    let prop_material = materials.add(propeller::blade_material());
+    for drone in &drones {
+        commands
+            .entity(drone)
+            .insert((my_mesh.clone(), VisibilityBundle::default()))
+            .with_children(|drone| {
+                for motor in 0..MOTOR_LAYOUT.len() {
+                    drone.spawn(propeller_bundle(
+                        motor,
+                        prop_mesh.clone(),
+                        prop_material.clone(),
+                    ));
+                }
+            });
+    }
+}
+
+fn setup_graphics(mut commands: Commands) {
+    commands
+        .spawn(Camera3dBundle {
+            transform: Transform::from_xyz(0.7, 0.7, 1.0)
+                .looking_at(Vec3::new(0.0, 0.3, 0.0), Vec3::Y),
+            ..default()
+        })
+        .insert(FollowCamera::default());
+
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
             shadows_enabled: true,
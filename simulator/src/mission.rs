@@ -0,0 +1,69 @@
+//! M sends every drone around a square from where it is, in position hold. M
+//! again hands control back to the sticks. H sends every drone home to land.
+
+use bevy::prelude::*;
+use controller::{FlightMode, TransmitterState, Waypoint};
+use nalgebra::Vector3;
+
+use crate::{to_controller_frame, DroneController, Sticks, FLIGHT_MODE};
+
+/// Corners of the route relative to the start, in the navigation frame
+/// (x north, z east), ending back at the start.
+const SQUARE: [[f32; 2]; 4] = [[4.0, 0.0], [4.0, 4.0], [0.0, 4.0], [0.0, 0.0]];
+/// Seconds spent at each corner.
+const CORNER_HOLD: f32 = 1.0;
+/// Meters above the arming point the route is flown at.
+const MISSION_ALTITUDE: f32 = 2.0;
+
+pub fn toggle_mission(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut sticks: ResMut<Sticks>,
+    mut drones: Query<(&Transform, &mut DroneController)>,
+) {
+    if !keys.just_pressed(KeyCode::KeyM) {
+        return;
+    }
+    for (transform, mut controller) in &mut drones {
+        let controller = &mut controller.c;
+        if controller.mode() == FlightMode::PositionHold {
+            controller.clear_waypoints();
+            controller.set_mode(FLIGHT_MODE);
+            continue;
+        }
+        controller.set_mode(FlightMode::PositionHold);
+        controller.set_target_altitude(MISSION_ALTITUDE);
+        let start = to_controller_frame(transform.translation);
+        for [north, east] in SQUARE {
+            let corner = start + Vector3::new(north, 0.0, east);
+            // The queue is far larger than the route.
+            let _ = controller.push_waypoint(Waypoint::new(corner, CORNER_HOLD));
+        }
+    }
+    // Centered sticks leave the route and altitude to the controller.
+    sticks.0 = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+}
+
+pub fn return_home(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut sticks: ResMut<Sticks>,
+    mut drones: Query<&mut DroneController>,
+) {
+    if !keys.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    for mut controller in &mut drones {
+        controller.c.clear_waypoints();
+        controller.c.set_mode(FlightMode::ReturnToHome);
+    }
+    sticks.0 = TransmitterState::new(0.5, 0.5, 0.5, 0.5);
+}
+
+pub fn report_mission(mut done: Local<bool>, drones: Query<&DroneController>) {
+    let complete = drones
+        .iter()
+        .any(|controller| controller.c.mission_complete());
+    if complete && !*done {
+        info!("mission complete, holding position");
+    }
+    *done = complete;
+}
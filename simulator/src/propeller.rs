@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+
+use crate::{DeadMotor, SpunUpMotors, MOTOR_LAYOUT};
+
+/// Prop rotation in rad/s at full motor output. Far slower than a real prop,
+/// so the blades don't alias into a standstill at the display's frame rate.
+const FULL_SPEED: f32 = 60.0;
+/// Height of the props above the motor positions, in model units.
+const PROP_HEIGHT: f32 = 0.9;
+
+/// A two blade prop over one motor, indexed like `DroneMotors::speeds`.
+///
+/// The glTF model's props are part of its static meshes, so the sim draws its
+/// own over the motors.
+#[derive(Component)]
+pub struct Propeller(usize);
+
+pub fn blade_mesh() -> Mesh {
+    Cuboid::new(3.2, 0.04, 0.3).into()
+}
+
+pub fn blade_material() -> StandardMaterial {
+    StandardMaterial {
+        base_color: Color::srgba(0.9, 0.9, 0.9, 0.6),
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    }
+}
+
+pub fn propeller_bundle(
+    motor: usize,
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+) -> (Propeller, PbrBundle) {
+    let (offset, _) = MOTOR_LAYOUT[motor];
+    (
+        Propeller(motor),
+        PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(offset + PROP_HEIGHT * Vec3::Y),
+            ..default()
+        },
+    )
+}
+
+/// Turns each prop at a rate proportional to its motor's spun up output, in
+/// the direction the motor spins.
+pub fn spin_propellers(
+    time: Res<Time>,
+    dead: Res<DeadMotor>,
+    drones: Query<&SpunUpMotors>,
+    mut props: Query<(&Propeller, &Parent, &mut Transform)>,
+) {
+    for (&Propeller(motor), parent, mut transform) in &mut props {
+        let Ok(SpunUpMotors(motors)) = drones.get(parent.get()) else {
+            continue;
+        };
+        let speed = dead.apply(motors.speeds())[motor];
+        let (_, spin) = MOTOR_LAYOUT[motor];
+        transform.rotate_y(spin.spin_sign() * speed * FULL_SPEED * time.delta_seconds());
+    }
+}
@@ -0,0 +1,164 @@
+//! Recording and replay of the sticks seen by the controller on every fixed
+//! step. `--record=<path>` writes them, `--replay=<path>` feeds them back in
+//! place of the keyboard and gamepad, and the wind comes from the recording.
+//!
+//! Nothing else is recorded, so a replay only reproduces the run step for
+//! step when it's started with the same `--loop-hz`, `--drones`,
+//! `--spawn-altitude`, `--spawn-attitude`, `--armed`, `--mass`,
+//! `--center-of-mass`, `--gyro-noise`, `--accel-noise`, `--gyro-bias`,
+//! `--accel-bias`, `--vibration`, `--imu-seed`, `--imu-delay`,
+//! `--baro-noise`, `--baro-delay`, `--baro-seed`, `--gps-noise`,
+//! `--gps-latency`, `--gps-seed` and `--tuning` file as the recording. Keys
+//! that change the run aren't recorded either: pressing F, 1 to 3, U, Esc,
+//! R, M, H or V during either run makes them diverge.
+//!
+//! The first line holds the wind: `wind,<x>,<y>,<z>,<gust intensity>,<gust
+//! time constant>,<seed>`. Every following line is one step's throttle, yaw,
+//! pitch and roll channels.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use controller::TransmitterState;
+
+use crate::args::Args;
+use crate::wind::Wind;
+use crate::Sticks;
+
+#[derive(Resource)]
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+}
+impl InputRecorder {
+    pub fn create(path: &Path, wind: &Wind) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "wind,{},{},{},{},{},{}",
+            wind.base.x,
+            wind.base.y,
+            wind.base.z,
+            wind.gust_intensity,
+            wind.gust_time_constant,
+            wind.seed
+        )?;
+        Ok(Self { writer })
+    }
+
+    /// Starts recording to the file named by `--record=<path>`, if given.
+    pub fn from_args(args: &Args, wind: &Wind) -> Option<Self> {
+        let path = args.record.as_ref()?;
+        match Self::create(path, wind) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                error!("can't create recording {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct InputReplay {
+    inputs: Vec<TransmitterState>,
+    next: usize,
+}
+impl InputReplay {
+    /// Loads a recording along with the wind it was made in.
+    pub fn load(path: &Path) -> io::Result<(Self, Wind)> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad replay line {}", line + 1),
+            )
+        };
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().ok_or_else(|| invalid(0))??;
+        let wind_fields: Vec<&str> = header.split(',').collect();
+        let wind = match wind_fields[..] {
+            ["wind", x, y, z, intensity, time_constant, seed] => {
+                let float = |v: &str| v.parse::<f32>().map_err(|_| invalid(0));
+                let mut wind = Wind::new(
+                    Vec3::new(float(x)?, float(y)?, float(z)?),
+                    float(intensity)?,
+                    seed.parse().map_err(|_| invalid(0))?,
+                );
+                wind.gust_time_constant = float(time_constant)?;
+                wind
+            }
+            _ => return Err(invalid(0)),
+        };
+
+        let mut inputs = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let channels: Vec<f32> = line
+                .split(',')
+                .map(|v| v.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(i + 1))?;
+            let [up_down, rotate_pos_neg, forward_backward, left_right] = channels[..] else {
+                return Err(invalid(i + 1));
+            };
+            let state =
+                TransmitterState::try_new(up_down, rotate_pos_neg, forward_backward, left_right)
+                    .map_err(|_| invalid(i + 1))?;
+            inputs.push(state);
+        }
+        Ok((Self { inputs, next: 0 }, wind))
+    }
+
+    /// Loads the recording named by `--replay=<path>`, if given. If it can't
+    /// be read the sim logs why and flies on the live sticks instead.
+    pub fn from_args(args: &Args) -> Option<(Self, Wind)> {
+        let path = args.replay.as_ref()?;
+        match Self::load(path) {
+            Ok(replay) => Some(replay),
+            Err(err) => {
+                error!("can't load replay {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+pub fn record_inputs(sticks: Res<Sticks>, mut recorder: ResMut<InputRecorder>) {
+    let sticks = sticks.0;
+    let written = writeln!(
+        recorder.writer,
+        "{},{},{},{}",
+        sticks.up_down(),
+        sticks.rotate_pos_neg(),
+        sticks.forward_backward(),
+        sticks.left_right()
+    );
+    if let Err(err) = written {
+        error!("input recording write failed: {}", err);
+    }
+}
+
+/// Overrides the sticks with the recorded ones, one per step. Once the
+/// recording runs out the sticks are left alone.
+pub fn replay_inputs(mut replay: ResMut<InputReplay>, mut sticks: ResMut<Sticks>) {
+    let Some(&state) = replay.inputs.get(replay.next) else {
+        return;
+    };
+    sticks.0 = state;
+    replay.next += 1;
+    if replay.next == replay.inputs.len() {
+        info!("replay finished after {} steps", replay.next);
+    }
+}
+
+pub fn flush_recording_on_exit(
+    mut exits: EventReader<AppExit>,
+    mut recorder: ResMut<InputRecorder>,
+) {
+    if exits.read().next().is_some() {
+        if let Err(err) = recorder.writer.flush() {
+            error!("input recording flush failed: {}", err);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional CSV log of every fixed step, enabled with `--log=<path>`.
+//!
+//! One row per drone and step. Columns, in order: simulation time in seconds;
+//! the drone's index; the throttle, yaw, pitch and
+//! roll channels; the front left, front right, rear left and rear right motor
+//! commands; world position and velocity (x, y up, z); the controller's
+//! estimated roll and pitch in radians; the rate loop's P, I, D and
+//! feed-forward terms, each for roll, pitch and yaw.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::args::Args;
+use crate::{Drone, DroneController, DroneMotors, Sticks};
+
+const HEADER: &str = "time,drone,throttle,yaw,pitch,roll,\
+motor_fl,motor_fr,motor_rl,motor_rr,\
+pos_x,pos_y,pos_z,vel_x,vel_y,vel_z,\
+est_roll,est_pitch,\
+p_roll,p_pitch,p_yaw,i_roll,i_pitch,i_yaw,\
+d_roll,d_pitch,d_yaw,ff_roll,ff_pitch,ff_yaw";
+
+/// Simulated seconds between flushes.
+const FLUSH_INTERVAL: f64 = 1.0;
+
+#[derive(Resource)]
+pub struct TelemetryLog {
+    writer: BufWriter<File>,
+    last_flush: f64,
+}
+impl TelemetryLog {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Self {
+            writer,
+            last_flush: 0.0,
+        })
+    }
+
+    /// Opens the file named by `--log=<path>`, if given.
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let path = args.log.as_ref()?;
+        match Self::create(path) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                error!("can't open telemetry log {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            error!("telemetry log flush failed: {}", err);
+        }
+    }
+}
+
+pub fn log_telemetry(
+    time: Res<Time>,
+    sticks: Res<Sticks>,
+    mut log: ResMut<TelemetryLog>,
+    drones: Query<(
+        &Drone,
+        &DroneController,
+        &DroneMotors,
+        &Transform,
+        &Velocity,
+    )>,
+) {
+    let mut drones: Vec<_> = drones.iter().collect();
+    drones.sort_by_key(|(Drone(index), ..)| *index);
+    let sticks = sticks.0;
+    for (Drone(index), controller, motors, transform, velocity) in drones {
+        let [fl, fr, rl, rr] = motors.speeds();
+        let position = transform.translation;
+        let linvel = velocity.linvel;
+        let (roll, pitch) = controller.c.attitude();
+        let terms = controller.c.last_pid_terms();
+        let written = writeln!(
+            log.writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            time.elapsed_seconds_f64(),
+            index,
+            sticks.up_down(),
+            sticks.rotate_pos_neg(),
+            sticks.forward_backward(),
+            sticks.left_right(),
+            fl,
+            fr,
+            rl,
+            rr,
+            position.x,
+            position.y,
+            position.z,
+            linvel.x,
+            linvel.y,
+            linvel.z,
+            roll,
+            pitch,
+            terms.p.x,
+            terms.p.z,
+            terms.p.y,
+            terms.i.x,
+            terms.i.z,
+            terms.i.y,
+            terms.d.x,
+            terms.d.z,
+            terms.d.y,
+            terms.ff.x,
+            terms.ff.z,
+            terms.ff.y,
+        );
+        if let Err(err) = written {
+            error!("telemetry log write failed: {}", err);
+        }
+    }
+    let now = time.elapsed_seconds_f64();
+    if now - log.last_flush >= FLUSH_INTERVAL {
+        log.flush();
+        log.last_flush = now;
+    }
+}
+
+pub fn flush_telemetry_on_exit(mut exits: EventReader<AppExit>, mut log: ResMut<TelemetryLog>) {
+    if exits.read().next().is_some() {
+        log.flush();
+    }
+}
@@ -0,0 +1,74 @@
+//! Each drone's recent path, drawn behind it and exported to CSV with T.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use bevy::prelude::*;
+
+use crate::Drone;
+
+/// Points kept per drone, one per fixed step. Older ones are dropped.
+const MAX_POINTS: usize = 8000;
+const EXPORT_PATH: &str = "trajectory.csv";
+const TRAIL_COLOR: Srgba = Srgba::rgb(1.0, 0.4, 0.1);
+
+/// World positions of a drone, oldest first, with the simulated time each
+/// was recorded at.
+#[derive(Component, Default)]
+pub struct Trail(VecDeque<(f32, Vec3)>);
+impl Trail {
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+pub fn record_trails(time: Res<Time>, mut drones: Query<(&Transform, &mut Trail)>) {
+    for (transform, mut trail) in &mut drones {
+        if trail.0.len() == MAX_POINTS {
+            trail.0.pop_front();
+        }
+        trail
+            .0
+            .push_back((time.elapsed_seconds(), transform.translation));
+    }
+}
+
+/// The oldest points fade out.
+pub fn draw_trails(mut gizmos: Gizmos, drones: Query<&Trail>) {
+    for Trail(points) in &drones {
+        let len = points.len() as f32;
+        gizmos.linestrip_gradient(
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, &(_, point))| (point, TRAIL_COLOR.with_alpha(i as f32 / len))),
+        );
+    }
+}
+
+/// T writes every drone's trail to `trajectory.csv` in the working directory.
+pub fn export_trails(keys: Res<ButtonInput<KeyCode>>, drones: Query<(&Drone, &Trail)>) {
+    if !keys.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    match write_trails(&drones) {
+        Ok(()) => info!("trajectory written to {}", EXPORT_PATH),
+        Err(err) => error!("can't write trajectory to {}: {}", EXPORT_PATH, err),
+    }
+}
+
+fn write_trails(drones: &Query<(&Drone, &Trail)>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(EXPORT_PATH)?);
+    writeln!(writer, "drone,time,pos_x,pos_y,pos_z")?;
+    for (Drone(index), Trail(points)) in drones {
+        for (time, point) in points {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                index, time, point.x, point.y, point.z
+            )?;
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,179 @@
+use std::path::Path;
+use std::time::Duration;
+
+use bevy::asset::io::{AssetSource, Reader};
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use controller::{Controller, PidGains, RateProfile};
+use serde::Deserialize;
+
+use crate::args::Args;
+use crate::DroneController;
+
+/// Used when no `--tuning` file is given.
+const DEFAULT_TUNING: &str = include_str!("../assets/tuning.ron");
+/// Asset source rooted at the tuning file's directory, so the file can live
+/// anywhere and still be watched.
+const TUNING_SOURCE: &str = "tuning";
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Axes {
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+}
+
+/// Controller parameters read from a RON file.
+#[derive(Asset, Resource, TypePath, Clone, Debug, Deserialize)]
+pub struct Tuning {
+    roll: PidGains,
+    pitch: PidGains,
+    yaw: PidGains,
+    gyro_lpf_hz: f32,
+    /// Degrees.
+    max_angle: f32,
+    expo: Axes,
+}
+impl Tuning {
+    /// The expo and gyro filter go into rate profile 0, whichever profile
+    /// is flying.
+    pub fn apply(&self, controller: &mut Controller) {
+        controller.set_gains(self.roll, self.pitch, self.yaw);
+        controller.set_max_angle(self.max_angle.to_radians());
+        let profile = RateProfile {
+            name: "tuning",
+            roll_expo: self.expo.roll,
+            pitch_expo: self.expo.pitch,
+            yaw_expo: self.expo.yaw,
+            gyro_lpf_hz: self.gyro_lpf_hz,
+            ..RateProfile::standard()
+        };
+        if let Err(err) = controller.set_rate_profile(0, profile) {
+            error!("tuning not applied: {}", err);
+        }
+    }
+}
+impl Default for Tuning {
+    fn default() -> Self {
+        ron::from_str(DEFAULT_TUNING).expect("built in tuning is valid")
+    }
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+impl std::fmt::Display for TuningError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TuningError::Io(err) => write!(f, "can't read tuning: {}", err),
+            TuningError::Ron(err) => write!(f, "bad tuning: {}", err),
+        }
+    }
+}
+impl std::error::Error for TuningError {}
+
+#[derive(Default)]
+struct TuningLoader;
+impl AssetLoader for TuningLoader {
+    type Asset = Tuning;
+    type Settings = ();
+    type Error = TuningError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Tuning, TuningError> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(TuningError::Io)?;
+        ron::de::from_bytes(&bytes).map_err(TuningError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// Set by `--tuning=<path>`. The file is loaded through the asset server and
+/// reapplied to every controller whenever it changes on disk.
+#[derive(Resource)]
+pub struct TuningFile {
+    dir: String,
+    name: String,
+    handle: Handle<Tuning>,
+}
+impl TuningFile {
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let path = args.tuning.as_deref()?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Some(Self {
+            dir: dir.to_string_lossy().into_owned(),
+            name: path.file_name()?.to_string_lossy().into_owned(),
+            handle: Handle::default(),
+        })
+    }
+
+    /// Has to run before the asset plugin is added.
+    pub fn register_source(&self, app: &mut App) {
+        app.register_asset_source(
+            TUNING_SOURCE,
+            AssetSource::build()
+                .with_reader(AssetSource::get_default_reader(self.dir.clone()))
+                .with_watcher(AssetSource::get_default_watcher(
+                    self.dir.clone(),
+                    Duration::from_millis(300),
+                )),
+        );
+    }
+
+    /// Has to run after the asset plugin is added.
+    pub fn watch(self, app: &mut App) {
+        app.init_asset::<Tuning>()
+            .init_asset_loader::<TuningLoader>()
+            .insert_resource(self)
+            .add_systems(Startup, load_tuning)
+            .add_systems(Update, apply_tuning);
+    }
+}
+
+fn load_tuning(asset_server: Res<AssetServer>, mut file: ResMut<TuningFile>) {
+    let path = format!("{}://{}", TUNING_SOURCE, file.name);
+    file.handle = asset_server.load(path);
+}
+
+/// Applies the tuning file to every controller once it loads and again each
+/// time it is edited.
+fn apply_tuning(
+    mut events: EventReader<AssetEvent<Tuning>>,
+    file: Res<TuningFile>,
+    assets: Res<Assets<Tuning>>,
+    mut tuning: ResMut<Tuning>,
+    mut drones: Query<&mut DroneController>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        if *id != file.handle.id() {
+            continue;
+        }
+        let Some(loaded) = assets.get(*id) else {
+            continue;
+        };
+        *tuning = loaded.clone();
+        for mut controller in &mut drones {
+            tuning.apply(&mut controller.c);
+        }
+        info!("applied tuning from {}/{}", file.dir, file.name);
+    }
+}
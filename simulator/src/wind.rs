@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::args::Args;
+
+/// Steady wind plus Ornstein-Uhlenbeck gusts, in world frame m/s.
+#[derive(Resource)]
+pub struct Wind {
+    pub enabled: bool,
+    pub base: Vec3,
+    /// Standard deviation of the gust speed along each axis.
+    pub gust_intensity: f32,
+    /// How long, in seconds, a gust takes to die down.
+    pub gust_time_constant: f32,
+    pub seed: u64,
+    gust: Vec3,
+    rng: StdRng,
+}
+impl Wind {
+    pub fn new(base: Vec3, gust_intensity: f32, seed: u64) -> Self {
+        Self {
+            enabled: true,
+            base,
+            gust_intensity,
+            gust_time_constant: 2.0,
+            seed,
+            gust: Vec3::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// From `--wind=x,y,z`, `--gust=intensity` and `--wind-seed=n`. Without
+    /// any of them the air is calm.
+    pub fn from_args(args: &Args) -> Self {
+        Self::new(args.wind, args.gust, args.wind_seed)
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        if self.enabled {
+            self.base + self.gust
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    fn step(&mut self, dt: f32) {
+        if self.gust_time_constant <= 0.0 {
+            self.gust = Vec3::ZERO;
+            return;
+        }
+        let noise = Vec3::from_array(std::array::from_fn(|_| {
+            StandardNormal.sample(&mut self.rng)
+        }));
+        let diffusion = self.gust_intensity * (2.0 * dt / self.gust_time_constant).sqrt();
+        self.gust += -self.gust * dt / self.gust_time_constant + diffusion * noise;
+    }
+}
+
+pub fn toggle_wind(keys: Res<ButtonInput<KeyCode>>, mut wind: ResMut<Wind>) {
+    if keys.just_pressed(KeyCode::KeyV) {
+        wind.enabled = !wind.enabled;
+    }
+}
+
+pub fn update_wind(time: Res<Time>, mut wind: ResMut<Wind>) {
+    wind.step(time.delta_seconds());
+}